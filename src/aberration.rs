@@ -0,0 +1,234 @@
+pub mod aberration {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    pub use crate::config::*;
+
+    use crate::convolve::*;
+    use crate::edge::*;
+    use crate::grayscale::*;
+    use crate::rustyimg::*;
+    use crate::log;
+
+    // the supported chromatic aberration corrections
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Chromatic {
+        // the red and blue scales are estimated from the image
+        Auto,
+        // the red and blue scales relative to green
+        Scales(f32, f32),
+    }
+
+    // parses auto or RED,BLUE scale factors relative to green, e.g. 1.0008,0.9994
+    pub fn parse_chromatic(spec: &str) -> Option<Chromatic> {
+        if spec.trim().eq_ignore_ascii_case("auto") {
+            return Some(Chromatic::Auto);
+        }
+        let values: Vec<f32> = spec
+            .split(',')
+            .map(|v| v.trim().parse::<f32>())
+            .collect::<Result<_, _>>()
+            .ok()?;
+        if values.len() != 2 || values.iter().any(|&v| !(0.9..=1.1).contains(&v)) {
+            return None;
+        }
+        Some(Chromatic::Scales(values[0], values[1]))
+    }
+
+    // samples a channel from get_channel (column by column) at a fractional
+    // position with bilinear interpolation
+    fn sample_channel(channel: &[u8], width: usize, height: usize, x: f32, y: f32) -> f32 {
+        let x = x.clamp(0.0, (width - 1) as f32);
+        let y = y.clamp(0.0, (height - 1) as f32);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let at = |px: usize, py: usize| channel[px * height + py] as f32;
+        let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+        let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    // scales a channel radially around the image center
+    pub fn scale_channel(channel: &[u8], width: usize, height: usize, scale: f32) -> Vec<u8> {
+        let (cx, cy) = ((width as f32 - 1.0) / 2.0, (height as f32 - 1.0) / 2.0);
+        let mut out = Vec::with_capacity(channel.len());
+        for x in 0..width {
+            for y in 0..height {
+                let sx = cx + (x as f32 - cx) * scale;
+                let sy = cy + (y as f32 - cy) * scale;
+                out.push(round_u8(sample_channel(channel, width, height, sx, sy)));
+            }
+        }
+        out
+    }
+
+    // estimates the scale of a channel against green from the strongest
+    // green edges away from the center, maximizing their correlation
+    pub fn estimate_channel_scale(channel: &[u8], green: &[u8], width: usize, height: usize) -> f32 {
+        // gradients of green in the usual row by row order
+        let mut plane = vec![0f32; width * height];
+        for x in 0..width {
+            for y in 0..height {
+                plane[y * width + x] = green[x * height + y] as f32;
+            }
+        }
+        let (gx, gy) = gradients(&plane, width, height, false);
+        let (cx, cy) = ((width as f32 - 1.0) / 2.0, (height as f32 - 1.0) / 2.0);
+        let half_diagonal = (cx * cx + cy * cy).sqrt().max(1.0);
+        let mut points: Vec<(f32, usize, usize)> = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let (dx, dy) = (x as f32 - cx, y as f32 - cy);
+                if (dx * dx + dy * dy).sqrt() / half_diagonal < 0.3 {
+                    continue;
+                }
+                let i = y * width + x;
+                let magnitude = (gx[i] * gx[i] + gy[i] * gy[i]).sqrt();
+                if magnitude > 20.0 {
+                    points.push((magnitude, x, y));
+                }
+            }
+        }
+        if points.len() < 100 {
+            return 1.0;
+        }
+        points.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
+        points.truncate(20000);
+
+        let reference: Vec<f32> = points.iter().map(|&(_, x, y)| green[x * height + y] as f32).collect();
+        let correlation = |scale: f32| -> f64 {
+            let values: Vec<f32> = points
+                .iter()
+                .map(|&(_, x, y)| {
+                    let sx = cx + (x as f32 - cx) * scale;
+                    let sy = cy + (y as f32 - cy) * scale;
+                    sample_channel(channel, width, height, sx, sy)
+                })
+                .collect();
+            let n = values.len() as f64;
+            let mean_a = reference.iter().map(|&v| v as f64).sum::<f64>() / n;
+            let mean_b = values.iter().map(|&v| v as f64).sum::<f64>() / n;
+            let (mut ab, mut aa, mut bb) = (0.0, 0.0, 0.0);
+            for (&a, &b) in reference.iter().zip(values.iter()) {
+                let (a, b) = (a as f64 - mean_a, b as f64 - mean_b);
+                ab += a * b;
+                aa += a * a;
+                bb += b * b;
+            }
+            ab / (aa * bb).sqrt().max(1e-9)
+        };
+
+        // up to half a percent, coarse and then fine steps
+        let search = |from: f32, step: f32, count: i32| -> f32 {
+            (0..=count)
+                .map(|i| from + i as f32 * step)
+                .map(|s| (s, correlation(s)))
+                .fold((1.0, f64::MIN), |best, (s, c)| if c > best.1 { (s, c) } else { best })
+                .0
+        };
+        let coarse = search(0.995, 0.0005, 20);
+        search(coarse - 0.0005, 0.00005, 20)
+    }
+
+    // scales the red and blue channels against green, estimating the scales
+    // for Chromatic::Auto
+    pub fn correct_chromatic(img: &Image<u8>, correction: Chromatic) -> Image<u8> {
+        let (width, height) = img.info().wh();
+        let (w, h) = (width as usize, height as usize);
+        let red = get_channel(img, 0);
+        let green = get_channel(img, 1);
+        let blue = get_channel(img, 2);
+        let (red_scale, blue_scale) = match correction {
+            Chromatic::Scales(red, blue) => (red, blue),
+            Chromatic::Auto => {
+                let s = (
+                    estimate_channel_scale(&red, &green, w, h),
+                    estimate_channel_scale(&blue, &green, w, h),
+                );
+                log!(" > Chromatic aberration scales red {:.5} blue {:.5}", s.0, s.1);
+                s
+            }
+        };
+
+        let mut img2 = img.clone();
+        if red_scale != 1.0 {
+            set_channel(&mut img2, 0, &scale_channel(&red, w, h, red_scale));
+        }
+        if blue_scale != 1.0 {
+            set_channel(&mut img2, 2, &scale_channel(&blue, w, h, blue_scale));
+        }
+        img2
+    }
+
+    // desaturates purple fringes next to high contrast edges; amount is
+    // 0 to 100 percent
+    pub fn defringe_image(img: &Image<u8>, amount: f32) -> Image<u8> {
+        let (width, height, channels, alpha) = img.info().whca();
+        let (w, h) = (width as usize, height as usize);
+        let plane = luma_plane(img);
+        let (gx, gy) = gradients(&plane, w, h, false);
+        let magnitude: Vec<f32> = gx.iter().zip(gy.iter()).map(|(x, y)| (x * x + y * y).sqrt()).collect();
+        // spread the edges so the pixels next to them count too
+        let kernel = gaussian_kernel(2.0);
+        let near = convolve_plane_separable(&magnitude, w, h, &kernel, &kernel, BorderMode::Reflect);
+
+        let mut data = img.data().to_vec();
+        for (i, pixel) in data.chunks_mut(channels as usize).enumerate() {
+            let edge = (near[i] / 40.0).min(1.0);
+            if edge <= 0.0 {
+                continue;
+            }
+            let (r, g, b) = (pixel[0] as f32, pixel[1] as f32, pixel[2] as f32);
+            let max = r.max(g).max(b);
+            let min = r.min(g).min(b);
+            if max - min < 8.0 {
+                continue;
+            }
+            let hue = if max == r {
+                60.0 * ((g - b) / (max - min)).rem_euclid(6.0)
+            } else if max == g {
+                60.0 * ((b - r) / (max - min) + 2.0)
+            } else {
+                60.0 * ((r - g) / (max - min) + 4.0)
+            };
+            // purple and magenta, fading out over 20 degrees on each side
+            let purple = ((hue - 250.0).min(330.0 - hue) / 20.0).clamp(0.0, 1.0);
+            let t = edge * purple * amount / 100.0;
+            if t > 0.0 {
+                let y = plane[i];
+                pixel[0] = round_u8(r + (y - r) * t);
+                pixel[1] = round_u8(g + (y - g) * t);
+                pixel[2] = round_u8(b + (y - b) * t);
+            }
+        }
+        Image::from_vec(width, height, channels, alpha, data)
+    }
+
+    // runs the chromatic aberration and defringe options on color images
+    pub fn apply_aberration(img: Image<u8>, opts: &ConfigOptions) -> Image<u8> {
+        if opts.chromatic.is_empty() && opts.defringe.is_empty() {
+            return img;
+        }
+        if img.info().channels_non_alpha() < 3 {
+            log!(" > Skipping color fringe correction of a grayscale image");
+            return img;
+        }
+        let mut img = img;
+
+        if !opts.chromatic.is_empty() {
+            match parse_chromatic(&opts.chromatic) {
+                Some(correction) => img = correct_chromatic(&img, correction),
+                None => println!("Invalid chromatic aberration correction {}", opts.chromatic),
+            }
+        }
+        if !opts.defringe.is_empty() {
+            match opts.defringe.trim().parse::<f32>() {
+                Ok(amount) if (0.0..=100.0).contains(&amount) => img = defringe_image(&img, amount),
+                _ => println!("Invalid defringe amount {}", opts.defringe),
+            }
+        }
+        img
+    }
+}
+
+pub use aberration::*;
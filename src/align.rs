@@ -0,0 +1,256 @@
+pub mod align {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    pub use crate::config::*;
+
+    use crate::convolve::*;
+    use crate::crop::*;
+    use crate::edge::*;
+    use crate::warp::*;
+
+    // the side of the planes compared by phase correlation
+    const CORRELATION_SIZE: usize = 512;
+
+    // an in place radix 2 FFT of complex values, the length a power of two;
+    // the inverse is scaled by 1/n
+    pub fn fft(data: &mut [(f64, f64)], inverse: bool) {
+        let n = data.len();
+        let mut j = 0;
+        for i in 1..n {
+            let mut bit = n >> 1;
+            while j & bit != 0 {
+                j ^= bit;
+                bit >>= 1;
+            }
+            j |= bit;
+            if i < j {
+                data.swap(i, j);
+            }
+        }
+        let mut len = 2;
+        while len <= n {
+            let angle = 2.0 * std::f64::consts::PI / len as f64 * if inverse { 1.0 } else { -1.0 };
+            let (ws, wc) = angle.sin_cos();
+            for start in (0..n).step_by(len) {
+                let (mut re, mut im) = (1.0, 0.0);
+                for k in 0..len / 2 {
+                    let (ar, ai) = data[start + k];
+                    let (br, bi) = data[start + k + len / 2];
+                    let (tr, ti) = (br * re - bi * im, br * im + bi * re);
+                    data[start + k] = (ar + tr, ai + ti);
+                    data[start + k + len / 2] = (ar - tr, ai - ti);
+                    let next = re * wc - im * ws;
+                    im = re * ws + im * wc;
+                    re = next;
+                }
+            }
+            len <<= 1;
+        }
+        if inverse {
+            for value in data.iter_mut() {
+                *value = (value.0 / n as f64, value.1 / n as f64);
+            }
+        }
+    }
+
+    // a 2D FFT of a size x size square stored row by row
+    pub fn fft2d(data: &mut [(f64, f64)], size: usize, inverse: bool) {
+        for row in data.chunks_mut(size) {
+            fft(row, inverse);
+        }
+        let mut column = vec![(0.0, 0.0); size];
+        for x in 0..size {
+            for y in 0..size {
+                column[y] = data[y * size + x];
+            }
+            fft(&mut column, inverse);
+            for y in 0..size {
+                data[y * size + x] = column[y];
+            }
+        }
+    }
+
+    // a windowed, zero padded square spectrum of a plane, at most size pixels
+    fn spectrum(plane: &[f32], width: usize, height: usize, size: usize) -> Vec<(f64, f64)> {
+        let mean = plane.iter().map(|&v| v as f64).sum::<f64>() / plane.len().max(1) as f64;
+        let hann = |i: usize, n: usize| 0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / (n.max(2) - 1) as f64).cos();
+        let mut data = vec![(0.0, 0.0); size * size];
+        for y in 0..height.min(size) {
+            for x in 0..width.min(size) {
+                let value = (plane[y * width + x] as f64 - mean) * hann(x, width) * hann(y, height);
+                data[y * size + x] = (value, 0.0);
+            }
+        }
+        fft2d(&mut data, size, false);
+        data
+    }
+
+    // the peak of the phase correlation of a plane against the spectrum of
+    // the reference within `radius` pixels, with sub pixel refinement
+    fn correlation_peak(reference: &[(f64, f64)], plane: &[f32], width: usize, height: usize, size: usize, radius: usize) -> (f32, f32) {
+        let mut cross = spectrum(plane, width, height, size);
+        for (c, r) in cross.iter_mut().zip(reference.iter()) {
+            // the image spectrum times the conjugate of the reference, only
+            // partly whitened so the strong structures keep more weight
+            let (re, im) = (c.0 * r.0 + c.1 * r.1, c.1 * r.0 - c.0 * r.1);
+            let magnitude = (re * re + im * im).sqrt().max(1e-12).sqrt();
+            *c = (re / magnitude, im / magnitude);
+        }
+        fft2d(&mut cross, size, true);
+
+        let wrap = |p: usize| if p > size / 2 { p as f64 - size as f64 } else { p as f64 };
+        let (peak, _) = cross
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| wrap(i % size).abs() <= radius as f64 && wrap(i / size).abs() <= radius as f64)
+            .fold((0, f64::MIN), |best, (i, v)| if v.0 > best.1 { (i, v.0) } else { best });
+        let (px, py) = (peak % size, peak / size);
+        let at = |x: usize, y: usize| cross[(y % size) * size + x % size].0;
+        // fit a parabola through the peak and its neighbours
+        let offset = |before: f64, center: f64, after: f64| {
+            let denominator = before - 2.0 * center + after;
+            if denominator.abs() < 1e-12 {
+                0.0
+            } else {
+                (0.5 * (before - after) / denominator).clamp(-0.5, 0.5)
+            }
+        };
+        let fx = offset(at(px + size - 1, py), at(px, py), at(px + 1, py));
+        let fy = offset(at(px, py + size - 1), at(px, py), at(px, py + 1));
+        ((wrap(px) + fx) as f32, (wrap(py) + fy) as f32)
+    }
+
+    // a plane sampled at (x + dx, y + dy) with bilinear interpolation,
+    // repeating the edge pixels
+    fn shift_plane(plane: &[f32], width: usize, height: usize, dx: f32, dy: f32) -> Vec<f32> {
+        let at = |x: usize, y: usize| plane[y * width + x];
+        let mut out = Vec::with_capacity(plane.len());
+        for y in 0..height {
+            for x in 0..width {
+                let sx = (x as f32 + dx).clamp(0.0, (width - 1) as f32);
+                let sy = (y as f32 + dy).clamp(0.0, (height - 1) as f32);
+                let (x0, y0) = (sx.floor() as usize, sy.floor() as usize);
+                let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+                let (fx, fy) = (sx - x0 as f32, sy - y0 as f32);
+                let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+                let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+                out.push(top * (1.0 - fy) + bottom * fy);
+            }
+        }
+        out
+    }
+
+    // the translation (dx, dy) moving the reference plane onto the other
+    // plane, found as the peak of their phase correlation within `radius`
+    // pixels; both planes have the same size. The window pulls the peak
+    // towards no movement, so the plane is moved back by the estimate and
+    // the small remainder is measured again
+    pub fn phase_correlation(reference: &[f32], plane: &[f32], width: usize, height: usize, radius: usize) -> (f32, f32) {
+        let size = width.max(height).next_power_of_two();
+        let a = spectrum(reference, width, height, size);
+        let (mut dx, mut dy) = correlation_peak(&a, plane, width, height, size, radius);
+        for _ in 0..3 {
+            let moved = shift_plane(plane, width, height, dx, dy);
+            let (rx, ry) = correlation_peak(&a, &moved, width, height, size, 1);
+            dx += rx;
+            dy += ry;
+            if rx.abs() < 0.01 && ry.abs() < 0.01 {
+                break;
+            }
+        }
+        (dx, dy)
+    }
+
+    // the translation of an image against a reference of the same size, as
+    // the position in the image showing the reference's top left corner;
+    // measured on a reduced copy and refined on the full size center
+    pub fn estimate_shift(reference: &Image<u8>, img: &Image<u8>) -> (f32, f32) {
+        let (width, height) = reference.info().wh();
+        let factor = (width.max(height) as f32 / CORRELATION_SIZE as f32).max(1.0);
+        let (sw, sh) = (((width as f32 / factor).round() as u32).max(1), ((height as f32 / factor).round() as u32).max(1));
+        // a little blur evens out differences in focus and noise
+        let kernel = gaussian_kernel(1.0);
+        let (uw, uh) = (sw as usize, sh as usize);
+        let reduce = |img: &Image<u8>| {
+            let plane = luma_plane(&resize_image(img, sw, sh));
+            convolve_plane_separable(&plane, uw, uh, &kernel, &kernel, BorderMode::Reflect)
+        };
+        let (dx, dy) = phase_correlation(&reduce(reference), &reduce(img), uw, uh, CORRELATION_SIZE);
+        let (dx, dy) = (dx * width as f32 / sw as f32, dy * height as f32 / sh as f32);
+        if factor <= 1.0 {
+            return (dx, dy);
+        }
+
+        // compare the centers at full size, the image one already moved
+        let side = (CORRELATION_SIZE as u32).min(width).min(height);
+        let (x0, y0) = ((width - side) / 2, (height - side) / 2);
+        let max_x = (width - side) as f32;
+        let max_y = (height - side) as f32;
+        let (ix, iy) = ((x0 as f32 + dx.round()).clamp(0.0, max_x), (y0 as f32 + dy.round()).clamp(0.0, max_y));
+        let center_reference = luma_plane(&crop_rect(reference, x0, y0, side, side));
+        let center = luma_plane(&crop_rect(img, ix as u32, iy as u32, side, side));
+        // only a small correction is left to find
+        let radius = factor.ceil() as usize * 2;
+        let (rx, ry) = phase_correlation(&center_reference, &center, side as usize, side as usize, radius);
+        (ix - x0 as f32 + rx, iy - y0 as f32 + ry)
+    }
+
+    // moves an image so the pixel at (dx, dy) ends up at the top left,
+    // repeating the edge pixels where nothing is left
+    pub fn shift_image(img: &Image<u8>, dx: f32, dy: f32) -> Image<u8> {
+        let (width, height) = img.info().wh();
+        let (max_x, max_y) = ((width - 1) as f32, (height - 1) as f32);
+        warp_image(img, width, height, &[], |x, y| {
+            Some(((x + dx).clamp(0.0, max_x), (y + dy).clamp(0.0, max_y)))
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        // soft random blobs, sampled at (x - dx, y - dy)
+        fn blobs(width: usize, height: usize, dx: f32, dy: f32) -> Vec<f32> {
+            let mut state = 7u32;
+            let mut next = || {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                (state >> 8) as f32 / (1 << 24) as f32
+            };
+            let centers: Vec<(f32, f32, f32)> = (0..40).map(|_| (next() * width as f32, next() * height as f32, next() * 200.0)).collect();
+            let mut plane = Vec::with_capacity(width * height);
+            for y in 0..height {
+                for x in 0..width {
+                    let (px, py) = (x as f32 - dx, y as f32 - dy);
+                    plane.push(centers.iter().map(|(cx, cy, v)| v * (-((px - cx).powi(2) + (py - cy).powi(2)) / 18.0).exp()).sum());
+                }
+            }
+            plane
+        }
+
+        #[test]
+        fn phase_correlation_finds_shifts() {
+            let (width, height) = (96, 80);
+            let reference = blobs(width, height, 0.0, 0.0);
+            for (dx, dy) in [(0.0, 0.0), (3.0, -5.0), (-7.0, 2.0), (2.5, -1.5), (-4.25, 3.75)] {
+                let plane = blobs(width, height, dx, dy);
+                let (fx, fy) = phase_correlation(&reference, &plane, width, height, 16);
+                assert!((fx - dx).abs() <= 0.05 && (fy - dy).abs() <= 0.05, "{} {}: {} {}", dx, dy, fx, fy);
+            }
+        }
+
+        #[test]
+        fn estimate_shift_of_images() {
+            let image = |dx, dy| {
+                let plane = blobs(160, 120, dx, dy);
+                Image::from_vec(160, 120, 1, false, plane.iter().map(|v| v.round().min(255.0) as u8).collect())
+            };
+            let reference = image(0.0, 0.0);
+            for (dx, dy) in [(0.0, 0.0), (6.0, -4.0), (-3.5, 2.0)] {
+                let (fx, fy) = estimate_shift(&reference, &image(dx, dy));
+                assert!((fx - dx).abs() <= 0.1 && (fy - dy).abs() <= 0.1, "{} {}: {} {}", dx, dy, fx, fy);
+            }
+        }
+    }
+}
+
+pub use align::*;
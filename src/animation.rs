@@ -0,0 +1,586 @@
+pub mod animation {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    pub use crate::config::*;
+
+    use std::borrow::Cow;
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+    use std::fs::File;
+    use std::io::{BufWriter, Write};
+
+    use crate::color::*;
+    use crate::warp::*;
+
+    // a frame as rows of RGB colors
+    pub type Frame = Vec<[u8; 3]>;
+
+    // the longest side of the frames unless set
+    const FRAME_SIZE: u32 = 640;
+
+    // the most pixels the palette is built from
+    const PALETTE_SAMPLES: usize = 250_000;
+
+    // the frame delays in milliseconds; the last one is used for the
+    // remaining frames
+    pub fn parse_delays(spec: &str) -> Option<Vec<u32>> {
+        let delays: Option<Vec<u32>> = split_list(spec).iter().map(|d| d.parse::<u32>().ok()).collect();
+        delays.filter(|d| !d.is_empty())
+    }
+
+    pub fn frame_delay(delays: &[u32], frame: usize) -> u32 {
+        delays[frame.min(delays.len() - 1)]
+    }
+
+    // the frame size as WxH, used as given, or the longest side keeping the
+    // aspect ratio of the first image and never larger than it
+    pub fn parse_frame_size(spec: &str, width: u32, height: u32) -> Option<(u32, u32)> {
+        let spec = spec.trim();
+        if let Some((w, h)) = spec.split_once('x') {
+            let (w, h) = (w.trim().parse::<u32>().ok()?, h.trim().parse::<u32>().ok()?);
+            return if w > 0 && h > 0 { Some((w, h)) } else { None };
+        }
+        let side = if spec.is_empty() { FRAME_SIZE } else { spec.parse::<u32>().ok()? };
+        if side == 0 {
+            return None;
+        }
+        let scale = (side as f32 / width.max(height) as f32).min(1.0);
+        Some((((width as f32 * scale).round() as u32).max(1), ((height as f32 * scale).round() as u32).max(1)))
+    }
+
+    // the image scaled to fit the frame, centered on the background color
+    pub fn fit_frame(img: &Image<u8>, width: u32, height: u32, background: [u8; 3]) -> Frame {
+        let (iw, ih) = img.info().wh();
+        let scale = (width as f32 / iw as f32).min(height as f32 / ih as f32);
+        let sw = ((iw as f32 * scale).round() as u32).clamp(1, width);
+        let sh = ((ih as f32 * scale).round() as u32).clamp(1, height);
+        let scaled = resize_image(img, sw, sh);
+        let (x0, y0) = ((width - sw) / 2, (height - sh) / 2);
+        let colors = scaled.info().channels_non_alpha();
+
+        let mut frame = vec![background; (width * height) as usize];
+        for y in 0..sh {
+            for x in 0..sw {
+                let p = scaled.get_pixel(x, y);
+                let rgb = if colors >= 3 { [p[0], p[1], p[2]] } else { [p[0], p[0], p[0]] };
+                frame[((y0 + y) * width + x0 + x) as usize] = rgb;
+            }
+        }
+        frame
+    }
+
+    // a palette shared by all frames with median cut: the box of colors with
+    // the most pixels times its widest channel range is split at the median
+    // until there are enough boxes, each giving its average color
+    pub fn median_cut_palette(frames: &[Frame], colors: usize) -> Vec<[u8; 3]> {
+        let total: usize = frames.iter().map(|f| f.len()).sum();
+        let step = total.div_ceil(PALETTE_SAMPLES).max(1);
+        let mut samples: Vec<[u8; 3]> = frames.iter().flat_map(|f| f.iter().step_by(step).copied()).collect();
+        if samples.is_empty() {
+            return vec![[0, 0, 0]];
+        }
+
+        // each box with its widest channel and that channel's range
+        let widest = |pixels: &[[u8; 3]]| -> (usize, u32) {
+            (0..3)
+                .map(|c| {
+                    let (min, max) = pixels.iter().fold((255u8, 0u8), |(lo, hi), p| (lo.min(p[c]), hi.max(p[c])));
+                    (c, max.saturating_sub(min) as u32)
+                })
+                .max_by_key(|&(_, range)| range)
+                .unwrap()
+        };
+        let mut boxes = vec![(0, samples.len(), widest(&samples))];
+        while boxes.len() < colors {
+            let candidate = boxes
+                .iter()
+                .enumerate()
+                .filter(|&(_, &(_, _, (_, range)))| range > 0)
+                .max_by_key(|&(_, &(start, end, (_, range)))| range as u64 * (end - start) as u64);
+            let (i, &(start, end, (channel, _))) = match candidate {
+                Some(c) => c,
+                None => break,
+            };
+            samples[start..end].sort_unstable_by_key(|p| p[channel]);
+            // the median moved to the closest change of value, so a color
+            // never ends up in both halves
+            let median = samples[start + (end - start) / 2][channel];
+            let below = start + samples[start..end].partition_point(|p| p[channel] < median);
+            let above = start + samples[start..end].partition_point(|p| p[channel] <= median);
+            let half = start + (end - start) / 2;
+            let middle = if below > start && (above == end || half - below <= above - half) { below } else { above };
+            boxes[i] = (start, middle, widest(&samples[start..middle]));
+            boxes.push((middle, end, widest(&samples[middle..end])));
+        }
+
+        boxes
+            .iter()
+            .map(|&(start, end, _)| {
+                let mut sum = [0u64; 3];
+                for p in samples[start..end].iter() {
+                    for c in 0..3 {
+                        sum[c] += p[c] as u64;
+                    }
+                }
+                let count = (end - start) as u64;
+                [(sum[0] / count) as u8, (sum[1] / count) as u8, (sum[2] / count) as u8]
+            })
+            .collect()
+    }
+
+    // finds the closest palette entries, remembered for colors with the
+    // same upper 6 bits
+    struct PaletteLookup<'a> {
+        palette: &'a [[u8; 3]],
+        cache: Vec<u16>,
+    }
+
+    impl<'a> PaletteLookup<'a> {
+        fn new(palette: &'a [[u8; 3]]) -> PaletteLookup<'a> {
+            PaletteLookup {
+                palette,
+                cache: vec![u16::MAX; 1 << 18],
+            }
+        }
+
+        fn nearest(&mut self, rgb: [u8; 3]) -> u8 {
+            let key = ((rgb[0] as usize >> 2) << 12) | ((rgb[1] as usize >> 2) << 6) | (rgb[2] as usize >> 2);
+            if self.cache[key] == u16::MAX {
+                let (best, _) = self.palette.iter().enumerate().fold((0, i32::MAX), |best, (i, p)| {
+                    let d: i32 = (0..3).map(|c| (p[c] as i32 - rgb[c] as i32).pow(2)).sum();
+                    if d < best.1 {
+                        (i, d)
+                    } else {
+                        best
+                    }
+                });
+                self.cache[key] = best as u16;
+            }
+            self.cache[key] as u8
+        }
+    }
+
+    // the palette indices of a frame, with Floyd-Steinberg dithering
+    // spreading the error of each pixel to its unprocessed neighbours
+    pub fn quantize_frame(frame: &Frame, width: u32, palette: &[[u8; 3]], dither: bool) -> Vec<u8> {
+        let w = width as usize;
+        let mut lookup = PaletteLookup::new(palette);
+        let mut errors = vec![[0f32; 3]; w + 2];
+        let mut next_errors = vec![[0f32; 3]; w + 2];
+        let mut indices = Vec::with_capacity(frame.len());
+        for row in frame.chunks(w) {
+            for (x, p) in row.iter().enumerate() {
+                let mut rgb = [0u8; 3];
+                for c in 0..3 {
+                    rgb[c] = (p[c] as f32 + errors[x + 1][c]).round().clamp(0.0, 255.0) as u8;
+                }
+                let index = lookup.nearest(rgb);
+                indices.push(index);
+                if dither {
+                    let chosen = palette[index as usize];
+                    for c in 0..3 {
+                        let error = rgb[c] as f32 - chosen[c] as f32;
+                        errors[x + 2][c] += error * 7.0 / 16.0;
+                        next_errors[x][c] += error * 3.0 / 16.0;
+                        next_errors[x + 1][c] += error * 5.0 / 16.0;
+                        next_errors[x + 2][c] += error / 16.0;
+                    }
+                }
+            }
+            std::mem::swap(&mut errors, &mut next_errors);
+            next_errors.iter_mut().for_each(|e| *e = [0.0; 3]);
+        }
+        indices
+    }
+
+    // saves indexed frames with a global palette as an animated GIF;
+    // loops 0 plays forever
+    pub fn write_gif(path: &str, frames: &[Vec<u8>], palette: &[[u8; 3]], width: u32, height: u32, delays: &[u32], loops: u16) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        let flat: Vec<u8> = palette.iter().flatten().copied().collect();
+        let mut encoder = gif::Encoder::new(BufWriter::new(file), width as u16, height as u16, &flat).map_err(|e| e.to_string())?;
+        // the count is of the repetitions after the first play
+        let repeat = if loops == 0 { gif::Repeat::Infinite } else { gif::Repeat::Finite(loops - 1) };
+        encoder.set_repeat(repeat).map_err(|e| e.to_string())?;
+        for (i, indices) in frames.iter().enumerate() {
+            let frame = gif::Frame {
+                // in hundredths of a second, most viewers slow down shorter delays
+                delay: ((frame_delay(delays, i) + 5) / 10).clamp(2, u16::MAX as u32) as u16,
+                width: width as u16,
+                height: height as u16,
+                buffer: Cow::Borrowed(indices),
+                ..gif::Frame::default()
+            };
+            encoder.write_frame(&frame).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    // collects bits from the least significant one up, as WebP reads them
+    struct BitWriter {
+        bytes: Vec<u8>,
+        value: u64,
+        bits: u32,
+    }
+
+    impl BitWriter {
+        fn new() -> BitWriter {
+            BitWriter {
+                bytes: Vec::new(),
+                value: 0,
+                bits: 0,
+            }
+        }
+
+        fn put(&mut self, value: u32, bits: u32) {
+            self.value |= (value as u64) << self.bits;
+            self.bits += bits;
+            while self.bits >= 8 {
+                self.bytes.push(self.value as u8);
+                self.value >>= 8;
+                self.bits -= 8;
+            }
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            if self.bits > 0 {
+                self.bytes.push(self.value as u8);
+            }
+            self.bytes
+        }
+    }
+
+    // huffman code lengths for a histogram, none longer than the limit;
+    // counts are halved until the tree is flat enough
+    fn code_lengths(histogram: &[u32], limit: u8) -> Vec<u8> {
+        let mut counts = histogram.to_vec();
+        loop {
+            let mut parents = vec![usize::MAX; counts.len()];
+            let mut heap: BinaryHeap<Reverse<(u64, usize)>> = counts
+                .iter()
+                .enumerate()
+                .filter(|&(_, &c)| c > 0)
+                .map(|(i, &c)| Reverse((c as u64, i)))
+                .collect();
+            while heap.len() > 1 {
+                let Reverse((a, i)) = heap.pop().unwrap();
+                let Reverse((b, j)) = heap.pop().unwrap();
+                parents.push(usize::MAX);
+                parents[i] = parents.len() - 1;
+                parents[j] = parents.len() - 1;
+                heap.push(Reverse((a + b, parents.len() - 1)));
+            }
+            let lengths: Vec<u8> = (0..counts.len())
+                .map(|i| {
+                    let mut depth = 0;
+                    let mut node = i;
+                    while parents[node] != usize::MAX {
+                        node = parents[node];
+                        depth += 1;
+                    }
+                    depth
+                })
+                .collect();
+            if lengths.iter().all(|&l| l <= limit) {
+                return lengths;
+            }
+            counts.iter_mut().filter(|c| **c > 0).for_each(|c| *c = (*c >> 1).max(1));
+        }
+    }
+
+    // canonical codes for the lengths, bit reversed for the writer
+    fn canonical_codes(lengths: &[u8]) -> Vec<u32> {
+        let mut length_counts = [0u32; 16];
+        lengths.iter().filter(|&&l| l > 0).for_each(|&l| length_counts[l as usize] += 1);
+        let mut next = [0u32; 16];
+        let mut code = 0;
+        for len in 1..16 {
+            code = (code + length_counts[len - 1]) << 1;
+            next[len] = code;
+        }
+        lengths
+            .iter()
+            .map(|&len| {
+                if len == 0 {
+                    return 0;
+                }
+                let code = next[len as usize];
+                next[len as usize] += 1;
+                code.reverse_bits() >> (32 - len as u32)
+            })
+            .collect()
+    }
+
+    // a prefix code ready to write symbols with
+    struct PrefixCode {
+        lengths: Vec<u8>,
+        codes: Vec<u32>,
+    }
+
+    impl PrefixCode {
+        fn put(&self, writer: &mut BitWriter, symbol: usize) {
+            writer.put(self.codes[symbol], self.lengths[symbol] as u32);
+        }
+    }
+
+    // the order the code length code lengths are stored in
+    const CODE_LENGTH_ORDER: [usize; 19] = [17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+    // writes the prefix code for a histogram and returns it; one or two
+    // small symbols use the short form, a single symbol then takes no bits
+    fn write_prefix_code(writer: &mut BitWriter, histogram: &[u32]) -> PrefixCode {
+        let used: Vec<usize> = (0..histogram.len()).filter(|&i| histogram[i] > 0).collect();
+        if used.len() <= 2 && used.iter().all(|&s| s < 256) {
+            let symbols = if used.is_empty() { vec![0] } else { used };
+            writer.put(1, 1);
+            writer.put(symbols.len() as u32 - 1, 1);
+            if symbols[0] < 2 {
+                writer.put(0, 1);
+                writer.put(symbols[0] as u32, 1);
+            } else {
+                writer.put(1, 1);
+                writer.put(symbols[0] as u32, 8);
+            }
+            let mut lengths = vec![0u8; histogram.len()];
+            if symbols.len() == 2 {
+                writer.put(symbols[1] as u32, 8);
+                lengths[symbols[0]] = 1;
+                lengths[symbols[1]] = 1;
+            }
+            let codes = canonical_codes(&lengths);
+            return PrefixCode { lengths, codes };
+        }
+
+        let lengths = code_lengths(histogram, 15);
+        let mut length_histogram = [0u32; 19];
+        lengths.iter().for_each(|&l| length_histogram[l as usize] += 1);
+        let mut length_lengths = code_lengths(&length_histogram, 7);
+        let single = length_histogram.iter().filter(|&&c| c > 0).count() == 1;
+        if single {
+            // a code of one symbol is read without any bits
+            let symbol = length_histogram.iter().position(|&c| c > 0).unwrap();
+            length_lengths[symbol] = 1;
+        }
+        let mut count = CODE_LENGTH_ORDER.len();
+        while count > 4 && length_lengths[CODE_LENGTH_ORDER[count - 1]] == 0 {
+            count -= 1;
+        }
+        writer.put(0, 1);
+        writer.put(count as u32 - 4, 4);
+        for &i in CODE_LENGTH_ORDER[..count].iter() {
+            writer.put(length_lengths[i] as u32, 3);
+        }
+        // the lengths of all symbols follow
+        writer.put(0, 1);
+        let length_codes = canonical_codes(&length_lengths);
+        for &l in lengths.iter() {
+            if !single {
+                writer.put(length_codes[l as usize], length_lengths[l as usize] as u32);
+            }
+        }
+        let codes = canonical_codes(&lengths);
+        PrefixCode { lengths, codes }
+    }
+
+    // a lossless VP8L bitstream of a frame, with the green subtracted from
+    // red and blue and every pixel coded as a literal
+    fn encode_vp8l(frame: &Frame, width: u32, height: u32) -> Vec<u8> {
+        let pixels: Vec<[u8; 3]> = frame
+            .iter()
+            .map(|p| [p[0].wrapping_sub(p[1]), p[1], p[2].wrapping_sub(p[1])])
+            .collect();
+        let mut histograms = [vec![0u32; 256 + 24], vec![0u32; 256], vec![0u32; 256], vec![0u32; 256], vec![0u32; 40]];
+        for p in pixels.iter() {
+            histograms[0][p[1] as usize] += 1;
+            histograms[1][p[0] as usize] += 1;
+            histograms[2][p[2] as usize] += 1;
+        }
+        histograms[3][255] = 1;
+
+        let mut writer = BitWriter::new();
+        writer.put(0x2f, 8);
+        writer.put(width - 1, 14);
+        writer.put(height - 1, 14);
+        // no alpha, version 0
+        writer.put(0, 1);
+        writer.put(0, 3);
+        // the subtract green transform and no other
+        writer.put(1, 1);
+        writer.put(2, 2);
+        writer.put(0, 1);
+        // no color cache and a single group of codes
+        writer.put(0, 1);
+        writer.put(0, 1);
+        let codes: Vec<PrefixCode> = histograms.iter().map(|h| write_prefix_code(&mut writer, h)).collect();
+        for p in pixels.iter() {
+            codes[0].put(&mut writer, p[1] as usize);
+            codes[1].put(&mut writer, p[0] as usize);
+            codes[2].put(&mut writer, p[2] as usize);
+            codes[3].put(&mut writer, 255);
+        }
+        writer.finish()
+    }
+
+    // a RIFF chunk, padded to an even size
+    fn chunk(name: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(data.len() + 9);
+        bytes.extend_from_slice(name);
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    fn u24(value: u32) -> [u8; 3] {
+        let b = value.to_le_bytes();
+        [b[0], b[1], b[2]]
+    }
+
+    // saves the frames as a lossless animated WebP; loops 0 plays forever
+    pub fn write_webp(path: &str, frames: &[Frame], width: u32, height: u32, delays: &[u32], loops: u16, background: [u8; 3]) -> Result<(), String> {
+        if width > 16384 || height > 16384 {
+            return Err(String::from("WebP frames are at most 16384 pixels wide and high"));
+        }
+        let mut file = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+        file.write_all(&webp_animation(frames, width, height, delays, loops, background)).map_err(|e| e.to_string())
+    }
+
+    // the RIFF file of an animated WebP
+    fn webp_animation(frames: &[Frame], width: u32, height: u32, delays: &[u32], loops: u16, background: [u8; 3]) -> Vec<u8> {
+        let mut header = vec![0x02, 0, 0, 0];
+        header.extend_from_slice(&u24(width - 1));
+        header.extend_from_slice(&u24(height - 1));
+        let mut body = Vec::from(*b"WEBP");
+        body.extend(chunk(b"VP8X", &header));
+
+        // the background is stored as blue, green, red, alpha
+        let mut animation = vec![background[2], background[1], background[0], 255];
+        animation.extend_from_slice(&loops.to_le_bytes());
+        body.extend(chunk(b"ANIM", &animation));
+
+        for (i, frame) in frames.iter().enumerate() {
+            // at the top left, covering the whole canvas without blending
+            let mut data = vec![0; 6];
+            data.extend_from_slice(&u24(width - 1));
+            data.extend_from_slice(&u24(height - 1));
+            data.extend_from_slice(&u24(frame_delay(delays, i).min(0xff_ffff)));
+            data.push(0x02);
+            data.extend(chunk(b"VP8L", &encode_vp8l(frame, width, height)));
+            body.extend(chunk(b"ANMF", &data));
+        }
+
+        let mut riff = Vec::from(*b"RIFF");
+        riff.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        riff.extend(body);
+        riff
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use image_webp::{LoopCount, WebPDecoder};
+        use std::io::Cursor;
+
+        // repeatable pseudo random values
+        fn noise(count: usize, seed: u32) -> Vec<u8> {
+            let mut state = seed;
+            (0..count)
+                .map(|_| {
+                    state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                    (state >> 24) as u8
+                })
+                .collect()
+        }
+
+        #[test]
+        fn webp_round_trip() {
+            let (width, height) = (37, 23);
+            let n = (width * height) as usize;
+            let random = noise(n * 3, 7);
+            let frames: Vec<Frame> = vec![
+                // a single color, two colors and a gradient take the short
+                // codes, noise and a skewed histogram the long ones
+                vec![[200, 10, 30]; n],
+                (0..n).map(|i| if i % 3 == 0 { [0, 0, 0] } else { [255, 255, 255] }).collect(),
+                (0..n).map(|i| [(i % 256) as u8, (i / 4 % 256) as u8, 128]).collect(),
+                random.chunks(3).map(|c| [c[0], c[1], c[2]]).collect(),
+                (0..n).map(|i| {
+                    let v = (i as u32).trailing_zeros().min(20) as u8 * 12;
+                    [v, v / 2, 255 - v]
+                }).collect(),
+            ];
+            let delays = [40, 100, 250];
+            let data = webp_animation(&frames, width, height, &delays, 3, [0, 0, 0]);
+
+            let mut decoder = WebPDecoder::new(Cursor::new(data)).unwrap();
+            assert!(decoder.is_animated());
+            assert_eq!(decoder.dimensions(), (width, height));
+            assert_eq!(decoder.num_frames(), frames.len() as u32);
+            assert_eq!(decoder.loop_count(), LoopCount::Times(3.try_into().unwrap()));
+            assert!(!decoder.has_alpha());
+            let mut buffer = vec![0; decoder.output_buffer_size().unwrap()];
+            for (i, frame) in frames.iter().enumerate() {
+                let delay = decoder.read_frame(&mut buffer).unwrap();
+                assert_eq!(delay, frame_delay(&delays, i));
+                let expected: Vec<u8> = frame.iter().flatten().copied().collect();
+                assert_eq!(buffer, expected, "frame {}", i);
+            }
+        }
+
+        #[test]
+        fn code_lengths_are_limited() {
+            // fibonacci counts make the deepest possible tree
+            let mut histogram = vec![1u32, 1];
+            while histogram.len() < 30 {
+                let next = histogram[histogram.len() - 1] + histogram[histogram.len() - 2];
+                histogram.push(next);
+            }
+            histogram.push(0);
+            let lengths = code_lengths(&histogram, 15);
+            assert!(lengths.iter().all(|&l| l <= 15));
+            assert_eq!(lengths[30], 0);
+            // a complete code
+            let kraft: f64 = lengths.iter().filter(|&&l| l > 0).map(|&l| 0.5f64.powi(l as i32)).sum();
+            assert_eq!(kraft, 1.0);
+        }
+
+        #[test]
+        fn median_cut_finds_the_colors() {
+            let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]];
+            let frames: Vec<Frame> = vec![colors.iter().cycle().take(400).copied().collect(), vec![[0, 0, 255]; 100]];
+            let mut palette = median_cut_palette(&frames, 4);
+            palette.sort();
+            let mut expected = colors.to_vec();
+            expected.sort();
+            assert_eq!(palette, expected);
+
+            // two clusters give their averages
+            let frames: Vec<Frame> = vec![vec![[10, 10, 10], [20, 20, 20], [230, 230, 230], [250, 240, 230]]];
+            let mut palette = median_cut_palette(&frames, 2);
+            palette.sort();
+            assert_eq!(palette, vec![[15, 15, 15], [240, 235, 230]]);
+
+            // no more entries than colors
+            assert_eq!(median_cut_palette(&[vec![[7, 8, 9]; 50]], 256), vec![[7, 8, 9]]);
+        }
+
+        #[test]
+        fn quantize_to_nearest_and_dither() {
+            let palette = [[0, 0, 0], [255, 255, 255], [255, 0, 0]];
+            let frame: Frame = vec![[10, 20, 5], [240, 250, 230], [200, 40, 30], [90, 90, 90]];
+            assert_eq!(quantize_frame(&frame, 2, &palette, false), vec![0, 1, 2, 0]);
+
+            // mid gray comes out as black and white in about equal parts
+            let gray: Frame = vec![[128, 128, 128]; 64 * 64];
+            let plain = quantize_frame(&gray, 64, &palette[..2], false);
+            assert!(plain.iter().all(|&i| i == 1));
+            let dithered = quantize_frame(&gray, 64, &palette[..2], true);
+            let white = dithered.iter().filter(|&&i| i == 1).count() as f32 / dithered.len() as f32;
+            assert!((white - 0.5).abs() < 0.02, "{}", white);
+        }
+    }
+}
+
+pub use animation::*;
@@ -0,0 +1,363 @@
+pub mod bilevel {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    use std::fs::File;
+    use std::io::{BufWriter, Write};
+
+    // white and black run codes as (run length, code, bits), from ITU-T T.4
+    const WHITE_TERMINATING: [(u16, u8); 64] = [
+        (0x35, 8), (0x07, 6), (0x07, 4), (0x08, 4), (0x0b, 4), (0x0c, 4), (0x0e, 4), (0x0f, 4),
+        (0x13, 5), (0x14, 5), (0x07, 5), (0x08, 5), (0x08, 6), (0x03, 6), (0x34, 6), (0x35, 6),
+        (0x2a, 6), (0x2b, 6), (0x27, 7), (0x0c, 7), (0x08, 7), (0x17, 7), (0x03, 7), (0x04, 7),
+        (0x28, 7), (0x2b, 7), (0x13, 7), (0x24, 7), (0x18, 7), (0x02, 8), (0x03, 8), (0x1a, 8),
+        (0x1b, 8), (0x12, 8), (0x13, 8), (0x14, 8), (0x15, 8), (0x16, 8), (0x17, 8), (0x28, 8),
+        (0x29, 8), (0x2a, 8), (0x2b, 8), (0x2c, 8), (0x2d, 8), (0x04, 8), (0x05, 8), (0x0a, 8),
+        (0x0b, 8), (0x52, 8), (0x53, 8), (0x54, 8), (0x55, 8), (0x24, 8), (0x25, 8), (0x58, 8),
+        (0x59, 8), (0x5a, 8), (0x5b, 8), (0x4a, 8), (0x4b, 8), (0x32, 8), (0x33, 8), (0x34, 8),
+    ];
+
+    const BLACK_TERMINATING: [(u16, u8); 64] = [
+        (0x37, 10), (0x02, 3), (0x03, 2), (0x02, 2), (0x03, 3), (0x03, 4), (0x02, 4), (0x03, 5),
+        (0x05, 6), (0x04, 6), (0x04, 7), (0x05, 7), (0x07, 7), (0x04, 8), (0x07, 8), (0x18, 9),
+        (0x17, 10), (0x18, 10), (0x08, 10), (0x67, 11), (0x68, 11), (0x6c, 11), (0x37, 11), (0x28, 11),
+        (0x17, 11), (0x18, 11), (0xca, 12), (0xcb, 12), (0xcc, 12), (0xcd, 12), (0x68, 12), (0x69, 12),
+        (0x6a, 12), (0x6b, 12), (0xd2, 12), (0xd3, 12), (0xd4, 12), (0xd5, 12), (0xd6, 12), (0xd7, 12),
+        (0x6c, 12), (0x6d, 12), (0xda, 12), (0xdb, 12), (0x54, 12), (0x55, 12), (0x56, 12), (0x57, 12),
+        (0x64, 12), (0x65, 12), (0x52, 12), (0x53, 12), (0x24, 12), (0x37, 12), (0x38, 12), (0x27, 12),
+        (0x28, 12), (0x58, 12), (0x59, 12), (0x2b, 12), (0x2c, 12), (0x5a, 12), (0x66, 12), (0x67, 12),
+    ];
+
+    // makeup codes for 64 to 1728 pixels in steps of 64
+    const WHITE_MAKEUP: [(u16, u8); 27] = [
+        (0x1b, 5), (0x12, 5), (0x17, 6), (0x37, 7), (0x36, 8), (0x37, 8), (0x64, 8), (0x65, 8),
+        (0x68, 8), (0x67, 8), (0xcc, 9), (0xcd, 9), (0xd2, 9), (0xd3, 9), (0xd4, 9), (0xd5, 9),
+        (0xd6, 9), (0xd7, 9), (0xd8, 9), (0xd9, 9), (0xda, 9), (0xdb, 9), (0x98, 9), (0x99, 9),
+        (0x9a, 9), (0x18, 6), (0x9b, 9),
+    ];
+
+    const BLACK_MAKEUP: [(u16, u8); 27] = [
+        (0x0f, 10), (0xc8, 12), (0xc9, 12), (0x5b, 12), (0x33, 12), (0x34, 12), (0x35, 12), (0x6c, 13),
+        (0x6d, 13), (0x4a, 13), (0x4b, 13), (0x4c, 13), (0x4d, 13), (0x72, 13), (0x73, 13), (0x74, 13),
+        (0x75, 13), (0x76, 13), (0x77, 13), (0x52, 13), (0x53, 13), (0x54, 13), (0x55, 13), (0x5a, 13),
+        (0x5b, 13), (0x64, 13), (0x65, 13),
+    ];
+
+    // makeup codes for 1792 to 2560 pixels shared by both colors
+    const EXTENDED_MAKEUP: [(u16, u8); 13] = [
+        (0x08, 11), (0x0c, 11), (0x0d, 11), (0x12, 12), (0x13, 12), (0x14, 12), (0x15, 12), (0x16, 12),
+        (0x17, 12), (0x1c, 12), (0x1d, 12), (0x1e, 12), (0x1f, 12),
+    ];
+
+    // writes codes most significant bit first
+    struct BitWriter {
+        data: Vec<u8>,
+        current: u32,
+        bits: u8,
+    }
+
+    impl BitWriter {
+        fn new() -> BitWriter {
+            BitWriter { data: Vec::new(), current: 0, bits: 0 }
+        }
+
+        fn put(&mut self, code: (u16, u8)) {
+            let (value, length) = code;
+            self.current = (self.current << length) | value as u32;
+            self.bits += length;
+            while self.bits >= 8 {
+                self.bits -= 8;
+                self.data.push((self.current >> self.bits) as u8);
+            }
+            self.current &= (1 << self.bits) - 1;
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            if self.bits > 0 {
+                self.data.push((self.current << (8 - self.bits)) as u8);
+            }
+            self.data
+        }
+    }
+
+    // the black pixels of an image row by row; pixels below 128 are black
+    fn black_rows(img: &Image<u8>) -> Vec<Vec<bool>> {
+        let (width, _height) = img.info().wh();
+        img.data()
+            .chunks(width as usize)
+            .map(|row| row.iter().map(|&v| v < 128).collect())
+            .collect()
+    }
+
+    // positions where a row changes color, starting from white; even
+    // entries turn black and odd entries turn white
+    fn changes(row: &[bool]) -> Vec<usize> {
+        let mut list = Vec::new();
+        let mut color = false;
+        for (x, &black) in row.iter().enumerate() {
+            if black != color {
+                list.push(x);
+                color = black;
+            }
+        }
+        list
+    }
+
+    fn put_run(writer: &mut BitWriter, run: usize, black: bool) {
+        let (terminating, makeup) = if black {
+            (&BLACK_TERMINATING, &BLACK_MAKEUP)
+        } else {
+            (&WHITE_TERMINATING, &WHITE_MAKEUP)
+        };
+        let mut run = run;
+        while run > 2560 {
+            writer.put(EXTENDED_MAKEUP[12]);
+            run -= 2560;
+        }
+        if run >= 1792 {
+            writer.put(EXTENDED_MAKEUP[(run - 1792) / 64]);
+            run %= 64;
+        } else if run >= 64 {
+            writer.put(makeup[run / 64 - 1]);
+            run %= 64;
+        }
+        writer.put(terminating[run]);
+    }
+
+    // encodes black and white rows with CCITT group 4 (ITU-T T.6), each row
+    // coded against the one above it
+    pub fn encode_g4(rows: &[Vec<bool>], width: usize) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        let mut reference: Vec<usize> = Vec::new();
+        for row in rows.iter() {
+            let coding = changes(row);
+            let mut a0: i64 = -1;
+            let mut black = false;
+            loop {
+                let next = |list: &[usize], after: i64| -> usize {
+                    list.iter().map(|&p| p as i64).find(|&p| p > after).unwrap_or(width as i64) as usize
+                };
+                let a1 = next(&coding, a0);
+                // the first reference change after a0 to the opposite color
+                let index = reference
+                    .iter()
+                    .enumerate()
+                    .position(|(i, &p)| p as i64 > a0 && (i % 2 == 0) != black);
+                let b1 = index.map(|i| reference[i]).unwrap_or(width);
+                let b2 = index.and_then(|i| reference.get(i + 1)).copied().unwrap_or(width);
+
+                if b2 < a1 {
+                    // pass mode
+                    writer.put((0x1, 4));
+                    a0 = b2 as i64;
+                } else if (a1 as i64 - b1 as i64).abs() <= 3 {
+                    // vertical mode
+                    let code = match a1 as i64 - b1 as i64 {
+                        0 => (0x1, 1),
+                        1 => (0x3, 3),
+                        2 => (0x3, 6),
+                        3 => (0x3, 7),
+                        -1 => (0x2, 3),
+                        -2 => (0x2, 6),
+                        _ => (0x2, 7),
+                    };
+                    writer.put(code);
+                    a0 = a1 as i64;
+                    black = !black;
+                } else {
+                    // horizontal mode
+                    let a2 = next(&coding, a1 as i64);
+                    writer.put((0x1, 3));
+                    put_run(&mut writer, a1 - a0.max(0) as usize, black);
+                    put_run(&mut writer, a2 - a1, !black);
+                    a0 = a2 as i64;
+                }
+                if a0 >= width as i64 {
+                    break;
+                }
+            }
+            reference = coding;
+        }
+        // end of facsimile block
+        writer.put((0x1, 12));
+        writer.put((0x1, 12));
+        writer.finish()
+    }
+
+    // saves a black and white image as a 1-bit grayscale PNG
+    pub fn write_png_1bit(img: &Image<u8>, path: &str) -> Result<(), String> {
+        let (width, height) = img.info().wh();
+        let mut data = Vec::new();
+        for row in black_rows(img) {
+            for byte in row.chunks(8) {
+                let mut value = 0u8;
+                for (i, &black) in byte.iter().enumerate() {
+                    if !black {
+                        value |= 0x80 >> i;
+                    }
+                }
+                data.push(value);
+            }
+        }
+
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::One);
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+        writer.write_image_data(&data).map_err(|e| e.to_string())
+    }
+
+    // saves a black and white image as a CCITT group 4 compressed TIFF
+    pub fn write_tiff_g4(img: &Image<u8>, path: &str) -> Result<(), String> {
+        let mut file = File::create(path).map_err(|e| e.to_string())?;
+        file.write_all(&tiff_g4(img)).map_err(|e| e.to_string())
+    }
+
+    // the TIFF file of a black and white image: the header, a single IFD,
+    // the resolution values and the strip
+    fn tiff_g4(img: &Image<u8>) -> Vec<u8> {
+        let (width, height) = img.info().wh();
+        let strip = encode_g4(&black_rows(img), width as usize);
+
+        // the resolution is not known, the usual 72 dpi is assumed
+        let resolution = 72u32;
+        let tags: [(u16, u16, u32); 12] = [
+            (256, 4, width),  // ImageWidth
+            (257, 4, height), // ImageLength
+            (258, 3, 1),      // BitsPerSample
+            (259, 3, 4),      // Compression: CCITT group 4
+            (262, 3, 0),      // PhotometricInterpretation: white is zero
+            (273, 4, 0),      // StripOffsets, set below
+            (277, 3, 1),      // SamplesPerPixel
+            (278, 4, height), // RowsPerStrip
+            (279, 4, strip.len() as u32), // StripByteCounts
+            (282, 5, 0),      // XResolution, the offset of the value set below
+            (283, 5, 0),      // YResolution, likewise
+            (296, 3, 2),      // ResolutionUnit: inch
+        ];
+        let rational_offset = 8 + 2 + tags.len() as u32 * 12 + 4;
+        let strip_offset = rational_offset + 16;
+
+        let mut out = Vec::with_capacity(strip_offset as usize + strip.len());
+        out.extend_from_slice(b"II*\0");
+        out.extend_from_slice(&8u32.to_le_bytes());
+        out.extend_from_slice(&(tags.len() as u16).to_le_bytes());
+        for &(tag, kind, value) in tags.iter() {
+            let value = match tag {
+                273 => strip_offset,
+                282 => rational_offset,
+                283 => rational_offset + 8,
+                _ => value,
+            };
+            out.extend_from_slice(&tag.to_le_bytes());
+            out.extend_from_slice(&kind.to_le_bytes());
+            out.extend_from_slice(&1u32.to_le_bytes());
+            if kind == 3 {
+                out.extend_from_slice(&(value as u16).to_le_bytes());
+                out.extend_from_slice(&[0, 0]);
+            } else {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        out.extend_from_slice(&0u32.to_le_bytes());
+        for _ in 0..2 {
+            out.extend_from_slice(&resolution.to_le_bytes());
+            out.extend_from_slice(&1u32.to_le_bytes());
+        }
+        out.extend_from_slice(&strip);
+        out
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        // the coded bits as a string, checking and removing the end of
+        // facsimile block and the padding
+        fn coded(rows: &[&str], width: usize) -> String {
+            let rows: Vec<Vec<bool>> = rows.iter().map(|r| r.chars().map(|c| c == '#').collect()).collect();
+            let bits: String = encode_g4(&rows, width).iter().map(|b| format!("{:08b}", b)).collect();
+            let end = bits.rfind('1').unwrap() + 1;
+            assert!(bits.len() - end < 8);
+            assert!(bits[..end].ends_with("000000000001000000000001"));
+            bits[..end - 24].to_string()
+        }
+
+        #[test]
+        fn all_white_row() {
+            assert_eq!(coded(&["........"], 8), "1");
+            assert_eq!(encode_g4(&[vec![false; 8]], 8), vec![0x80, 0x08, 0x00, 0x80]);
+        }
+
+        #[test]
+        fn single_black_run() {
+            // horizontal mode: 2 white, 3 black, then V0 to the end
+            assert_eq!(coded(&["..###..."], 8), "001".to_owned() + "0111" + "10" + "1");
+        }
+
+        #[test]
+        fn vertical_modes() {
+            let reference = "....######......";
+            // horizontal mode: 4 white, 6 black, then V0 to the end
+            let first = "001".to_owned() + "1011" + "0010" + "1";
+            let codes = [
+                (-3, "0000010"),
+                (-2, "000010"),
+                (-1, "010"),
+                (0, "1"),
+                (1, "011"),
+                (2, "000011"),
+                (3, "0000011"),
+            ];
+            for (shift, code) in codes {
+                let start = (4 + shift) as usize;
+                let row: String = (0..16).map(|x| if x >= start && x < 10 { '#' } else { '.' }).collect();
+                // the first change moved, the second and the end unchanged
+                assert_eq!(coded(&[reference, &row], 16), first.clone() + code + "1" + "1", "shift {}", shift);
+            }
+        }
+
+        #[test]
+        fn pass_mode() {
+            let first = "001".to_owned() + "0111" + "11" + "1";
+            // the reference run ends before the coding row turns black,
+            // then horizontal mode with 4 white and 2 black
+            let second = "0001".to_owned() + "001" + "1011" + "11" + "1";
+            assert_eq!(coded(&["..##............", "........##......"], 16), first + &second);
+        }
+
+        #[test]
+        fn makeup_codes() {
+            // 70 white as 64 and 6, then 10 black
+            let row: String = (0..80).map(|x| if x >= 70 { '#' } else { '.' }).collect();
+            assert_eq!(coded(&[&row], 80), "001".to_owned() + "11011" + "1110" + "0000100");
+            // no white, 130 black as 128 and 2, then V0 to the end
+            let row: String = (0..200).map(|x| if x < 130 { '#' } else { '.' }).collect();
+            assert_eq!(coded(&[&row], 200), "001".to_owned() + "00110101" + "000011001000" + "11" + "1");
+        }
+
+        #[test]
+        fn tiff_tags() {
+            let img = Image::from_slice(8, 1, 1, false, &[255, 255, 0, 0, 0, 255, 255, 255]);
+            let tiff = tiff_g4(&img);
+            let u16_at = |i: usize| u16::from_le_bytes([tiff[i], tiff[i + 1]]);
+            let u32_at = |i: usize| u32::from_le_bytes([tiff[i], tiff[i + 1], tiff[i + 2], tiff[i + 3]]);
+            assert_eq!(&tiff[0..8], b"II*\0\x08\0\0\0");
+            let count = u16_at(8) as usize;
+            let entries: Vec<(u16, u16, u32)> = (0..count).map(|i| 10 + i * 12).map(|e| (u16_at(e), u16_at(e + 2), u32_at(e + 8))).collect();
+            // sorted by tag, as TIFF requires
+            assert!(entries.windows(2).all(|w| w[0].0 < w[1].0));
+            let value = |tag: u16| entries.iter().find(|e| e.0 == tag).unwrap().2;
+            for tag in [282, 283] {
+                let offset = value(tag) as usize;
+                assert_eq!((u32_at(offset), u32_at(offset + 4)), (72, 1));
+            }
+            assert_eq!(value(296) & 0xffff, 2);
+            let (offset, length) = (value(273) as usize, value(279) as usize);
+            assert_eq!(&tiff[offset..], encode_g4(&black_rows(&img), 8).as_slice());
+            assert_eq!(offset + length, tiff.len());
+        }
+    }
+}
+
+pub use bilevel::*;
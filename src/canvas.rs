@@ -0,0 +1,220 @@
+pub mod canvas {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    pub use crate::config::*;
+
+    use crate::color::*;
+    use crate::convolve::*;
+    use crate::crop::*;
+    use crate::grayscale::*;
+    use crate::warp::*;
+
+    // the size to pad to
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum PadTarget {
+        // a canvas of exactly this size, larger images are scaled down to fit
+        Size(u32, u32),
+        // the smallest canvas with this aspect ratio holding the image
+        Aspect(u32, u32),
+    }
+
+    // what the padding is filled with
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum PadFill {
+        Color([u8; 3]),
+        // a blurred copy of the image covering the canvas
+        Blur,
+    }
+
+    // parses WxH or W:H, e.g. 1080x1080 or 1:1
+    pub fn parse_pad(spec: &str) -> Option<PadTarget> {
+        let spec = spec.trim().to_lowercase();
+        let (target, sep): (fn(u32, u32) -> PadTarget, char) = if spec.contains(':') {
+            (PadTarget::Aspect, ':')
+        } else {
+            (PadTarget::Size, 'x')
+        };
+        let (w, h) = spec.split_once(sep)?;
+        let w = w.trim().parse::<u32>().ok()?;
+        let h = h.trim().parse::<u32>().ok()?;
+        if w == 0 || h == 0 {
+            return None;
+        }
+        Some(target(w, h))
+    }
+
+    pub fn parse_pad_fill(spec: &str) -> Option<PadFill> {
+        if spec.trim().eq_ignore_ascii_case("blur") {
+            return Some(PadFill::Blur);
+        }
+        Some(PadFill::Color(parse_color(spec)?))
+    }
+
+    // parses WIDTH[,COLOR[,RADIUS]], e.g. 20,white,40 (default: black, square corners)
+    pub fn parse_border(spec: &str) -> Option<(u32, [u8; 3], u32)> {
+        let parts = split_list(spec);
+        if parts.len() > 3 {
+            return None;
+        }
+        let width = parts[0].parse::<u32>().ok()?;
+        let color = match parts.get(1) {
+            Some(c) => parse_color(c)?,
+            None => [0, 0, 0],
+        };
+        let radius = match parts.get(2) {
+            Some(r) => r.parse::<u32>().ok()?,
+            None => 0,
+        };
+        Some((width, color, radius))
+    }
+
+    // a color as a pixel of an image with the given channels
+    fn color_pixel(color: [u8; 3], channels: u8, alpha: bool) -> Vec<u8> {
+        let colors = if alpha { channels - 1 } else { channels };
+        let mut pixel = if colors < 3 {
+            vec![gray_value(&color, GrayMethod::Rec601, false)]
+        } else {
+            color.to_vec()
+        };
+        if alpha {
+            pixel.push(255);
+        }
+        pixel
+    }
+
+    // an image of one color
+    pub fn solid_image(width: u32, height: u32, channels: u8, alpha: bool, color: [u8; 3]) -> Image<u8> {
+        let pixel = color_pixel(color, channels, alpha);
+        let data = pixel.iter().copied().cycle().take((width * height) as usize * pixel.len()).collect();
+        Image::from_vec(width, height, channels, alpha, data)
+    }
+
+    // the image scaled to cover the canvas and blurred; the blur is done on
+    // a small copy, which is much faster and just as smooth
+    pub fn blurred_image(img: &Image<u8>, width: u32, height: u32) -> Image<u8> {
+        let (iw, ih) = img.info().wh();
+        let scale = (width as f32 / iw as f32).max(height as f32 / ih as f32);
+        let (cw, ch) = ((width as f32 / scale).round() as u32, (height as f32 / scale).round() as u32);
+        let (cw, ch) = (cw.clamp(1, iw), ch.clamp(1, ih));
+        let cover = crop_rect(img, (iw - cw) / 2, (ih - ch) / 2, cw, ch);
+
+        let shrink = (200.0 / cw.max(ch) as f32).min(1.0);
+        let (sw, sh) = (((cw as f32 * shrink).round() as u32).max(1), ((ch as f32 * shrink).round() as u32).max(1));
+        let small = resize_image(&cover, sw, sh);
+        let blurred = gaussian_blur(&small, sw.max(sh) as f32 / 40.0, BorderMode::Reflect);
+        resize_image(&blurred, width, height)
+    }
+
+    // copies an image onto a larger one at x, y
+    pub fn paste(canvas: &mut Image<u8>, img: &Image<u8>, x: u32, y: u32) {
+        let (width, height) = img.info().wh();
+        for yi in 0..height {
+            for xi in 0..width {
+                canvas.set_pixel(x + xi, y + yi, img.get_pixel(xi, yi));
+            }
+        }
+    }
+
+    // pads an image to the target, placing it by gravity
+    pub fn pad_image(img: &Image<u8>, target: PadTarget, fill: PadFill, gravity: Gravity) -> Image<u8> {
+        let (width, height, channels, alpha) = img.info().whca();
+        let (cw, ch) = match target {
+            PadTarget::Size(w, h) => (w, h),
+            PadTarget::Aspect(aw, ah) => {
+                // grow whichever side is short for the ratio
+                let w = (height as u64 * aw as u64).div_ceil(ah as u64) as u32;
+                if w >= width {
+                    (w, height)
+                } else {
+                    (width, (width as u64 * ah as u64).div_ceil(aw as u64) as u32)
+                }
+            }
+        };
+
+        let fitted;
+        let img = if width > cw || height > ch {
+            let scale = (cw as f32 / width as f32).min(ch as f32 / height as f32);
+            let w = ((width as f32 * scale).round() as u32).clamp(1, cw);
+            let h = ((height as f32 * scale).round() as u32).clamp(1, ch);
+            fitted = resize_image(img, w, h);
+            &fitted
+        } else {
+            img
+        };
+
+        let mut canvas = match fill {
+            PadFill::Color(color) => solid_image(cw, ch, channels, alpha, color),
+            PadFill::Blur => blurred_image(img, cw, ch),
+        };
+        let (w, h) = img.info().wh();
+        let (x, y) = gravity_offset(cw, ch, w, h, gravity);
+        paste(&mut canvas, img, x, y);
+        canvas
+    }
+
+    // surrounds an image with a border; with a radius the corners of the
+    // image are rounded into the border
+    pub fn add_border(img: &Image<u8>, width: u32, color: [u8; 3], radius: u32) -> Image<u8> {
+        let (iw, ih, channels, alpha) = img.info().whca();
+        let mut canvas = solid_image(iw + 2 * width, ih + 2 * width, channels, alpha, color);
+        paste(&mut canvas, img, width, width);
+        if radius == 0 {
+            return canvas;
+        }
+
+        let fill = color_pixel(color, channels, alpha);
+        let r = radius.min(iw / 2).min(ih / 2) as f32;
+        for y in 0..ih {
+            for x in 0..iw {
+                // distance past the corner circle, 0 outside the corners
+                let dx = (r - 0.5 - x as f32).max(x as f32 + 0.5 - (iw as f32 - r)).max(0.0);
+                let dy = (r - 0.5 - y as f32).max(y as f32 + 0.5 - (ih as f32 - r)).max(0.0);
+                if dx == 0.0 || dy == 0.0 {
+                    continue;
+                }
+                // antialiased coverage of the border color
+                let t = ((dx * dx + dy * dy).sqrt() - r + 0.5).clamp(0.0, 1.0);
+                if t > 0.0 {
+                    let pixel: Vec<u8> = img
+                        .get_pixel(x, y)
+                        .iter()
+                        .zip(fill.iter())
+                        .map(|(&p, &f)| round_u8(p as f32 * (1.0 - t) + f as f32 * t))
+                        .collect();
+                    canvas.set_pixel(x + width, y + width, &pixel);
+                }
+            }
+        }
+        canvas
+    }
+
+    // runs the padding and then the border options
+    pub fn apply_canvas(img: Image<u8>, opts: &ConfigOptions) -> Image<u8> {
+        let mut img = img;
+
+        if !opts.pad.is_empty() {
+            let fill = match parse_pad_fill(&opts.pad_fill) {
+                Some(f) => f,
+                None => {
+                    println!("Invalid padding fill {}, using white", opts.pad_fill);
+                    PadFill::Color([255, 255, 255])
+                }
+            };
+            let gravity = parse_gravity(&opts.gravity).unwrap_or(Gravity::Center);
+            match parse_pad(&opts.pad) {
+                Some(target) => img = pad_image(&img, target, fill, gravity),
+                None => println!("Invalid padding {}", opts.pad),
+            }
+        }
+
+        if !opts.border.is_empty() {
+            match parse_border(&opts.border) {
+                Some((width, color, radius)) => img = add_border(&img, width, color, radius),
+                None => println!("Invalid border {}", opts.border),
+            }
+        }
+        img
+    }
+}
+
+pub use canvas::*;
@@ -0,0 +1,296 @@
+pub mod color {
+
+    use colorsys::{Hsl, Rgb};
+    use imgproc_rs::image::{BaseImage, Image, ImageInfo};
+    pub use crate::config::*;
+
+    use crate::grayscale::*;
+
+    // the CSS named colors
+    const CSS_COLORS: [(&str, u32); 148] = [
+        ("aliceblue", 0xf0f8ff), ("antiquewhite", 0xfaebd7), ("aqua", 0x00ffff),
+        ("aquamarine", 0x7fffd4), ("azure", 0xf0ffff), ("beige", 0xf5f5dc),
+        ("bisque", 0xffe4c4), ("black", 0x000000), ("blanchedalmond", 0xffebcd),
+        ("blue", 0x0000ff), ("blueviolet", 0x8a2be2), ("brown", 0xa52a2a),
+        ("burlywood", 0xdeb887), ("cadetblue", 0x5f9ea0), ("chartreuse", 0x7fff00),
+        ("chocolate", 0xd2691e), ("coral", 0xff7f50), ("cornflowerblue", 0x6495ed),
+        ("cornsilk", 0xfff8dc), ("crimson", 0xdc143c), ("cyan", 0x00ffff),
+        ("darkblue", 0x00008b), ("darkcyan", 0x008b8b), ("darkgoldenrod", 0xb8860b),
+        ("darkgray", 0xa9a9a9), ("darkgreen", 0x006400), ("darkgrey", 0xa9a9a9),
+        ("darkkhaki", 0xbdb76b), ("darkmagenta", 0x8b008b), ("darkolivegreen", 0x556b2f),
+        ("darkorange", 0xff8c00), ("darkorchid", 0x9932cc), ("darkred", 0x8b0000),
+        ("darksalmon", 0xe9967a), ("darkseagreen", 0x8fbc8f), ("darkslateblue", 0x483d8b),
+        ("darkslategray", 0x2f4f4f), ("darkslategrey", 0x2f4f4f), ("darkturquoise", 0x00ced1),
+        ("darkviolet", 0x9400d3), ("deeppink", 0xff1493), ("deepskyblue", 0x00bfff),
+        ("dimgray", 0x696969), ("dimgrey", 0x696969), ("dodgerblue", 0x1e90ff),
+        ("firebrick", 0xb22222), ("floralwhite", 0xfffaf0), ("forestgreen", 0x228b22),
+        ("fuchsia", 0xff00ff), ("gainsboro", 0xdcdcdc), ("ghostwhite", 0xf8f8ff),
+        ("gold", 0xffd700), ("goldenrod", 0xdaa520), ("gray", 0x808080), ("green", 0x008000),
+        ("greenyellow", 0xadff2f), ("grey", 0x808080), ("honeydew", 0xf0fff0),
+        ("hotpink", 0xff69b4), ("indianred", 0xcd5c5c), ("indigo", 0x4b0082),
+        ("ivory", 0xfffff0), ("khaki", 0xf0e68c), ("lavender", 0xe6e6fa),
+        ("lavenderblush", 0xfff0f5), ("lawngreen", 0x7cfc00), ("lemonchiffon", 0xfffacd),
+        ("lightblue", 0xadd8e6), ("lightcoral", 0xf08080), ("lightcyan", 0xe0ffff),
+        ("lightgoldenrodyellow", 0xfafad2), ("lightgray", 0xd3d3d3), ("lightgreen", 0x90ee90),
+        ("lightgrey", 0xd3d3d3), ("lightpink", 0xffb6c1), ("lightsalmon", 0xffa07a),
+        ("lightseagreen", 0x20b2aa), ("lightskyblue", 0x87cefa), ("lightslategray", 0x778899),
+        ("lightslategrey", 0x778899), ("lightsteelblue", 0xb0c4de), ("lightyellow", 0xffffe0),
+        ("lime", 0x00ff00), ("limegreen", 0x32cd32), ("linen", 0xfaf0e6), ("magenta", 0xff00ff),
+        ("maroon", 0x800000), ("mediumaquamarine", 0x66cdaa), ("mediumblue", 0x0000cd),
+        ("mediumorchid", 0xba55d3), ("mediumpurple", 0x9370db), ("mediumseagreen", 0x3cb371),
+        ("mediumslateblue", 0x7b68ee), ("mediumspringgreen", 0x00fa9a),
+        ("mediumturquoise", 0x48d1cc), ("mediumvioletred", 0xc71585),
+        ("midnightblue", 0x191970), ("mintcream", 0xf5fffa), ("mistyrose", 0xffe4e1),
+        ("moccasin", 0xffe4b5), ("navajowhite", 0xffdead), ("navy", 0x000080),
+        ("oldlace", 0xfdf5e6), ("olive", 0x808000), ("olivedrab", 0x6b8e23),
+        ("orange", 0xffa500), ("orangered", 0xff4500), ("orchid", 0xda70d6),
+        ("palegoldenrod", 0xeee8aa), ("palegreen", 0x98fb98), ("paleturquoise", 0xafeeee),
+        ("palevioletred", 0xdb7093), ("papayawhip", 0xffefd5), ("peachpuff", 0xffdab9),
+        ("peru", 0xcd853f), ("pink", 0xffc0cb), ("plum", 0xdda0dd), ("powderblue", 0xb0e0e6),
+        ("purple", 0x800080), ("rebeccapurple", 0x663399), ("red", 0xff0000),
+        ("rosybrown", 0xbc8f8f), ("royalblue", 0x4169e1), ("saddlebrown", 0x8b4513),
+        ("salmon", 0xfa8072), ("sandybrown", 0xf4a460), ("seagreen", 0x2e8b57),
+        ("seashell", 0xfff5ee), ("sienna", 0xa0522d), ("silver", 0xc0c0c0),
+        ("skyblue", 0x87ceeb), ("slateblue", 0x6a5acd), ("slategray", 0x708090),
+        ("slategrey", 0x708090), ("snow", 0xfffafa), ("springgreen", 0x00ff7f),
+        ("steelblue", 0x4682b4), ("tan", 0xd2b48c), ("teal", 0x008080), ("thistle", 0xd8bfd8),
+        ("tomato", 0xff6347), ("turquoise", 0x40e0d0), ("violet", 0xee82ee),
+        ("wheat", 0xf5deb3), ("white", 0xffffff), ("whitesmoke", 0xf5f5f5),
+        ("yellow", 0xffff00), ("yellowgreen", 0x9acd32),
+    ];
+
+    // parses a color as a CSS name, #RGB / #RRGGBB hex or rgb(R,G,B)
+    pub fn parse_color(spec: &str) -> Option<[u8; 3]> {
+        let spec = spec.trim().to_lowercase();
+        if let Some((_, value)) = CSS_COLORS.iter().find(|(name, _)| *name == spec) {
+            return Some([(value >> 16) as u8, (value >> 8) as u8, *value as u8]);
+        }
+        let rgb = if spec.starts_with("rgb") {
+            spec.parse::<Rgb>().ok()?
+        } else {
+            let hex = spec.trim_start_matches('#');
+            if (hex.len() != 3 && hex.len() != 6) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+            Rgb::from_hex_str(hex).ok()?
+        };
+        Some([
+            round_u8(rgb.red() as f32),
+            round_u8(rgb.green() as f32),
+            round_u8(rgb.blue() as f32),
+        ])
+    }
+
+    // splits a list on commas outside of parentheses, so rgb(...) colors survive
+    pub fn split_list(spec: &str) -> Vec<&str> {
+        let mut parts = Vec::new();
+        let mut depth = 0;
+        let mut start = 0;
+        for (i, c) in spec.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ',' if depth == 0 => {
+                    parts.push(spec[start..i].trim());
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        parts.push(spec[start..].trim());
+        parts
+    }
+
+    // an adjustment of the colors within a hue range
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct HueRange {
+        // center of the range in degrees
+        pub center: f64,
+        // full width of the range in degrees, fading out over another half width
+        pub width: f64,
+        // hue shift in degrees
+        pub hue: f64,
+        // saturation and lightness changes from -100 to 100 percent
+        pub saturation: f64,
+        pub lightness: f64,
+    }
+
+    // the color adjustments applied in one pass
+    #[derive(Debug, Clone, Default, PartialEq)]
+    pub struct ColorAdjustments {
+        // saturation change from -100 (gray) to 100 (double) percent
+        pub saturation: f64,
+        // like saturation, but leaves saturated colors and skin tones mostly alone
+        pub vibrance: f64,
+        // hue rotation in degrees
+        pub hue: f64,
+        pub ranges: Vec<HueRange>,
+    }
+
+    impl ColorAdjustments {
+        pub fn is_empty(&self) -> bool {
+            self.saturation == 0.0 && self.vibrance == 0.0 && self.hue == 0.0 && self.ranges.is_empty()
+        }
+    }
+
+    // the named hue ranges, as center and width in degrees
+    fn named_range(name: &str) -> Option<(f64, f64)> {
+        match name {
+            "reds" | "red" => Some((0.0, 60.0)),
+            "yellows" | "yellow" => Some((60.0, 60.0)),
+            "greens" | "green" => Some((120.0, 60.0)),
+            "cyans" | "cyan" => Some((180.0, 60.0)),
+            "blues" | "blue" => Some((240.0, 60.0)),
+            "magentas" | "magenta" => Some((300.0, 60.0)),
+            _ => None,
+        }
+    }
+
+    // parses hue range adjustments separated by ';', each as
+    // RANGE:HUE[,SATURATION[,LIGHTNESS]] where RANGE is a name (reds,
+    // yellows, greens, cyans, blues, magentas) or CENTER/WIDTH in degrees
+    pub fn parse_hue_ranges(spec: &str) -> Option<Vec<HueRange>> {
+        let mut ranges = Vec::new();
+        for part in spec.to_lowercase().split(';') {
+            let (range, values) = part.split_once(':')?;
+            let range = range.trim();
+            let (center, width) = match named_range(range) {
+                Some(r) => r,
+                None => {
+                    let (c, w) = range.split_once('/')?;
+                    (c.trim().parse::<f64>().ok()?, w.trim().parse::<f64>().ok()?)
+                }
+            };
+            let values: Vec<f64> = values
+                .split(',')
+                .map(|v| v.trim().parse::<f64>())
+                .collect::<Result<_, _>>()
+                .ok()?;
+            if values.is_empty() || values.len() > 3 || width <= 0.0 {
+                return None;
+            }
+            ranges.push(HueRange {
+                center: center.rem_euclid(360.0),
+                width: width.min(360.0),
+                hue: values[0],
+                saturation: *values.get(1).unwrap_or(&0.0),
+                lightness: *values.get(2).unwrap_or(&0.0),
+            });
+        }
+        Some(ranges)
+    }
+
+    // collects the color adjustment options, reporting invalid ones
+    pub fn color_adjustments(opts: &ConfigOptions) -> ColorAdjustments {
+        let mut adjustments = ColorAdjustments::default();
+        let number = |spec: &str, name: &str, min: f64, max: f64| -> f64 {
+            if spec.is_empty() {
+                return 0.0;
+            }
+            match spec.trim().parse::<f64>() {
+                Ok(v) if v >= min && v <= max => v,
+                _ => {
+                    println!("Invalid {} {}", name, spec);
+                    0.0
+                }
+            }
+        };
+        adjustments.saturation = number(&opts.saturation, "saturation", -100.0, 100.0);
+        adjustments.vibrance = number(&opts.vibrance, "vibrance", -100.0, 100.0);
+        adjustments.hue = number(&opts.hue, "hue", -360.0, 360.0);
+        if !opts.hue_ranges.is_empty() {
+            match parse_hue_ranges(&opts.hue_ranges) {
+                Some(ranges) => adjustments.ranges = ranges,
+                None => println!("Invalid hue ranges {}", opts.hue_ranges),
+            }
+        }
+        adjustments
+    }
+
+    // how much a hue belongs to the range: 1 inside, fading to 0
+    // over another half width on both sides
+    fn range_weight(hue: f64, range: &HueRange) -> f64 {
+        let distance = (hue - range.center).rem_euclid(360.0);
+        let distance = distance.min(360.0 - distance);
+        let half = range.width / 2.0;
+        if distance <= half {
+            1.0
+        } else if distance >= range.width {
+            0.0
+        } else {
+            1.0 - (distance - half) / half
+        }
+    }
+
+    // how much vibrance may change a hue, lower around skin tones
+    fn skin_protection(hue: f64) -> f64 {
+        let distance = (hue - 25.0).abs().min(360.0 - (hue - 25.0).abs());
+        1.0 - 0.7 * (-(distance / 20.0).powi(2)).exp()
+    }
+
+    // adjusts a single RGB color
+    pub fn adjust_color(rgb: [u8; 3], adjustments: &ColorAdjustments) -> [u8; 3] {
+        let mut hsl = Hsl::from(&Rgb::from((rgb[0] as f64, rgb[1] as f64, rgb[2] as f64)));
+        let original_hue = hsl.hue();
+        let original_saturation = hsl.saturation();
+        let mut hue = original_hue + adjustments.hue;
+        let mut saturation = hsl.saturation();
+        let mut lightness = hsl.lightness();
+
+        if adjustments.saturation != 0.0 {
+            saturation *= 1.0 + adjustments.saturation / 100.0;
+        }
+
+        if adjustments.vibrance != 0.0 {
+            let amount = adjustments.vibrance / 100.0 * skin_protection(original_hue);
+            if amount > 0.0 {
+                // dull colors gain the most
+                saturation += (100.0 - saturation) * amount * (1.0 - saturation / 100.0);
+            } else {
+                saturation *= 1.0 + amount * (1.0 - saturation / 100.0);
+            }
+        }
+
+        for range in adjustments.ranges.iter() {
+            // nearly neutral colors have no real hue to select them by
+            let weight = range_weight(original_hue, range) * (original_saturation / 25.0).min(1.0);
+            if weight == 0.0 {
+                continue;
+            }
+            hue += range.hue * weight;
+            saturation *= 1.0 + range.saturation / 100.0 * weight;
+            if range.lightness > 0.0 {
+                lightness += (100.0 - lightness) * range.lightness / 100.0 * weight;
+            } else {
+                lightness *= 1.0 + range.lightness / 100.0 * weight;
+            }
+        }
+
+        hsl.set_hue(hue.rem_euclid(360.0));
+        hsl.set_saturation(saturation.clamp(0.0, 100.0));
+        hsl.set_lightness(lightness.clamp(0.0, 100.0));
+        let rgb = Rgb::from(&hsl);
+        [
+            round_u8(rgb.red() as f32),
+            round_u8(rgb.green() as f32),
+            round_u8(rgb.blue() as f32),
+        ]
+    }
+
+    // applies the color adjustments to an RGB image, leaving alpha alone
+    pub fn adjust_colors(img: &Image<u8>, adjustments: &ColorAdjustments) -> Image<u8> {
+        let (width, height, channels, alpha) = img.info().whca();
+        let mut img2 = Image::blank(ImageInfo::new(width, height, channels, alpha));
+        for y in 0..height {
+            for x in 0..width {
+                let mut pixel = img.get_pixel(x, y).to_vec();
+                let rgb = adjust_color([pixel[0], pixel[1], pixel[2]], adjustments);
+                pixel[0..3].copy_from_slice(&rgb);
+                img2.set_pixel(x, y, &pixel);
+            }
+        }
+        img2
+    }
+}
+
+pub use color::*;
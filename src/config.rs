@@ -24,11 +24,85 @@ pub mod config {
         pub fliph: bool,
         pub flipv: bool,
         pub noexif: bool,
+        pub crop: String,
+        pub gravity: String,
+        pub gray_method: String,
+        pub linear: bool,
+        pub mixer: String,
+        pub clip: String,
+        pub linked: bool,
+        pub equalize: bool,
+        pub clahe: String,
+        pub levels: String,
+        pub curves: String,
+        pub gamma: String,
+        pub brightness: String,
+        pub contrast: String,
+        pub lut: String,
+        pub lut_interpolation: String,
+        pub white_balance: String,
+        pub saturation: String,
+        pub vibrance: String,
+        pub hue: String,
+        pub hue_ranges: String,
+        pub sepia: String,
+        pub duotone: String,
+        pub split_tone: String,
+        pub blur: String,
+        pub box_blur: String,
+        pub sharpen: String,
+        pub unsharp: String,
+        pub border_mode: String,
+        pub median: String,
+        pub bilateral: String,
+        pub nlm: String,
+        pub denoise_mode: String,
+        pub edges: String,
+        pub morph: String,
+        pub threshold: String,
+        pub format: String,
+        pub deskew: String,
+        pub perspective: String,
+        pub watermark: String,
+        pub watermark_text: String,
+        pub watermark_opacity: String,
+        pub watermark_scale: String,
+        pub watermark_gravity: String,
+        pub watermark_margin: String,
+        pub watermark_tile: bool,
+        pub watermark_color: String,
+        pub pad: String,
+        pub pad_fill: String,
+        pub border: String,
+        pub vignette: String,
+        pub distortion: String,
+        pub lens_profiles: String,
+        pub chromatic: String,
+        pub defringe: String,
+        pub bracket_gap: String,
+        pub stack_gap: String,
+        pub focal: String,
+        pub columns: String,
+        pub rows: String,
+        pub thumb_size: String,
+        pub spacing: String,
+        pub background: String,
+        pub captions: String,
+        pub delay: String,
+        pub loops: String,
+        pub colors: String,
+        pub dither: String,
+        pub frame_size: String,
     }
 
     lazy_static! {
         pub static ref OPTS: HashMap<&'static str, String> = {
-            let args = parse_cmdline_args().get_matches();
+            // the tests run without a command line of their own
+            let args = if cfg!(test) {
+                parse_cmdline_args().get_matches_from(["rustyimage", "test", "."])
+            } else {
+                parse_cmdline_args().get_matches()
+            };
             let mut opts = HashMap::new();
             if let Some(c) = args.get_one::<String>("COMMAND") {
                 opts.insert("action", c.to_string());
@@ -40,6 +114,12 @@ pub mod config {
                 println!("  - set-date: Sets the EXIF and file date to the date specified with -dt=YYYY-MM-DD");
                 println!("  - print-exit: Prints the EXIF data");
                 println!("  - fix-jpeg-ext: Renames *.JPEG to JPG");
+                println!("  - watermark: Adds a logo or text watermark, the artist by default");
+                println!("  - fuse: Fuses the bracketed exposures in a folder into one image");
+                println!("  - stack: Focus stacks the series of shots in a folder into one image");
+                println!("  - stitch: Stitches the images in a folder, left to right by name, into a panorama");
+                println!("  - contact-sheet: Lays out thumbnails of the images in a folder on pages");
+                println!("  - animate: Makes an animated GIF or WebP of the images in a folder or a HEIC file");
 
                 opts.insert("error", String::from("true"));
             }
@@ -169,6 +249,310 @@ pub mod config {
                 );
             }
 
+            if let Some(c) = args.get_one::<String>("crop") {
+                opts.insert("crop", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("gravity") {
+                opts.insert("gravity", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("gray-method") {
+                opts.insert("gray_method", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<bool>("linear") {
+                opts.insert(
+                    "linear",
+                    if *c {
+                        String::from("true")
+                    } else {
+                        String::from("false")
+                    },
+                );
+            }
+
+            if let Some(c) = args.get_one::<String>("mixer") {
+                opts.insert("mixer", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("clip") {
+                opts.insert("clip", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<bool>("linked") {
+                opts.insert(
+                    "linked",
+                    if *c {
+                        String::from("true")
+                    } else {
+                        String::from("false")
+                    },
+                );
+            }
+
+            if let Some(c) = args.get_one::<bool>("equalize") {
+                opts.insert(
+                    "equalize",
+                    if *c {
+                        String::from("true")
+                    } else {
+                        String::from("false")
+                    },
+                );
+            }
+
+            if let Some(c) = args.get_one::<String>("clahe") {
+                opts.insert("clahe", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("levels") {
+                opts.insert("levels", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("curves") {
+                opts.insert("curves", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("gamma") {
+                opts.insert("gamma", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("brightness") {
+                opts.insert("brightness", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("contrast") {
+                opts.insert("contrast", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("lut") {
+                opts.insert("lut", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("lut-interpolation") {
+                opts.insert("lut_interpolation", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("white-balance") {
+                opts.insert("white_balance", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("saturation") {
+                opts.insert("saturation", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("vibrance") {
+                opts.insert("vibrance", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("hue") {
+                opts.insert("hue", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("hue-range") {
+                opts.insert("hue_ranges", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("sepia") {
+                opts.insert("sepia", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("duotone") {
+                opts.insert("duotone", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("split-tone") {
+                opts.insert("split_tone", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("blur") {
+                opts.insert("blur", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("box-blur") {
+                opts.insert("box_blur", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("sharpen") {
+                opts.insert("sharpen", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("unsharp") {
+                opts.insert("unsharp", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("border-mode") {
+                opts.insert("border_mode", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("median") {
+                opts.insert("median", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("bilateral") {
+                opts.insert("bilateral", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("nlm") {
+                opts.insert("nlm", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("denoise-mode") {
+                opts.insert("denoise_mode", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("edges") {
+                opts.insert("edges", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("morph") {
+                opts.insert("morph", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("threshold") {
+                opts.insert("threshold", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("format") {
+                opts.insert("format", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("deskew") {
+                opts.insert("deskew", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("perspective") {
+                opts.insert("perspective", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("watermark") {
+                opts.insert("watermark", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("watermark-text") {
+                opts.insert("watermark_text", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("watermark-opacity") {
+                opts.insert("watermark_opacity", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("watermark-scale") {
+                opts.insert("watermark_scale", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("watermark-gravity") {
+                opts.insert("watermark_gravity", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("watermark-margin") {
+                opts.insert("watermark_margin", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<bool>("watermark-tile") {
+                opts.insert(
+                    "watermark_tile",
+                    if *c {
+                        String::from("true")
+                    } else {
+                        String::from("false")
+                    },
+                );
+            }
+
+            if let Some(c) = args.get_one::<String>("watermark-color") {
+                opts.insert("watermark_color", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("pad") {
+                opts.insert("pad", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("pad-fill") {
+                opts.insert("pad_fill", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("border") {
+                opts.insert("border", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("vignette") {
+                opts.insert("vignette", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("distortion") {
+                opts.insert("distortion", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("lens-profiles") {
+                opts.insert("lens_profiles", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("chromatic") {
+                opts.insert("chromatic", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("defringe") {
+                opts.insert("defringe", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("bracket-gap") {
+                opts.insert("bracket_gap", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("stack-gap") {
+                opts.insert("stack_gap", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("focal") {
+                opts.insert("focal", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("columns") {
+                opts.insert("columns", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("rows") {
+                opts.insert("rows", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("thumb-size") {
+                opts.insert("thumb_size", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("spacing") {
+                opts.insert("spacing", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("background") {
+                opts.insert("background", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("captions") {
+                opts.insert("captions", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("delay") {
+                opts.insert("delay", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("loops") {
+                opts.insert("loops", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("colors") {
+                opts.insert("colors", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("dither") {
+                opts.insert("dither", c.to_string());
+            }
+
+            if let Some(c) = args.get_one::<String>("frame-size") {
+                opts.insert("frame_size", c.to_string());
+            }
+
             opts
         };
     }
@@ -193,6 +577,75 @@ pub mod config {
             noexif: option("noexif", "false") == "true",
             grayscale: option("grayscale", "false") == "true",
             autocontrast: option("autocontrast", "false") == "true",
+            crop: option("crop", ""),
+            gravity: option("gravity", "center"),
+            gray_method: option("gray_method", "rec601"),
+            linear: option("linear", "false") == "true",
+            mixer: option("mixer", ""),
+            clip: option("clip", "0.1"),
+            linked: option("linked", "false") == "true",
+            equalize: option("equalize", "false") == "true",
+            clahe: option("clahe", ""),
+            levels: option("levels", ""),
+            curves: option("curves", ""),
+            gamma: option("gamma", ""),
+            brightness: option("brightness", ""),
+            contrast: option("contrast", ""),
+            lut: option("lut", ""),
+            lut_interpolation: option("lut_interpolation", "tetrahedral"),
+            white_balance: option("white_balance", ""),
+            saturation: option("saturation", ""),
+            vibrance: option("vibrance", ""),
+            hue: option("hue", ""),
+            hue_ranges: option("hue_ranges", ""),
+            sepia: option("sepia", ""),
+            duotone: option("duotone", ""),
+            split_tone: option("split_tone", ""),
+            blur: option("blur", ""),
+            box_blur: option("box_blur", ""),
+            sharpen: option("sharpen", ""),
+            unsharp: option("unsharp", ""),
+            border_mode: option("border_mode", "reflect"),
+            median: option("median", ""),
+            bilateral: option("bilateral", ""),
+            nlm: option("nlm", ""),
+            denoise_mode: option("denoise_mode", "all"),
+            edges: option("edges", ""),
+            morph: option("morph", ""),
+            threshold: option("threshold", ""),
+            format: option("format", ""),
+            deskew: option("deskew", ""),
+            perspective: option("perspective", ""),
+            watermark: option("watermark", ""),
+            watermark_text: option("watermark_text", ""),
+            watermark_opacity: option("watermark_opacity", "50"),
+            watermark_scale: option("watermark_scale", "20"),
+            watermark_gravity: option("watermark_gravity", "southeast"),
+            watermark_margin: option("watermark_margin", "2"),
+            watermark_tile: option("watermark_tile", "false") == "true",
+            watermark_color: option("watermark_color", "white"),
+            pad: option("pad", ""),
+            pad_fill: option("pad_fill", "white"),
+            border: option("border", ""),
+            vignette: option("vignette", ""),
+            distortion: option("distortion", ""),
+            lens_profiles: option("lens_profiles", ""),
+            chromatic: option("chromatic", ""),
+            defringe: option("defringe", ""),
+            bracket_gap: option("bracket_gap", "2"),
+            stack_gap: option("stack_gap", "10"),
+            focal: option("focal", ""),
+            columns: option("columns", "5"),
+            rows: option("rows", "6"),
+            thumb_size: option("thumb_size", "240"),
+            spacing: option("spacing", "16"),
+            background: option("background", "white"),
+            captions: option("captions", "filename,date"),
+            delay: option("delay", "500"),
+            loops: option("loops", "0"),
+            colors: option("colors", "256"),
+            dither: option("dither", "floyd-steinberg"),
+            frame_size: option("frame_size", ""),
         };
         return opts;
     }
@@ -209,11 +662,80 @@ pub mod config {
                 arg!(-o --overwrite "overwrite the original file"),
                 arg!(-f --force "force grayscale action"),
                 arg!(-g --grayscale "convert to grayscale"),
+                arg!(--"gray-method" <METHOD> "grayscale formula: rec601, rec709, average, lightness, red, green, blue or R,G,B weights"),
+                arg!(--linear "compute grayscale in linear light"),
+                arg!(--mixer <MIXER> "channel mixer preset (red, orange, yellow, green, blue, infrared, neutral) or matrix rows R,G,B[,offset] separated by ;"),
+                arg!(--sepia [STRENGTH] "sepia toning, strength from 0 to 100").require_equals(true).default_missing_value("100"),
+                arg!(--duotone <COLORS> "duotone as SHADOW,HIGHLIGHT colors (hex, CSS names or rgb())"),
+                arg!(--"split-tone" <COLORS> "split toning as SHADOW,HIGHLIGHT[,BALANCE[,STRENGTH]]"),
                 arg!(-c --autocontrast "apply auto-contrast to the image"),
+                arg!(--clip <PERCENT> "autocontrast: percent of darkest and brightest pixels to ignore, as LOW[,HIGH]"),
+                arg!(--linked "autocontrast: stretch the luminance only, keeping the color balance"),
+                arg!(--equalize "equalize the histogram of the luminance"),
+                arg!(--clahe <TILES> "adaptive equalization of the luminance as COLSxROWS[,CLIP], e.g. 8x8,2"),
+                arg!(--"white-balance" <MODE> "white balance: gray-world, white-patch, percentile[:P], temp:KELVIN[,TINT] or pixel:X,Y"),
+                arg!(--levels <LEVELS> "input levels as BLACK,WHITE[,GAMMA], or R;G;B"),
+                arg!(--curves <POINTS> "tone curve through X:Y control points separated by ',', or R;G;B"),
+                arg!(--gamma <GAMMA> "gamma correction, above 1 brightens"),
+                arg!(--brightness <BRIGHTNESS> "brightness from -255 to 255").allow_negative_numbers(true),
+                arg!(--contrast <CONTRAST> "contrast from -100 to 100").allow_negative_numbers(true),
+                arg!(--saturation <PERCENT> "saturation change from -100 to 100").allow_negative_numbers(true),
+                arg!(--vibrance <PERCENT> "saturation change protecting skin tones and saturated colors, from -100 to 100").allow_negative_numbers(true),
+                arg!(--hue <DEGREES> "rotate the hue").allow_negative_numbers(true),
+                arg!(--"hue-range" <RANGES> "adjust hue ranges as RANGE:HUE[,SAT[,LIGHT]] separated by ;, RANGE is reds, greens, ... or CENTER/WIDTH"),
+                arg!(--lut <FILE> "apply a .cube 3D LUT or .acv curves file"),
+                arg!(--"lut-interpolation" <METHOD> "3D LUT interpolation: tetrahedral or trilinear"),
+                arg!(--median <RADIUS> "median noise filter"),
+                arg!(--bilateral <SIGMAS> "bilateral noise filter as SPACE_SIGMA,RANGE_SIGMA, e.g. 3,20"),
+                arg!(--nlm <SETTINGS> "non-local means noise filter as STRENGTH[,PATCH_RADIUS,SEARCH_RADIUS], e.g. 10,1,5; the time grows with the square of SEARCH_RADIUS"),
+                arg!(--"denoise-mode" <MODE> "channels to denoise: all, luma or chroma"),
+                arg!(--blur <SIGMA> "gaussian blur"),
+                arg!(--"box-blur" <RADIUS> "box blur"),
+                arg!(--sharpen <AMOUNT> "sharpen with a 3x3 kernel, 1 is the classic strength"),
+                arg!(--unsharp <SETTINGS> "unsharp mask as AMOUNT[,RADIUS[,THRESHOLD]], e.g. 80,1.2,3"),
+                arg!(--"border-mode" <MODE> "pixels outside the image for filters: reflect, clamp or wrap"),
+                arg!(--edges <DETECTOR> "edge detection with a grayscale result: sobel, scharr or canny[,LOW,HIGH]"),
+                arg!(--morph <OPERATIONS> "erode, dilate, open or close the grayscale result as OP[,RADIUS[,SHAPE]] separated by ;, SHAPE is square or disk"),
+                arg!(--threshold <METHOD> "black and white result: a level (0-255), otsu, sauvola[,WINDOW[,K]] or mean[,WINDOW[,OFFSET]]"),
+                arg!(--format <EXT> "output file format, e.g. png or tif; black and white images are saved with 1 bit per pixel; gif or webp for animate"),
+                arg!(--deskew [METHOD] "straighten the image as METHOD[,MAX_ANGLE], METHOD is projection or hough").require_equals(true).default_missing_value("projection"),
+                arg!(--perspective <CORNERS> "correct the perspective of a document: auto or X1,Y1,X2,Y2,X3,Y3,X4,Y4 from the top left, clockwise"),
+                arg!(--watermark <FILE> "watermark with a logo image, PNG with alpha"),
+                arg!(--"watermark-text" <TEXT> "watermark with a text, the artist with the watermark action"),
+                arg!(--"watermark-opacity" <PERCENT> "watermark opacity from 0 to 100"),
+                arg!(--"watermark-scale" <PERCENT> "watermark width in percent of the image width"),
+                arg!(--"watermark-gravity" <GRAVITY> "watermark position: southeast, center, north, ..."),
+                arg!(--"watermark-margin" <PERCENT> "watermark distance from the edges in percent of the image width"),
+                arg!(--"watermark-tile" "repeat the watermark over the whole image"),
+                arg!(--"watermark-color" <COLOR> "text watermark color, a name, #RRGGBB or rgb(R,G,B)"),
+                arg!(--pad <SIZE> "pad to a size WxH (scaling larger images down) or an aspect ratio W:H, placed by --gravity"),
+                arg!(--"pad-fill" <FILL> "padding color, a name, #RRGGBB or rgb(R,G,B), or blur for a blurred copy of the image"),
+                arg!(--border <BORDER> "add a border as WIDTH[,COLOR[,RADIUS]], a radius rounds the corners of the image"),
+                arg!(--vignette <VIGNETTE> "darken the corners as STRENGTH[,RADIUS] in percent, a negative strength removes a vignette").allow_hyphen_values(true),
+                arg!(--distortion <K> "correct lens distortion as K1[,K2], negative for barrel and positive for pincushion").allow_hyphen_values(true),
+                arg!(--"lens-profiles" <FILE> "JSON lens profiles keyed by the EXIF LensModel with k1, k2, vignette and vignette_radius"),
+                arg!(--chromatic <SCALES> "correct lateral chromatic aberration, auto or the RED,BLUE scales relative to green"),
+                arg!(--defringe [AMOUNT] "reduce purple fringes along high contrast edges, 0 to 100 percent").require_equals(true).default_missing_value("50"),
+                arg!(--"bracket-gap" <SECONDS> "fuse: the longest pause between the shots of a bracket without a burst identifier"),
+                arg!(--"stack-gap" <SECONDS> "stack: the longest pause between the shots of a focus stack without a burst identifier"),
+                arg!(--focal <MM> "stitch: the 35mm equivalent focal length, when the EXIF data has none"),
+                arg!(--columns <N> "contact-sheet: thumbnails per row, up to 100"),
+                arg!(--rows <N> "contact-sheet: rows per page, up to 100"),
+                arg!(--"thumb-size" <PIXELS> "contact-sheet: the square each thumbnail fits in, up to 4000"),
+                arg!(--spacing <PIXELS> "contact-sheet: the space around the thumbnails, up to 1000"),
+                arg!(--background <COLOR> "contact-sheet: the page color; animate: the color around frames of another shape"),
+                arg!(--captions <LIST> "contact-sheet: filename and/or date below each thumbnail, or none"),
+                arg!(--delay <MS> "animate: frame delays in milliseconds, the last one is used for the remaining frames"),
+                arg!(--loops <N> "animate: how many times the animation plays, 0 for forever"),
+                arg!(--colors <N> "animate: the GIF palette size, 2 to 256"),
+                arg!(--dither <METHOD> "animate: GIF dithering, floyd-steinberg or none"),
+                arg!(--"frame-size" <SIZE> "animate: the frames as WxH or their longest side, 640 by default"),
                 arg!(-i --invert "invert image"),
                 arg!(-x --fliph "flip image horizontally"),
                 arg!(-y --flipv "flip image vertically"),
                 arg!(-n --noexif "do not preserve the EXIF data"),
+                arg!(--crop <CROP> "crop to WxH+X+Y, an aspect ratio W:H[,gravity], smart[,W:H] or trim[,tolerance]"),
+                arg!(--gravity <GRAVITY> "anchor for aspect ratio crops and padding: center, north, southwest, ..."),
                 arg!(-t --date <DATE> "date to set to the file with set-date action"),
                 arg!(-a --artist <ARTIST> "artist to set to the file with set-artist action"),
                 arg!(-v --verbose "turns on verbose mode"),
@@ -0,0 +1,285 @@
+pub mod convolve {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    pub use crate::config::*;
+
+    use crate::grayscale::*;
+
+    // how pixels outside the image are taken
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum BorderMode {
+        // repeat the edge pixel
+        Clamp,
+        // mirror around the edge pixel
+        Reflect,
+        // continue from the opposite edge
+        Wrap,
+    }
+
+    // a 2D kernel with odd width and height, stored row by row
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Kernel {
+        pub width: usize,
+        pub height: usize,
+        pub data: Vec<f32>,
+    }
+
+    pub fn parse_border_mode(name: &str) -> Option<BorderMode> {
+        match name.trim().to_lowercase().as_str() {
+            "clamp" | "edge" => Some(BorderMode::Clamp),
+            "" | "reflect" | "mirror" => Some(BorderMode::Reflect),
+            "wrap" | "tile" => Some(BorderMode::Wrap),
+            _ => None,
+        }
+    }
+
+    // maps a possibly outside coordinate into 0..n
+    pub fn border_index(i: i64, n: i64, border: BorderMode) -> usize {
+        if i >= 0 && i < n {
+            return i as usize;
+        }
+        if n == 1 {
+            return 0;
+        }
+        match border {
+            BorderMode::Clamp => i.clamp(0, n - 1) as usize,
+            BorderMode::Wrap => i.rem_euclid(n) as usize,
+            BorderMode::Reflect => {
+                let period = 2 * (n - 1);
+                let m = i.rem_euclid(period);
+                (if m < n { m } else { period - m }) as usize
+            }
+        }
+    }
+
+    // splits an image into one float plane per channel
+    pub fn to_planes(img: &Image<u8>) -> Vec<Vec<f32>> {
+        let channels = img.info().channels as usize;
+        let mut planes = vec![Vec::with_capacity(img.data().len() / channels); channels];
+        for pixel in img.data().chunks(channels) {
+            for (c, &value) in pixel.iter().enumerate() {
+                planes[c].push(value as f32);
+            }
+        }
+        planes
+    }
+
+    // joins float planes back into an image, rounding and clamping
+    pub fn from_planes(planes: &[Vec<f32>], width: u32, height: u32, alpha: bool) -> Image<u8> {
+        let channels = planes.len();
+        let mut data = Vec::with_capacity(planes[0].len() * channels);
+        for i in 0..planes[0].len() {
+            for plane in planes.iter() {
+                data.push(round_u8(plane[i]));
+            }
+        }
+        Image::from_vec(width, height, channels as u8, alpha, data)
+    }
+
+    // convolves a plane with a horizontal and then a vertical 1D kernel
+    pub fn convolve_plane_separable(
+        plane: &[f32],
+        width: usize,
+        height: usize,
+        kx: &[f32],
+        ky: &[f32],
+        border: BorderMode,
+    ) -> Vec<f32> {
+        let rx = (kx.len() / 2) as i64;
+        let ry = (ky.len() / 2) as i64;
+        let mut tmp = vec![0f32; plane.len()];
+        for y in 0..height {
+            let row = &plane[y * width..(y + 1) * width];
+            for x in 0..width {
+                let mut sum = 0.0;
+                for (k, weight) in kx.iter().enumerate() {
+                    let xi = border_index(x as i64 + k as i64 - rx, width as i64, border);
+                    sum += row[xi] * weight;
+                }
+                tmp[y * width + x] = sum;
+            }
+        }
+
+        let mut out = vec![0f32; plane.len()];
+        for y in 0..height {
+            for (k, weight) in ky.iter().enumerate() {
+                let yi = border_index(y as i64 + k as i64 - ry, height as i64, border);
+                let src = &tmp[yi * width..(yi + 1) * width];
+                let dst = &mut out[y * width..(y + 1) * width];
+                for (d, s) in dst.iter_mut().zip(src.iter()) {
+                    *d += s * weight;
+                }
+            }
+        }
+        out
+    }
+
+    // convolves a plane with a 2D kernel
+    pub fn convolve_plane(plane: &[f32], width: usize, height: usize, kernel: &Kernel, border: BorderMode) -> Vec<f32> {
+        let rx = (kernel.width / 2) as i64;
+        let ry = (kernel.height / 2) as i64;
+        let mut out = vec![0f32; plane.len()];
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = 0.0;
+                for ky in 0..kernel.height {
+                    let yi = border_index(y as i64 + ky as i64 - ry, height as i64, border);
+                    for kx in 0..kernel.width {
+                        let xi = border_index(x as i64 + kx as i64 - rx, width as i64, border);
+                        sum += plane[yi * width + xi] * kernel.data[ky * kernel.width + kx];
+                    }
+                }
+                out[y * width + x] = sum;
+            }
+        }
+        out
+    }
+
+    // convolves every channel of an image with separable kernels
+    pub fn convolve_separable(img: &Image<u8>, kx: &[f32], ky: &[f32], border: BorderMode) -> Image<u8> {
+        let (width, height, _channels, alpha) = img.info().whca();
+        let planes: Vec<Vec<f32>> = to_planes(img)
+            .iter()
+            .map(|p| convolve_plane_separable(p, width as usize, height as usize, kx, ky, border))
+            .collect();
+        from_planes(&planes, width, height, alpha)
+    }
+
+    // convolves every channel of an image with a 2D kernel
+    pub fn convolve(img: &Image<u8>, kernel: &Kernel, border: BorderMode) -> Image<u8> {
+        let (width, height, _channels, alpha) = img.info().whca();
+        let planes: Vec<Vec<f32>> = to_planes(img)
+            .iter()
+            .map(|p| convolve_plane(p, width as usize, height as usize, kernel, border))
+            .collect();
+        from_planes(&planes, width, height, alpha)
+    }
+
+    // a normalized 1D gaussian reaching out to 3 sigma
+    pub fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+        let radius = (sigma * 3.0).ceil().max(1.0) as i32;
+        let mut kernel: Vec<f32> = (-radius..=radius)
+            .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+            .collect();
+        let sum: f32 = kernel.iter().sum();
+        for weight in kernel.iter_mut() {
+            *weight /= sum;
+        }
+        kernel
+    }
+
+    // a normalized 1D box of 2 * radius + 1 pixels
+    pub fn box_kernel(radius: usize) -> Vec<f32> {
+        let size = 2 * radius + 1;
+        vec![1.0 / size as f32; size]
+    }
+
+    pub fn gaussian_blur(img: &Image<u8>, sigma: f32, border: BorderMode) -> Image<u8> {
+        let kernel = gaussian_kernel(sigma);
+        convolve_separable(img, &kernel, &kernel, border)
+    }
+
+    pub fn box_blur(img: &Image<u8>, radius: usize, border: BorderMode) -> Image<u8> {
+        let kernel = box_kernel(radius);
+        convolve_separable(img, &kernel, &kernel, border)
+    }
+
+    // sharpens with a 3x3 laplacian kernel, amount 1.0 is the classic
+    // 0,-1,0 / -1,5,-1 / 0,-1,0 kernel
+    pub fn sharpen(img: &Image<u8>, amount: f32, border: BorderMode) -> Image<u8> {
+        let a = amount;
+        let kernel = Kernel {
+            width: 3,
+            height: 3,
+            data: vec![0.0, -a, 0.0, -a, 1.0 + 4.0 * a, -a, 0.0, -a, 0.0],
+        };
+        convolve(img, &kernel, border)
+    }
+
+    // adds amount percent of the difference to a gaussian blur of the given
+    // radius; differences up to threshold levels are left alone so noise and
+    // smooth areas are not sharpened
+    pub fn unsharp_mask(img: &Image<u8>, amount: f32, radius: f32, threshold: f32, border: BorderMode) -> Image<u8> {
+        let blurred = gaussian_blur(img, radius, border);
+        let colors = img.info().channels_non_alpha() as usize;
+        let channels = img.info().channels as usize;
+        let (width, height, _channels, alpha) = img.info().whca();
+
+        let mut data = img.data().to_vec();
+        for (i, value) in data.iter_mut().enumerate() {
+            if i % channels >= colors {
+                continue;
+            }
+            let diff = *value as f32 - blurred.data()[i] as f32;
+            if diff.abs() >= threshold {
+                *value = round_u8(*value as f32 + diff * amount / 100.0);
+            }
+        }
+        Image::from_vec(width, height, channels as u8, alpha, data)
+    }
+
+    // parses unsharp mask settings as AMOUNT[,RADIUS[,THRESHOLD]],
+    // e.g. 80,1.2,3 (defaults: radius 1, threshold 0)
+    pub fn parse_unsharp(spec: &str) -> Option<(f32, f32, f32)> {
+        let values: Vec<f32> = spec
+            .split(',')
+            .map(|v| v.trim().parse::<f32>())
+            .collect::<Result<_, _>>()
+            .ok()?;
+        if values.is_empty() || values.len() > 3 {
+            return None;
+        }
+        let amount = values[0];
+        let radius = *values.get(1).unwrap_or(&1.0);
+        let threshold = *values.get(2).unwrap_or(&0.0);
+        if amount < 0.0 || radius <= 0.0 || threshold < 0.0 {
+            return None;
+        }
+        Some((amount, radius, threshold))
+    }
+
+    // runs the blur and sharpen options, blurring first
+    pub fn apply_filters(img: Image<u8>, opts: &ConfigOptions) -> Image<u8> {
+        let border = match parse_border_mode(&opts.border_mode) {
+            Some(b) => b,
+            None => {
+                println!("Invalid border mode {}, using reflect", opts.border_mode);
+                BorderMode::Reflect
+            }
+        };
+        let mut img = img;
+        let number = |spec: &str, name: &str| -> Option<f32> {
+            if spec.is_empty() {
+                return None;
+            }
+            match spec.trim().parse::<f32>() {
+                Ok(v) if v > 0.0 => Some(v),
+                _ => {
+                    println!("Invalid {} {}", name, spec);
+                    None
+                }
+            }
+        };
+
+        if let Some(sigma) = number(&opts.blur, "blur") {
+            img = gaussian_blur(&img, sigma, border);
+        }
+        if let Some(radius) = number(&opts.box_blur, "box blur") {
+            img = box_blur(&img, radius.round() as usize, border);
+        }
+        if let Some(amount) = number(&opts.sharpen, "sharpen") {
+            img = sharpen(&img, amount, border);
+        }
+        if !opts.unsharp.is_empty() {
+            match parse_unsharp(&opts.unsharp) {
+                Some((amount, radius, threshold)) => {
+                    img = unsharp_mask(&img, amount, radius, threshold, border);
+                }
+                None => println!("Invalid unsharp mask {}", opts.unsharp),
+            }
+        }
+        img
+    }
+}
+
+pub use convolve::*;
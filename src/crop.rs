@@ -0,0 +1,338 @@
+pub mod crop {
+
+    use imgproc_rs::image::{BaseImage, Image, ImageInfo};
+    pub use crate::config::*;
+
+    use crate::log;
+
+    // where to anchor a region inside the image
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Gravity {
+        Center,
+        North,
+        South,
+        East,
+        West,
+        NorthEast,
+        NorthWest,
+        SouthEast,
+        SouthWest,
+    }
+
+    // the supported crop modes
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum CropMode {
+        // WxH+X+Y pixel rectangle
+        Rect(u32, u32, u32, u32),
+        // W:H aspect ratio anchored by gravity
+        Aspect(u32, u32, Gravity),
+        // remove uniform borders, with the allowed color deviation
+        Trim(u8),
+        // W:H aspect ratio placed over the most detailed area
+        Smart(u32, u32),
+    }
+
+    // parses a gravity name (center, north, northeast, ...)
+    pub fn parse_gravity(name: &str) -> Option<Gravity> {
+        match name.trim().to_lowercase().replace(['-', '_'], "").as_str() {
+            "" | "center" | "centre" | "c" => Some(Gravity::Center),
+            "north" | "n" | "top" => Some(Gravity::North),
+            "south" | "s" | "bottom" => Some(Gravity::South),
+            "east" | "e" | "right" => Some(Gravity::East),
+            "west" | "w" | "left" => Some(Gravity::West),
+            "northeast" | "ne" => Some(Gravity::NorthEast),
+            "northwest" | "nw" => Some(Gravity::NorthWest),
+            "southeast" | "se" => Some(Gravity::SouthEast),
+            "southwest" | "sw" => Some(Gravity::SouthWest),
+            _ => None,
+        }
+    }
+
+    // parses a crop specification:
+    //   WxH+X+Y    pixel rectangle (offset is optional)
+    //   W:H[,G]    aspect ratio with optional gravity, otherwise the passed one is used
+    //   trim[,T]   trim uniform borders with optional tolerance (default 24)
+    //   smart[,W:H] aspect ratio (default 1:1) placed over the most detailed area
+    pub fn parse_crop(spec: &str, gravity: &str) -> Option<CropMode> {
+        let spec = spec.trim().to_lowercase();
+        let mut parts = spec.splitn(2, ',');
+        let mode = parts.next().unwrap_or("");
+        let arg = parts.next();
+
+        if mode == "trim" {
+            let tolerance = match arg {
+                Some(t) => t.trim().parse::<u8>().ok()?,
+                None => 24,
+            };
+            return Some(CropMode::Trim(tolerance));
+        }
+
+        if mode == "smart" {
+            let (w, h) = arg.unwrap_or("1:1").split_once(':')?;
+            let w = w.trim().parse::<u32>().ok()?;
+            let h = h.trim().parse::<u32>().ok()?;
+            if w == 0 || h == 0 {
+                return None;
+            }
+            return Some(CropMode::Smart(w, h));
+        }
+
+        if mode.contains(':') {
+            let (w, h) = mode.split_once(':')?;
+            let w = w.trim().parse::<u32>().ok()?;
+            let h = h.trim().parse::<u32>().ok()?;
+            if w == 0 || h == 0 {
+                return None;
+            }
+            let gravity = parse_gravity(arg.unwrap_or(gravity))?;
+            return Some(CropMode::Aspect(w, h, gravity));
+        }
+
+        // WxH+X+Y
+        let (size, offset) = match mode.find('+') {
+            Some(i) => (&mode[0..i], &mode[i + 1..]),
+            None => (mode, "0+0"),
+        };
+        let (w, h) = size.split_once('x')?;
+        let (x, y) = offset.split_once('+')?;
+        let w = w.trim().parse::<u32>().ok()?;
+        let h = h.trim().parse::<u32>().ok()?;
+        if w == 0 || h == 0 {
+            return None;
+        }
+        Some(CropMode::Rect(
+            x.trim().parse::<u32>().ok()?,
+            y.trim().parse::<u32>().ok()?,
+            w,
+            h,
+        ))
+    }
+
+    // crops the image according to the crop mode; None if the rectangle
+    // starts outside the image
+    pub fn crop_image(img: &Image<u8>, mode: CropMode) -> Option<Image<u8>> {
+        let (width, height) = img.info().wh();
+        let (x, y, w, h) = match mode {
+            CropMode::Rect(x, y, w, h) => {
+                if x >= width || y >= height {
+                    println!("Crop offset +{}+{} is outside the {}x{} image", x, y, width, height);
+                    return None;
+                }
+                // keep the rectangle inside the image
+                (x, y, w.min(width - x), h.min(height - y))
+            }
+            CropMode::Aspect(aw, ah, gravity) => aspect_rect(width, height, aw, ah, gravity),
+            CropMode::Trim(tolerance) => trim_rect(img, tolerance),
+            CropMode::Smart(aw, ah) => smart_rect(img, aw, ah),
+        };
+        log!(" > Cropping to {}x{}+{}+{}", w, h, x, y);
+        Some(crop_rect(img, x, y, w, h))
+    }
+
+    // copies the rectangle out of the image
+    pub fn crop_rect(img: &Image<u8>, x: u32, y: u32, w: u32, h: u32) -> Image<u8> {
+        let (_width, _height, channels, alpha) = img.info().whca();
+        let mut img2 = Image::blank(ImageInfo::new(w, h, channels, alpha));
+        for yi in 0..h {
+            for xi in 0..w {
+                img2.set_pixel(xi, yi, img.get_pixel(x + xi, y + yi));
+            }
+        }
+        img2
+    }
+
+    // places a w x h box inside the image according to the gravity
+    pub fn gravity_offset(width: u32, height: u32, w: u32, h: u32, gravity: Gravity) -> (u32, u32) {
+        let dx = width.saturating_sub(w);
+        let dy = height.saturating_sub(h);
+        let x = match gravity {
+            Gravity::West | Gravity::NorthWest | Gravity::SouthWest => 0,
+            Gravity::East | Gravity::NorthEast | Gravity::SouthEast => dx,
+            _ => dx / 2,
+        };
+        let y = match gravity {
+            Gravity::North | Gravity::NorthWest | Gravity::NorthEast => 0,
+            Gravity::South | Gravity::SouthWest | Gravity::SouthEast => dy,
+            _ => dy / 2,
+        };
+        (x, y)
+    }
+
+    // the largest rectangle with the given aspect ratio that fits in the image
+    pub fn aspect_rect(width: u32, height: u32, aw: u32, ah: u32, gravity: Gravity) -> (u32, u32, u32, u32) {
+        let (w, h) = aspect_size(width, height, aw, ah);
+        let (x, y) = gravity_offset(width, height, w, h, gravity);
+        (x, y, w, h)
+    }
+
+    // the size of the largest box with the given aspect ratio that fits in the image
+    pub fn aspect_size(width: u32, height: u32, aw: u32, ah: u32) -> (u32, u32) {
+        let (w, h);
+        if width as u64 * ah as u64 > height as u64 * aw as u64 {
+            // image is wider than the ratio
+            h = height;
+            w = ((height as u64 * aw as u64) / ah as u64).max(1) as u32;
+        } else {
+            w = width;
+            h = ((width as u64 * ah as u64) / aw as u64).max(1) as u32;
+        }
+        (w, h)
+    }
+
+    // finds the window with the given aspect ratio holding the most edge energy
+    // the window is as large as possible, so only one axis has to be searched
+    pub fn smart_rect(img: &Image<u8>, aw: u32, ah: u32) -> (u32, u32, u32, u32) {
+        let (width, height) = img.info().wh();
+        let (w, h) = aspect_size(width, height, aw, ah);
+        let energy = edge_energy_table(img);
+        let stride = width as usize + 1;
+        let sum = |x: u32, y: u32| -> u64 {
+            let (x0, y0, x1, y1) = (x as usize, y as usize, (x + w) as usize, (y + h) as usize);
+            energy[y1 * stride + x1] + energy[y0 * stride + x0]
+                - energy[y0 * stride + x1]
+                - energy[y1 * stride + x0]
+        };
+
+        let (mut best_x, mut best_y) = gravity_offset(width, height, w, h, Gravity::Center);
+        let mut best = sum(best_x, best_y);
+        for y in 0..=(height - h) {
+            for x in 0..=(width - w) {
+                let score = sum(x, y);
+                if score > best {
+                    best = score;
+                    best_x = x;
+                    best_y = y;
+                }
+            }
+        }
+        log!(" > Smart crop picked {}x{}+{}+{} (energy {})", w, h, best_x, best_y, best);
+        (best_x, best_y, w, h)
+    }
+
+    // builds a summed area table of the luma gradient magnitude,
+    // (width + 1) x (height + 1) entries with a zero first row and column
+    fn edge_energy_table(img: &Image<u8>) -> Vec<u64> {
+        let (width, height) = img.info().wh();
+        let luma = |x: u32, y: u32| -> i32 {
+            let pixel = img.get_pixel(x, y);
+            if pixel.len() < 3 {
+                return pixel[0] as i32;
+            }
+            (pixel[0] as i32 * 299 + pixel[1] as i32 * 587 + pixel[2] as i32 * 114) / 1000
+        };
+
+        let stride = width as usize + 1;
+        let mut table = vec![0u64; stride * (height as usize + 1)];
+        for y in 0..height {
+            let mut row = 0u64;
+            for x in 0..width {
+                let dx = luma((x + 1).min(width - 1), y) - luma(x.saturating_sub(1), y);
+                let dy = luma(x, (y + 1).min(height - 1)) - luma(x, y.saturating_sub(1));
+                row += (dx.abs() + dy.abs()) as u64;
+                let i = (y as usize + 1) * stride + x as usize + 1;
+                table[i] = table[i - stride] + row;
+            }
+        }
+        table
+    }
+
+    // finds the rectangle left after removing uniform borders
+    // the border color is taken from the top left corner; a row or column
+    // still counts as border if 99.5% of its pixels are within the tolerance,
+    // so dust specks on scanner margins do not stop the trimming
+    pub fn trim_rect(img: &Image<u8>, tolerance: u8) -> (u32, u32, u32, u32) {
+        let (width, height) = img.info().wh();
+        let border = img.get_pixel(0, 0).to_vec();
+
+        let matches = |x: u32, y: u32| -> bool {
+            let pixel = img.get_pixel(x, y);
+            pixel
+                .iter()
+                .zip(border.iter())
+                .all(|(&p, &b)| (p as i16 - b as i16).abs() <= tolerance as i16)
+        };
+        let is_border_row = |y: u32, x0: u32, x1: u32| -> bool {
+            let total = x1 - x0;
+            let hits = (x0..x1).filter(|&x| matches(x, y)).count() as u32;
+            hits * 1000 >= total * 995
+        };
+        let is_border_col = |x: u32, y0: u32, y1: u32| -> bool {
+            let total = y1 - y0;
+            let hits = (y0..y1).filter(|&y| matches(x, y)).count() as u32;
+            hits * 1000 >= total * 995
+        };
+
+        let mut top = 0;
+        while top < height - 1 && is_border_row(top, 0, width) {
+            top += 1;
+        }
+        let mut bottom = height;
+        while bottom > top + 1 && is_border_row(bottom - 1, 0, width) {
+            bottom -= 1;
+        }
+        let mut left = 0;
+        while left < width - 1 && is_border_col(left, top, bottom) {
+            left += 1;
+        }
+        let mut right = width;
+        while right > left + 1 && is_border_col(right - 1, top, bottom) {
+            right -= 1;
+        }
+
+        (left, top, right - left, bottom - top)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn pixel_rectangles() {
+            assert_eq!(parse_crop("640x480+10+20", "center"), Some(CropMode::Rect(10, 20, 640, 480)));
+            assert_eq!(parse_crop(" 640X480 ", "center"), Some(CropMode::Rect(0, 0, 640, 480)));
+            assert_eq!(parse_crop("640x480+10", "center"), None);
+            assert_eq!(parse_crop("640x480+10+", "center"), None);
+            assert_eq!(parse_crop("0x480", "center"), None);
+            assert_eq!(parse_crop("640x0+1+1", "center"), None);
+            assert_eq!(parse_crop("640", "center"), None);
+            assert_eq!(parse_crop("-640x480", "center"), None);
+
+            // a rectangle running past the edge is cut at the edge, one
+            // starting outside the image is rejected
+            let img = Image::blank(ImageInfo::new(40, 30, 3, false));
+            let size = |mode| crop_image(&img, mode).map(|c| c.info().wh());
+            assert_eq!(size(CropMode::Rect(10, 5, 20, 10)), Some((20, 10)));
+            assert_eq!(size(CropMode::Rect(30, 20, 100, 100)), Some((10, 10)));
+            assert_eq!(size(CropMode::Rect(39, 29, 5, 5)), Some((1, 1)));
+            assert_eq!(size(CropMode::Rect(40, 0, 5, 5)), None);
+            assert_eq!(size(CropMode::Rect(0, 30, 5, 5)), None);
+            assert_eq!(size(CropMode::Rect(5000, 0, 100, 100)), None);
+        }
+
+        #[test]
+        fn aspect_ratios() {
+            assert_eq!(parse_crop("16:9", "center"), Some(CropMode::Aspect(16, 9, Gravity::Center)));
+            // the gravity in the spec wins over the passed one
+            assert_eq!(parse_crop("16:9", "north"), Some(CropMode::Aspect(16, 9, Gravity::North)));
+            assert_eq!(parse_crop("1:1,southeast", "north"), Some(CropMode::Aspect(1, 1, Gravity::SouthEast)));
+            assert_eq!(parse_crop("1:1,upwards", "center"), None);
+            assert_eq!(parse_crop("0:1", "center"), None);
+            assert_eq!(parse_crop("4:", "center"), None);
+        }
+
+        #[test]
+        fn trim_smart_and_garbage() {
+            assert_eq!(parse_crop("trim", "center"), Some(CropMode::Trim(24)));
+            assert_eq!(parse_crop("trim,5", "center"), Some(CropMode::Trim(5)));
+            assert_eq!(parse_crop("trim,300", "center"), None);
+            assert_eq!(parse_crop("smart", "center"), Some(CropMode::Smart(1, 1)));
+            assert_eq!(parse_crop("smart,3:2", "center"), Some(CropMode::Smart(3, 2)));
+            assert_eq!(parse_crop("smart,3:0", "center"), None);
+            assert_eq!(parse_crop("", "center"), None);
+            assert_eq!(parse_crop("banana", "center"), None);
+            assert_eq!(parse_crop("axb+c+d", "center"), None);
+        }
+    }
+}
+
+pub use crop::*;
@@ -0,0 +1,364 @@
+pub mod denoise {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    pub use crate::config::*;
+
+    use crate::convolve::*;
+
+    // which part of a color image is denoised
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum DenoiseMode {
+        All,
+        // only the luma, keeping the colors
+        Luma,
+        // only the chroma, keeping the detail
+        Chroma,
+    }
+
+    pub fn parse_denoise_mode(name: &str) -> Option<DenoiseMode> {
+        match name.trim().to_lowercase().as_str() {
+            "" | "all" | "rgb" => Some(DenoiseMode::All),
+            "luma" | "luminance" => Some(DenoiseMode::Luma),
+            "chroma" | "color" => Some(DenoiseMode::Chroma),
+            _ => None,
+        }
+    }
+
+    // converts RGB planes to full range BT.601 YCbCr planes in place
+    pub fn rgb_to_ycbcr_planes(planes: &mut [Vec<f32>]) {
+        for i in 0..planes[0].len() {
+            let (r, g, b) = (planes[0][i], planes[1][i], planes[2][i]);
+            planes[0][i] = 0.299 * r + 0.587 * g + 0.114 * b;
+            planes[1][i] = 128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b;
+            planes[2][i] = 128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b;
+        }
+    }
+
+    // converts full range BT.601 YCbCr planes back to RGB in place
+    pub fn ycbcr_to_rgb_planes(planes: &mut [Vec<f32>]) {
+        for i in 0..planes[0].len() {
+            let (y, cb, cr) = (planes[0][i], planes[1][i] - 128.0, planes[2][i] - 128.0);
+            planes[0][i] = y + 1.402 * cr;
+            planes[1][i] = y - 0.344136 * cb - 0.714136 * cr;
+            planes[2][i] = y + 1.772 * cb;
+        }
+    }
+
+    // runs a plane filter over the channels selected by the mode,
+    // alpha is never filtered
+    pub fn filter_image<F>(img: &Image<u8>, mode: DenoiseMode, filter: F) -> Image<u8>
+    where
+        F: Fn(&[f32], usize, usize) -> Vec<f32>,
+    {
+        let (width, height, _channels, alpha) = img.info().whca();
+        let colors = img.info().channels_non_alpha() as usize;
+        let (w, h) = (width as usize, height as usize);
+        let mut planes = to_planes(img);
+
+        if colors < 3 {
+            if mode != DenoiseMode::Chroma {
+                planes[0] = filter(&planes[0], w, h);
+            }
+            return from_planes(&planes, width, height, alpha);
+        }
+
+        let selected = match mode {
+            DenoiseMode::All => vec![0, 1, 2],
+            DenoiseMode::Luma => vec![0],
+            DenoiseMode::Chroma => vec![1, 2],
+        };
+        if mode != DenoiseMode::All {
+            rgb_to_ycbcr_planes(&mut planes);
+        }
+        for c in selected {
+            planes[c] = filter(&planes[c], w, h);
+        }
+        if mode != DenoiseMode::All {
+            ycbcr_to_rgb_planes(&mut planes);
+        }
+        from_planes(&planes, width, height, alpha)
+    }
+
+    // median of a (2r+1)^2 window; small windows are sorted, larger ones use
+    // a sliding histogram updated by one column per step (Huang's algorithm)
+    pub fn median_plane(plane: &[f32], width: usize, height: usize, radius: usize, border: BorderMode) -> Vec<f32> {
+        let r = radius as i64;
+        let size = 2 * radius + 1;
+        let half = (size * size / 2) as u32;
+        let at = |x: i64, y: i64| -> u8 {
+            let xi = border_index(x, width as i64, border);
+            let yi = border_index(y, height as i64, border);
+            plane[yi * width + xi].round().clamp(0.0, 255.0) as u8
+        };
+        let mut out = vec![0f32; plane.len()];
+
+        if radius <= 2 {
+            let mut window = Vec::with_capacity(size * size);
+            for y in 0..height as i64 {
+                for x in 0..width as i64 {
+                    window.clear();
+                    for dy in -r..=r {
+                        for dx in -r..=r {
+                            window.push(at(x + dx, y + dy));
+                        }
+                    }
+                    window.sort_unstable();
+                    out[y as usize * width + x as usize] = window[window.len() / 2] as f32;
+                }
+            }
+            return out;
+        }
+
+        for y in 0..height as i64 {
+            let mut histogram = [0u32; 256];
+            for dy in -r..=r {
+                for dx in -r..=r {
+                    histogram[at(dx, y + dy) as usize] += 1;
+                }
+            }
+            for x in 0..width as i64 {
+                if x > 0 {
+                    // slide the window one column to the right
+                    for dy in -r..=r {
+                        histogram[at(x - r - 1, y + dy) as usize] -= 1;
+                        histogram[at(x + r, y + dy) as usize] += 1;
+                    }
+                }
+                let mut count = 0;
+                for (value, &n) in histogram.iter().enumerate() {
+                    count += n;
+                    if count > half {
+                        out[y as usize * width + x as usize] = value as f32;
+                        break;
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    // edge preserving blur: neighbours are weighted by their distance
+    // (sigma_space pixels) and their difference (sigma_range levels)
+    pub fn bilateral_plane(
+        plane: &[f32],
+        width: usize,
+        height: usize,
+        sigma_space: f32,
+        sigma_range: f32,
+        border: BorderMode,
+    ) -> Vec<f32> {
+        let r = (2.0 * sigma_space).ceil().max(1.0) as i64;
+        let mut spatial = Vec::new();
+        for dy in -r..=r {
+            for dx in -r..=r {
+                let d2 = (dx * dx + dy * dy) as f32;
+                spatial.push((dx, dy, (-d2 / (2.0 * sigma_space * sigma_space)).exp()));
+            }
+        }
+        let range: Vec<f32> = (0..256)
+            .map(|d| (-((d * d) as f32) / (2.0 * sigma_range * sigma_range)).exp())
+            .collect();
+
+        let mut out = vec![0f32; plane.len()];
+        for y in 0..height as i64 {
+            for x in 0..width as i64 {
+                let center = plane[y as usize * width + x as usize];
+                let mut sum = 0.0;
+                let mut weights = 0.0;
+                for &(dx, dy, ws) in spatial.iter() {
+                    let xi = border_index(x + dx, width as i64, border);
+                    let yi = border_index(y + dy, height as i64, border);
+                    let value = plane[yi * width + xi];
+                    let diff = ((value - center).abs().round() as usize).min(255);
+                    let w = ws * range[diff];
+                    sum += value * w;
+                    weights += w;
+                }
+                out[y as usize * width + x as usize] = sum / weights;
+            }
+        }
+        out
+    }
+
+    // non-local means: averages pixels of the search window weighted by how
+    // similar their surrounding patches are; strength is the filter parameter h.
+    // The patch distances of all pixels are found one search offset at a time
+    // with running sums along the rows and down the columns, so the time
+    // grows with the (2 * search + 1)^2 offsets and not with the patch size
+    pub fn nlm_plane(
+        plane: &[f32],
+        width: usize,
+        height: usize,
+        strength: f32,
+        patch: usize,
+        search: usize,
+        border: BorderMode,
+    ) -> Vec<f32> {
+        let p = patch as i64;
+        let s = search as i64;
+        let patch_size = ((2 * p + 1) * (2 * p + 1)) as f32;
+        let h2 = strength * strength;
+
+        // the plane with room around it for the patches of the whole window
+        let margin = p + s;
+        let padded_width = width as i64 + 2 * margin;
+        let mut padded = Vec::with_capacity((padded_width * (height as i64 + 2 * margin)) as usize);
+        for y in -margin..height as i64 + margin {
+            let yi = border_index(y, height as i64, border);
+            for x in -margin..width as i64 + margin {
+                padded.push(plane[yi * width + border_index(x, width as i64, border)]);
+            }
+        }
+        let at = |x: i64, y: i64| -> f32 { padded[((y + margin) * padded_width + x + margin) as usize] };
+
+        let rows = (2 * p + 1) as usize;
+        let mut sums = vec![0f32; plane.len()];
+        let mut weights = vec![0f32; plane.len()];
+        let mut ring = vec![vec![0f64; width]; rows];
+        let mut column = vec![0f64; width];
+        for sy in -s..=s {
+            for sx in -s..=s {
+                let difference = |x: i64, y: i64| -> f64 {
+                    let d = (at(x, y) - at(x + sx, y + sy)) as f64;
+                    d * d
+                };
+                ring.iter_mut().for_each(|r| r.iter_mut().for_each(|v| *v = 0.0));
+                column.iter_mut().for_each(|v| *v = 0.0);
+                for v in -p..height as i64 + p {
+                    // the ring slot holds the row leaving the column sums
+                    let row = &mut ring[(v + p) as usize % rows];
+                    let mut run: f64 = (-p..p).map(|u| difference(u, v)).sum();
+                    for x in 0..width {
+                        run += difference(x as i64 + p, v);
+                        column[x] += run - row[x];
+                        row[x] = run;
+                        run -= difference(x as i64 - p, v);
+                    }
+
+                    // the column sums now cover the patches of row v - p
+                    let y = v - p;
+                    if y >= 0 {
+                        for (x, d2) in column.iter().enumerate() {
+                            let w = (-(*d2 as f32 / patch_size) / h2).exp();
+                            let i = y as usize * width + x;
+                            sums[i] += at(x as i64 + sx, y + sy) * w;
+                            weights[i] += w;
+                        }
+                    }
+                }
+            }
+        }
+        sums.iter().zip(weights.iter()).map(|(s, w)| s / w).collect()
+    }
+
+    // runs the denoise options
+    pub fn apply_denoise(img: Image<u8>, opts: &ConfigOptions) -> Image<u8> {
+        let mode = match parse_denoise_mode(&opts.denoise_mode) {
+            Some(m) => m,
+            None => {
+                println!("Invalid denoise mode {}, using all", opts.denoise_mode);
+                DenoiseMode::All
+            }
+        };
+        let border = parse_border_mode(&opts.border_mode).unwrap_or(BorderMode::Reflect);
+        let numbers = |spec: &str| -> Option<Vec<f32>> {
+            let values: Vec<f32> = spec
+                .split(',')
+                .map(|v| v.trim().parse::<f32>())
+                .collect::<Result<_, _>>()
+                .ok()?;
+            if values.iter().any(|&v| v <= 0.0) {
+                return None;
+            }
+            Some(values)
+        };
+        let mut img = img;
+
+        if !opts.median.is_empty() {
+            match numbers(&opts.median) {
+                Some(v) if v.len() == 1 => {
+                    let radius = v[0].round() as usize;
+                    img = filter_image(&img, mode, |p, w, h| median_plane(p, w, h, radius, border));
+                }
+                _ => println!("Invalid median radius {}", opts.median),
+            }
+        }
+
+        if !opts.bilateral.is_empty() {
+            match numbers(&opts.bilateral) {
+                Some(v) if v.len() == 2 => {
+                    img = filter_image(&img, mode, |p, w, h| bilateral_plane(p, w, h, v[0], v[1], border));
+                }
+                _ => println!("Invalid bilateral settings {}", opts.bilateral),
+            }
+        }
+
+        if !opts.nlm.is_empty() {
+            match numbers(&opts.nlm) {
+                Some(v) if v.len() == 1 || v.len() == 3 => {
+                    let patch = v.get(1).map(|&p| p.round() as usize).unwrap_or(1);
+                    let search = v.get(2).map(|&s| s.round() as usize).unwrap_or(5);
+                    img = filter_image(&img, mode, |p, w, h| nlm_plane(p, w, h, v[0], patch, search, border));
+                }
+                _ => println!("Invalid non-local means settings {}", opts.nlm),
+            }
+        }
+        img
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        // the direct sum over every patch pixel of every search offset
+        fn nlm_reference(plane: &[f32], width: usize, height: usize, strength: f32, patch: i64, search: i64, border: BorderMode) -> Vec<f32> {
+            let at = |x: i64, y: i64| plane[border_index(y, height as i64, border) * width + border_index(x, width as i64, border)];
+            let patch_size = ((2 * patch + 1) * (2 * patch + 1)) as f32;
+            let mut out = Vec::new();
+            for y in 0..height as i64 {
+                for x in 0..width as i64 {
+                    let (mut sum, mut weights) = (0.0, 0.0);
+                    for sy in -search..=search {
+                        for sx in -search..=search {
+                            let mut d2 = 0.0;
+                            for py in -patch..=patch {
+                                for px in -patch..=patch {
+                                    let d = at(x + px, y + py) - at(x + sx + px, y + sy + py);
+                                    d2 += d * d;
+                                }
+                            }
+                            let w = (-(d2 / patch_size) / (strength * strength)).exp();
+                            sum += at(x + sx, y + sy) * w;
+                            weights += w;
+                        }
+                    }
+                    out.push(sum / weights);
+                }
+            }
+            out
+        }
+
+        #[test]
+        fn nlm_matches_the_direct_sum() {
+            let (width, height) = (13, 9);
+            let mut state = 12345u32;
+            let plane: Vec<f32> = (0..width * height)
+                .map(|i| {
+                    state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                    (i % width) as f32 * 12.0 + (state >> 27) as f32
+                })
+                .collect();
+            for border in [BorderMode::Reflect, BorderMode::Clamp, BorderMode::Wrap] {
+                for (patch, search) in [(0, 1), (1, 2), (2, 3)] {
+                    let fast = nlm_plane(&plane, width, height, 10.0, patch, search, border);
+                    let direct = nlm_reference(&plane, width, height, 10.0, patch as i64, search as i64, border);
+                    for (a, b) in fast.iter().zip(direct.iter()) {
+                        assert!((a - b).abs() < 1e-3, "{:?} {} {}: {} {}", border, patch, search, a, b);
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub use denoise::*;
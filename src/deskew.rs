@@ -0,0 +1,268 @@
+pub mod deskew {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    pub use crate::config::*;
+
+    use crate::convolve::*;
+    use crate::crop::*;
+    use crate::edge::*;
+    use crate::threshold::*;
+    use crate::warp::*;
+    use crate::log;
+
+    // how the skew angle is found
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum SkewMethod {
+        // the angle where the dark pixels project onto the fewest rows
+        Projection,
+        // the angle of the strongest straight edges
+        Hough,
+    }
+
+    // parses METHOD[,MAX_ANGLE], e.g. hough,5 (default: projection, 10 degrees)
+    pub fn parse_deskew(spec: &str) -> Option<(SkewMethod, f32)> {
+        let spec = spec.trim().to_lowercase();
+        let mut parts = spec.split(',');
+        let method = match parts.next()?.trim() {
+            "" | "projection" | "profile" => SkewMethod::Projection,
+            "hough" => SkewMethod::Hough,
+            _ => return None,
+        };
+        let max = match parts.next() {
+            Some(m) => m.trim().parse::<f32>().ok()?,
+            None => 10.0,
+        };
+        if max <= 0.0 || max > 45.0 || parts.next().is_some() {
+            return None;
+        }
+        Some((method, max))
+    }
+
+    // parses four corners as X1,Y1,X2,Y2,X3,Y3,X4,Y4 in the order top left,
+    // top right, bottom right, bottom left
+    pub fn parse_corners(spec: &str) -> Option<[(f64, f64); 4]> {
+        let values: Vec<f64> = spec
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .ok()?;
+        if values.len() != 8 {
+            return None;
+        }
+        Some([
+            (values[0], values[1]),
+            (values[2], values[3]),
+            (values[4], values[5]),
+            (values[6], values[7]),
+        ])
+    }
+
+    // the luma plane sampled every `step` pixels so the longer side is
+    // at most `size` pixels, with its width, height and step
+    fn small_plane(img: &Image<u8>, size: u32) -> (Vec<f32>, usize, usize, usize) {
+        let (width, height) = img.info().wh();
+        let step = width.max(height).div_ceil(size).max(1) as usize;
+        let plane = luma_plane(img);
+        let (w, h) = (width as usize, height as usize);
+        let (sw, sh) = (w.div_ceil(step), h.div_ceil(step));
+        let mut small = Vec::with_capacity(sw * sh);
+        for y in (0..h).step_by(step) {
+            for x in (0..w).step_by(step) {
+                small.push(plane[y * w + x]);
+            }
+        }
+        (small, sw, sh, step)
+    }
+
+    // counts the points per row after rotating them by `degrees`
+    fn row_profile(points: &[(f32, f32)], degrees: f32, rows: usize) -> Vec<u32> {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        // rotated rows can reach below zero and past the height
+        let offset = rows as f32;
+        let mut bins = vec![0u32; rows * 3];
+        for &(x, y) in points.iter() {
+            let row = (y * cos - x * sin + offset).round();
+            if row >= 0.0 && (row as usize) < bins.len() {
+                bins[row as usize] += 1;
+            }
+        }
+        bins
+    }
+
+    // the angle within +/- max scoring best, searched coarsely and then in
+    // 0.05 degree steps around the best coarse angle
+    fn best_angle<F>(max: f32, score: F) -> f32
+    where
+        F: Fn(f32) -> f64,
+    {
+        let search = |from: f32, to: f32, step: f32| -> f32 {
+            let count = ((to - from) / step).round() as i32;
+            (0..=count)
+                .map(|i| from + i as f32 * step)
+                .map(|angle| (angle, score(angle)))
+                .fold((0.0, f64::MIN), |best, (angle, s)| if s > best.1 { (angle, s) } else { best })
+                .0
+        };
+        let coarse = search(-max, max, 0.5);
+        search(coarse - 0.5, coarse + 0.5, 0.05)
+    }
+
+    // finds the skew angle in degrees, positive when the content is
+    // rotated clockwise
+    pub fn detect_skew(img: &Image<u8>, method: SkewMethod, max: f32) -> f32 {
+        let (plane, w, h, _step) = small_plane(img, 1000);
+        let mut points = Vec::new();
+        match method {
+            SkewMethod::Projection => {
+                // the dark pixels, mostly text
+                let mut histogram = [0u32; 256];
+                for &v in plane.iter() {
+                    histogram[v as usize] += 1;
+                }
+                let level = otsu_level(&histogram) as f32;
+                for (i, &v) in plane.iter().enumerate() {
+                    if v <= level {
+                        points.push(((i % w) as f32, (i / w) as f32));
+                    }
+                }
+                // sharp row profiles have the largest sum of squares
+                best_angle(max, |angle| {
+                    row_profile(&points, angle, h).iter().map(|&n| (n as f64).powi(2)).sum()
+                })
+            }
+            SkewMethod::Hough => {
+                let small = from_planes(&[plane], w as u32, h as u32, false);
+                let edges = detect_edges(&small, EdgeDetector::Canny(20.0, 50.0));
+                for (i, &v) in edges.data().iter().enumerate() {
+                    if v > 0 {
+                        points.push(((i % w) as f32, (i / w) as f32));
+                    }
+                }
+                // the accumulator column of each angle is its row profile, the
+                // angle of the strongest lines wins
+                best_angle(max, |angle| {
+                    let mut votes = row_profile(&points, angle, h);
+                    votes.sort_unstable_by(|a, b| b.cmp(a));
+                    votes.iter().take(10).map(|&n| n as f64).sum()
+                })
+            }
+        }
+    }
+
+    // finds the corners of a light document on a darker background: the
+    // largest bright region after blurring and otsu thresholding
+    pub fn detect_quad(img: &Image<u8>) -> Option<[(f64, f64); 4]> {
+        let (plane, w, h, step) = small_plane(img, 500);
+        let kernel = gaussian_kernel(2.0);
+        let plane = convolve_plane_separable(&plane, w, h, &kernel, &kernel, BorderMode::Reflect);
+        let mut histogram = [0u32; 256];
+        for &v in plane.iter() {
+            histogram[v.round().clamp(0.0, 255.0) as usize] += 1;
+        }
+        let level = otsu_level(&histogram) as f32;
+
+        // label the bright regions and keep the largest
+        let mut seen: Vec<bool> = plane.iter().map(|&v| v <= level).collect();
+        let mut best: Vec<usize> = Vec::new();
+        for start in 0..plane.len() {
+            if seen[start] {
+                continue;
+            }
+            seen[start] = true;
+            let mut region = vec![start];
+            let mut stack = vec![start];
+            while let Some(i) = stack.pop() {
+                let (x, y) = (i % w, i / w);
+                let mut visit = |j: usize| {
+                    if !seen[j] {
+                        seen[j] = true;
+                        region.push(j);
+                        stack.push(j);
+                    }
+                };
+                if x > 0 {
+                    visit(i - 1);
+                }
+                if x + 1 < w {
+                    visit(i + 1);
+                }
+                if y > 0 {
+                    visit(i - w);
+                }
+                if y + 1 < h {
+                    visit(i + w);
+                }
+            }
+            if region.len() > best.len() {
+                best = region;
+            }
+        }
+        if best.len() < plane.len() / 10 {
+            return None;
+        }
+
+        // the corners are the extremes along the diagonals
+        let point = |i: usize| ((i % w) as f64, (i / w) as f64);
+        let pick = |key: &dyn Fn((f64, f64)) -> f64| -> (f64, f64) {
+            let i = *best.iter().max_by(|&&a, &&b| key(point(a)).total_cmp(&key(point(b)))).unwrap();
+            let (x, y) = point(i);
+            (x * step as f64, y * step as f64)
+        };
+        Some([
+            pick(&|(x, y)| -x - y),
+            pick(&|(x, y)| x - y),
+            pick(&|(x, y)| x + y),
+            pick(&|(x, y)| y - x),
+        ])
+    }
+
+    // runs the perspective correction and then the deskew options
+    pub fn straighten_image(img: &Image<u8>, opts: &ConfigOptions) -> Image<u8> {
+        let mut img = img.clone();
+
+        if !opts.perspective.is_empty() {
+            let corners = if opts.perspective.trim().eq_ignore_ascii_case("auto") {
+                let quad = detect_quad(&img);
+                match quad {
+                    Some(c) => log!(
+                        " > Document corners {:.0},{:.0} {:.0},{:.0} {:.0},{:.0} {:.0},{:.0}",
+                        c[0].0, c[0].1, c[1].0, c[1].1, c[2].0, c[2].1, c[3].0, c[3].1
+                    ),
+                    None => println!("No document found, skipping perspective correction"),
+                }
+                quad
+            } else {
+                let corners = parse_corners(&opts.perspective);
+                if corners.is_none() {
+                    println!("Invalid perspective corners {}", opts.perspective);
+                }
+                corners
+            };
+            if let Some(corners) = corners {
+                match warp_perspective(&img, &corners) {
+                    Some(warped) => img = warped,
+                    None => println!("Invalid perspective corners {}", opts.perspective),
+                }
+            }
+        }
+
+        if !opts.deskew.is_empty() {
+            match parse_deskew(&opts.deskew) {
+                Some((method, max)) => {
+                    let angle = detect_skew(&img, method, max);
+                    log!(" > Skew angle {:.2}", angle);
+                    if angle.abs() >= 0.1 {
+                        let (width, height) = img.info().wh();
+                        let rotated = rotate_image(&img, -angle, &[255, 255, 255, 255]);
+                        let (x, y, w, h) = rotated_crop_rect(width, height, angle);
+                        img = crop_rect(&rotated, x, y, w, h);
+                    }
+                }
+                None => println!("Invalid deskew settings {}", opts.deskew),
+            }
+        }
+        img
+    }
+}
+
+pub use deskew::*;
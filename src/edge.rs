@@ -0,0 +1,164 @@
+pub mod edge {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    pub use crate::config::*;
+
+    use crate::convolve::*;
+    use crate::grayscale::*;
+    use crate::rustyimg::*;
+
+    // the supported edge detectors
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum EdgeDetector {
+        Sobel,
+        Scharr,
+        // low and high hysteresis thresholds on the gradient magnitude
+        Canny(f32, f32),
+    }
+
+    // parses sobel, scharr or canny[,LOW,HIGH] (default 20,50)
+    pub fn parse_edge_detector(spec: &str) -> Option<EdgeDetector> {
+        let spec = spec.trim().to_lowercase();
+        let mut parts = spec.split(',');
+        match parts.next()? {
+            "sobel" => Some(EdgeDetector::Sobel),
+            "scharr" => Some(EdgeDetector::Scharr),
+            "canny" => {
+                let values: Vec<f32> = parts
+                    .map(|v| v.trim().parse::<f32>())
+                    .collect::<Result<_, _>>()
+                    .ok()?;
+                match values.len() {
+                    0 => Some(EdgeDetector::Canny(20.0, 50.0)),
+                    2 if values[0] >= 0.0 && values[0] <= values[1] => {
+                        Some(EdgeDetector::Canny(values[0], values[1]))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // the luma of an image as a float plane
+    pub fn luma_plane(img: &Image<u8>) -> Vec<f32> {
+        let gray = if is_grayscale_image(img) {
+            img.clone()
+        } else {
+            to_grayscale(img, GrayMethod::Rec601, false)
+        };
+        to_planes(&gray).swap_remove(0)
+    }
+
+    // horizontal and vertical gradients, scaled so a full black to white
+    // step has a magnitude of 255
+    pub fn gradients(plane: &[f32], width: usize, height: usize, scharr: bool) -> (Vec<f32>, Vec<f32>) {
+        let (a, b, scale) = if scharr { (3.0, 10.0, 16.0) } else { (1.0, 2.0, 4.0) };
+        let kx = Kernel {
+            width: 3,
+            height: 3,
+            data: [-a, 0.0, a, -b, 0.0, b, -a, 0.0, a].iter().map(|v| v / scale).collect(),
+        };
+        let ky = Kernel {
+            width: 3,
+            height: 3,
+            data: [-a, -b, -a, 0.0, 0.0, 0.0, a, b, a].iter().map(|v| v / scale).collect(),
+        };
+        (
+            convolve_plane(plane, width, height, &kx, BorderMode::Clamp),
+            convolve_plane(plane, width, height, &ky, BorderMode::Clamp),
+        )
+    }
+
+    // detects edges, producing a single channel image
+    pub fn detect_edges(img: &Image<u8>, detector: EdgeDetector) -> Image<u8> {
+        let (width, height) = img.info().wh();
+        let (w, h) = (width as usize, height as usize);
+        let plane = luma_plane(img);
+
+        let edges = match detector {
+            EdgeDetector::Sobel | EdgeDetector::Scharr => {
+                let (gx, gy) = gradients(&plane, w, h, detector == EdgeDetector::Scharr);
+                gx.iter().zip(gy.iter()).map(|(x, y)| (x * x + y * y).sqrt()).collect()
+            }
+            EdgeDetector::Canny(low, high) => canny(&plane, w, h, low, high),
+        };
+        from_planes(&[edges], width, height, false)
+    }
+
+    // canny edge detection: smoothing, gradients, thinning to the local
+    // maxima and hysteresis between the low and high thresholds
+    fn canny(plane: &[f32], width: usize, height: usize, low: f32, high: f32) -> Vec<f32> {
+        let kernel = gaussian_kernel(1.4);
+        let smooth = convolve_plane_separable(plane, width, height, &kernel, &kernel, BorderMode::Reflect);
+        let (gx, gy) = gradients(&smooth, width, height, false);
+        let magnitude: Vec<f32> = gx.iter().zip(gy.iter()).map(|(x, y)| (x * x + y * y).sqrt()).collect();
+
+        // keep only the maxima across the gradient direction
+        let mut thin = vec![0f32; magnitude.len()];
+        for y in 1..height.saturating_sub(1) {
+            for x in 1..width.saturating_sub(1) {
+                let i = y * width + x;
+                let m = magnitude[i];
+                if m < low {
+                    continue;
+                }
+                let angle = gy[i].atan2(gx[i]).to_degrees().rem_euclid(180.0);
+                let (dx, dy): (i64, i64) = if !(22.5..157.5).contains(&angle) {
+                    (1, 0)
+                } else if angle < 67.5 {
+                    (1, 1)
+                } else if angle < 112.5 {
+                    (0, 1)
+                } else {
+                    (-1, 1)
+                };
+                let a = magnitude[((y as i64 + dy) * width as i64 + x as i64 + dx) as usize];
+                let b = magnitude[((y as i64 - dy) * width as i64 + x as i64 - dx) as usize];
+                if m >= a && m >= b {
+                    thin[i] = m;
+                }
+            }
+        }
+
+        // grow the strong edges into the connected weak ones
+        let mut out = vec![0f32; thin.len()];
+        let mut stack: Vec<usize> = (0..thin.len()).filter(|&i| thin[i] >= high).collect();
+        for &i in stack.iter() {
+            out[i] = 255.0;
+        }
+        while let Some(i) = stack.pop() {
+            let (x, y) = ((i % width) as i64, (i / width) as i64);
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                        continue;
+                    }
+                    let j = ny as usize * width + nx as usize;
+                    if out[j] == 0.0 && thin[j] >= low {
+                        out[j] = 255.0;
+                        stack.push(j);
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    // runs the edge detection option
+    pub fn apply_edges(img: Image<u8>, opts: &ConfigOptions) -> Image<u8> {
+        if opts.edges.is_empty() {
+            return img;
+        }
+        match parse_edge_detector(&opts.edges) {
+            Some(detector) => detect_edges(&img, detector),
+            None => {
+                println!("Invalid edge detector {}", opts.edges);
+                img
+            }
+        }
+    }
+}
+
+pub use edge::*;
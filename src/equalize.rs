@@ -0,0 +1,158 @@
+pub mod equalize {
+
+    use imgproc_rs::image::{BaseImage, Image, ImageInfo};
+    pub use crate::config::*;
+
+    use crate::grayscale::*;
+    use crate::rustyimg::*;
+
+    // parses the CLAHE settings as COLSxROWS[,CLIP], e.g. 8x8,2.5
+    pub fn parse_clahe(spec: &str) -> Option<(u32, u32, f32)> {
+        let mut parts = spec.split(',');
+        let (cols, rows) = parts.next()?.trim().split_once('x')?;
+        let cols = cols.trim().parse::<u32>().ok()?;
+        let rows = rows.trim().parse::<u32>().ok()?;
+        let clip = match parts.next() {
+            Some(c) => c.trim().parse::<f32>().ok()?,
+            None => 2.0,
+        };
+        if cols == 0 || rows == 0 || clip < 1.0 || parts.next().is_some() {
+            return None;
+        }
+        Some((cols, rows, clip))
+    }
+
+    // equalizes the histogram of the luminance
+    pub fn equalize_image(img: &Image<u8>) -> Image<u8> {
+        map_luminance(img, |luma| {
+            let lut = equalize_lut(&get_channel_histogram(luma, 0), 0.0);
+            let (width, height) = luma.info().wh();
+            let data = luma.data().iter().map(|&v| lut[v as usize]).collect();
+            Image::from_vec(width, height, 1, false, data)
+        })
+    }
+
+    // contrast limited adaptive histogram equalization of the luminance
+    // over a grid of cols x rows tiles; clip limits each histogram bin to
+    // clip times the average bin count
+    pub fn clahe_image(img: &Image<u8>, cols: u32, rows: u32, clip: f32) -> Image<u8> {
+        map_luminance(img, |luma| clahe_luma(luma, cols, rows, clip))
+    }
+
+    fn clahe_luma(luma: &Image<u8>, cols: u32, rows: u32, clip: f32) -> Image<u8> {
+        let (width, height) = luma.info().wh();
+        let cols = cols.min(width);
+        let rows = rows.min(height);
+        let tile_w = width as f32 / cols as f32;
+        let tile_h = height as f32 / rows as f32;
+
+        // a lookup table for every tile
+        let mut luts = Vec::with_capacity((cols * rows) as usize);
+        for ty in 0..rows {
+            for tx in 0..cols {
+                let x0 = (tx as f32 * tile_w) as u32;
+                let y0 = (ty as f32 * tile_h) as u32;
+                let x1 = (((tx + 1) as f32 * tile_w) as u32).min(width);
+                let y1 = (((ty + 1) as f32 * tile_h) as u32).min(height);
+                let mut histogram = [0u32; 256];
+                for y in y0..y1 {
+                    for x in x0..x1 {
+                        histogram[luma.get_pixel(x, y)[0] as usize] += 1;
+                    }
+                }
+                luts.push(equalize_lut(&histogram, clip));
+            }
+        }
+
+        // interpolate between the four nearest tile centers
+        let mut out = Image::blank(ImageInfo::new(width, height, 1, false));
+        for y in 0..height {
+            let fy = ((y as f32 + 0.5) / tile_h - 0.5).clamp(0.0, (rows - 1) as f32);
+            let ty0 = fy as u32;
+            let ty1 = (ty0 + 1).min(rows - 1);
+            let wy = fy - ty0 as f32;
+            for x in 0..width {
+                let fx = ((x as f32 + 0.5) / tile_w - 0.5).clamp(0.0, (cols - 1) as f32);
+                let tx0 = fx as u32;
+                let tx1 = (tx0 + 1).min(cols - 1);
+                let wx = fx - tx0 as f32;
+
+                let v = luma.get_pixel(x, y)[0] as usize;
+                let lut = |tx: u32, ty: u32| luts[(ty * cols + tx) as usize][v] as f32;
+                let top = lut(tx0, ty0) * (1.0 - wx) + lut(tx1, ty0) * wx;
+                let bottom = lut(tx0, ty1) * (1.0 - wx) + lut(tx1, ty1) * wx;
+                out.set_pixel(x, y, &[round_u8(top * (1.0 - wy) + bottom * wy)]);
+            }
+        }
+        out
+    }
+
+    // builds the equalization lookup table of a histogram
+    // with a clip factor above 0 the bins are limited to clip times the
+    // average bin count and the excess is spread over all bins
+    pub fn equalize_lut(histogram: &[u32; 256], clip: f32) -> [u8; 256] {
+        let total: f32 = histogram.iter().map(|&c| c as f32).sum();
+        let mut bins: Vec<f32> = histogram.iter().map(|&c| c as f32).collect();
+
+        if clip > 0.0 {
+            let limit = (clip * total / 256.0).max(1.0);
+            let mut excess = 0.0;
+            for bin in bins.iter_mut() {
+                if *bin > limit {
+                    excess += *bin - limit;
+                    *bin = limit;
+                }
+            }
+            for bin in bins.iter_mut() {
+                *bin += excess / 256.0;
+            }
+        }
+
+        // skip the empty bins at the bottom, so the darkest value maps to 0
+        let first = bins.iter().position(|&b| b > 0.0).unwrap_or(0);
+        let base = bins[first];
+        let mut lut = [0u8; 256];
+        let mut sum = 0.0;
+        for (i, bin) in bins.iter().enumerate() {
+            sum += bin;
+            if i < first || total <= base {
+                lut[i] = i as u8;
+                continue;
+            }
+            lut[i] = round_u8(255.0 * (sum - base) / (total - base));
+        }
+        lut
+    }
+
+    // applies a transformation to the luminance of an image, keeping the
+    // chroma; single channel images are transformed directly
+    pub fn map_luminance<F>(img: &Image<u8>, f: F) -> Image<u8>
+    where
+        F: Fn(&Image<u8>) -> Image<u8>,
+    {
+        if is_grayscale_image(img) {
+            return f(img);
+        }
+
+        let luma = to_grayscale(img, GrayMethod::Rec601, false);
+        let mapped = f(&luma);
+
+        // adding the same amount to all channels changes the luma by that
+        // amount and leaves the chroma untouched
+        let (width, height, channels, alpha) = img.info().whca();
+        let mut img2 = Image::blank(ImageInfo::new(width, height, channels, alpha));
+        for y in 0..height {
+            for x in 0..width {
+                let mut pixel = img.get_pixel(x, y).to_vec();
+                let delta = mapped.get_pixel(x, y)[0] as f32 - luma.get_pixel(x, y)[0] as f32;
+                for value in pixel.iter_mut().take(3) {
+                    *value = round_u8(*value as f32 + delta);
+                }
+                img2.set_pixel(x, y, &pixel);
+            }
+        }
+        img2
+    }
+}
+
+pub use equalize::*;
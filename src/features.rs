@@ -0,0 +1,249 @@
+pub mod features {
+
+    pub use crate::config::*;
+
+    use crate::convolve::*;
+
+    // the FAST circle of 16 pixels with a radius of 3
+    const CIRCLE: [(i32, i32); 16] = [
+        (0, -3), (1, -3), (2, -2), (3, -1), (3, 0), (3, 1), (2, 2), (1, 3),
+        (0, 3), (-1, 3), (-2, 2), (-3, 1), (-3, 0), (-3, -1), (-2, -2), (-1, -3),
+    ];
+
+    // the radius of the patch around a keypoint the descriptor looks at
+    const PATCH_RADIUS: i32 = 15;
+
+    // keypoints closer to the border have no full patch, even rotated
+    const BORDER: i32 = 22;
+
+    // a corner with its orientation and binary descriptor
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Keypoint {
+        pub x: f32,
+        pub y: f32,
+        pub score: f32,
+        // the direction of the intensity centroid, in radians
+        pub angle: f32,
+        pub descriptor: [u64; 4],
+    }
+
+    // the 256 point pairs compared by the descriptor, the same on every run
+    fn brief_pattern() -> Vec<[(f32, f32); 2]> {
+        let mut seed: u64 = 0x2545f4914f6cdd1d;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            // roughly gaussian around the center, inside the patch
+            let sum: f32 = (0..4).map(|i| ((seed >> (i * 16)) & 0xffff) as f32 / 65535.0).sum();
+            ((sum - 2.0) * 12.0).clamp(-(PATCH_RADIUS as f32) + 1.0, PATCH_RADIUS as f32 - 1.0)
+        };
+        (0..256).map(|_| [(next(), next()), (next(), next())]).collect()
+    }
+
+    // FAST-9 corners: 9 contiguous circle pixels all brighter or all darker
+    // than the center by `threshold`; scored by how much they differ
+    fn fast_corners(plane: &[f32], width: usize, height: usize, threshold: f32) -> Vec<(usize, usize, f32)> {
+        let mut corners = Vec::new();
+        let offsets: Vec<isize> = CIRCLE.iter().map(|&(dx, dy)| dy as isize * width as isize + dx as isize).collect();
+        let mut scores = vec![0f32; width * height];
+        for y in BORDER as usize..height.saturating_sub(BORDER as usize) {
+            for x in BORDER as usize..width.saturating_sub(BORDER as usize) {
+                let i = y * width + x;
+                let center = plane[i];
+                let ring: Vec<f32> = offsets.iter().map(|&o| plane[(i as isize + o) as usize] - center).collect();
+                // a quick test on the four compass points first
+                let bright = [0, 4, 8, 12].iter().filter(|&&k| ring[k] > threshold).count();
+                let dark = [0, 4, 8, 12].iter().filter(|&&k| ring[k] < -threshold).count();
+                if bright < 2 && dark < 2 {
+                    continue;
+                }
+                let arc = |test: &dyn Fn(f32) -> bool| {
+                    let mut run = 0;
+                    for k in 0..32 {
+                        if test(ring[k % 16]) {
+                            run += 1;
+                            if run >= 9 {
+                                return true;
+                            }
+                        } else {
+                            run = 0;
+                        }
+                    }
+                    false
+                };
+                if arc(&|d| d > threshold) || arc(&|d| d < -threshold) {
+                    scores[i] = ring.iter().map(|d| (d.abs() - threshold).max(0.0)).sum();
+                }
+            }
+        }
+        // keep the local maxima only
+        for y in 1..height.saturating_sub(1) {
+            for x in 1..width.saturating_sub(1) {
+                let score = scores[y * width + x];
+                if score <= 0.0 {
+                    continue;
+                }
+                let is_max = (-1..=1).all(|dy: isize| {
+                    (-1..=1).all(|dx: isize| {
+                        let j = ((y as isize + dy) * width as isize + x as isize + dx) as usize;
+                        scores[j] < score || (dx == 0 && dy == 0) || (scores[j] == score && (dy, dx) > (0, 0))
+                    })
+                });
+                if is_max {
+                    corners.push((x, y, score));
+                }
+            }
+        }
+        corners
+    }
+
+    // finds up to `count` keypoints in a luma plane spread over the image,
+    // with oriented BRIEF descriptors
+    pub fn detect_keypoints(plane: &[f32], width: usize, height: usize, count: usize) -> Vec<Keypoint> {
+        let mut corners = fast_corners(plane, width, height, 20.0);
+
+        // no more than a few keypoints in each cell, the best ones
+        let cell = 32;
+        let per_cell = (count * cell * cell / (width * height).max(1)).max(2) * 2;
+        corners.sort_by(|a, b| b.2.total_cmp(&a.2));
+        let columns = width.div_ceil(cell);
+        let mut used = vec![0usize; columns * height.div_ceil(cell)];
+        corners.retain(|&(x, y, _)| {
+            let c = (y / cell) * columns + x / cell;
+            used[c] += 1;
+            used[c] <= per_cell
+        });
+        corners.truncate(count);
+
+        let kernel = gaussian_kernel(2.0);
+        let smooth = convolve_plane_separable(plane, width, height, &kernel, &kernel, BorderMode::Reflect);
+        let pattern = brief_pattern();
+        corners
+            .iter()
+            .map(|&(x, y, score)| {
+                // the intensity centroid of the circular patch
+                let (mut m10, mut m01) = (0f32, 0f32);
+                for dy in -PATCH_RADIUS..=PATCH_RADIUS {
+                    for dx in -PATCH_RADIUS..=PATCH_RADIUS {
+                        if dx * dx + dy * dy <= PATCH_RADIUS * PATCH_RADIUS {
+                            let v = plane[(y as i32 + dy) as usize * width + (x as i32 + dx) as usize];
+                            m10 += dx as f32 * v;
+                            m01 += dy as f32 * v;
+                        }
+                    }
+                }
+                let angle = m01.atan2(m10);
+                let (sin, cos) = angle.sin_cos();
+                let at = |(px, py): (f32, f32)| {
+                    let rx = (x as f32 + px * cos - py * sin).round() as usize;
+                    let ry = (y as f32 + px * sin + py * cos).round() as usize;
+                    smooth[ry * width + rx]
+                };
+                let mut descriptor = [0u64; 4];
+                for (bit, pair) in pattern.iter().enumerate() {
+                    if at(pair[0]) < at(pair[1]) {
+                        descriptor[bit / 64] |= 1 << (bit % 64);
+                    }
+                }
+                Keypoint {
+                    x: x as f32,
+                    y: y as f32,
+                    score,
+                    angle,
+                    descriptor,
+                }
+            })
+            .collect()
+    }
+
+    pub fn hamming_distance(a: &[u64; 4], b: &[u64; 4]) -> u32 {
+        a.iter().zip(b.iter()).map(|(x, y)| (x ^ y).count_ones()).sum()
+    }
+
+    // the closest and second closest descriptor in a list
+    fn nearest(descriptor: &[u64; 4], keypoints: &[Keypoint]) -> Option<(usize, u32, u32)> {
+        let mut best: Option<(usize, u32)> = None;
+        let mut second = u32::MAX;
+        for (i, k) in keypoints.iter().enumerate() {
+            let d = hamming_distance(descriptor, &k.descriptor);
+            match best {
+                Some((_, b)) if d >= b => second = second.min(d),
+                _ => {
+                    if let Some((_, b)) = best {
+                        second = second.min(b);
+                    }
+                    best = Some((i, d));
+                }
+            }
+        }
+        best.map(|(i, d)| (i, d, second))
+    }
+
+    // pairs of keypoint indices whose descriptors match both ways and are
+    // clearly closer than the next best candidate
+    pub fn match_keypoints(a: &[Keypoint], b: &[Keypoint]) -> Vec<(usize, usize)> {
+        let mut matches = Vec::new();
+        for (i, k) in a.iter().enumerate() {
+            if let Some((j, best, second)) = nearest(&k.descriptor, b) {
+                if best > 64 || best as f32 > 0.8 * second as f32 {
+                    continue;
+                }
+                if let Some((back, _, _)) = nearest(&b[j].descriptor, a) {
+                    if back == i {
+                        matches.push((i, j));
+                    }
+                }
+            }
+        }
+        matches
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        // overlapping random rectangles, sampled at (x - dx, y - dy)
+        fn rectangles(width: usize, height: usize, dx: i32, dy: i32) -> Vec<f32> {
+            let mut state = 5u32;
+            let mut next = |n: u32| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                (state >> 8) % n
+            };
+            let boxes: Vec<(i32, i32, i32, i32, f32)> = (0..80)
+                .map(|_| (next(240) as i32 - 20, next(200) as i32 - 20, 8 + next(40) as i32, 8 + next(40) as i32, next(90) as f32 - 30.0))
+                .collect();
+            let mut plane = Vec::with_capacity(width * height);
+            for y in 0..height as i32 {
+                for x in 0..width as i32 {
+                    let (px, py) = (x - dx, y - dy);
+                    let value: f32 = boxes.iter().filter(|b| px >= b.0 && px < b.0 + b.2 && py >= b.1 && py < b.1 + b.3).map(|b| b.4).sum();
+                    plane.push((100.0 + value).clamp(0.0, 255.0));
+                }
+            }
+            plane
+        }
+
+        #[test]
+        fn keypoints_follow_a_shift() {
+            let (width, height) = (200, 160);
+            let a = detect_keypoints(&rectangles(width, height, 0, 0), width, height, 300);
+            let b = detect_keypoints(&rectangles(width, height, 7, -4), width, height, 300);
+            assert!(a.len() > 30 && b.len() > 30, "{} {}", a.len(), b.len());
+            assert!(a.iter().all(|k| k.x >= BORDER as f32 && k.x < (width as i32 - BORDER) as f32));
+
+            let matches = match_keypoints(&a, &b);
+            let shifted = matches.iter().filter(|&&(i, j)| b[j].x - a[i].x == 7.0 && b[j].y - a[i].y == -4.0).count();
+            assert!(matches.len() >= 20, "{} matches", matches.len());
+            assert!(shifted * 10 >= matches.len() * 9, "{} of {} matches follow the shift", shifted, matches.len());
+        }
+
+        #[test]
+        fn hamming() {
+            assert_eq!(hamming_distance(&[0; 4], &[0; 4]), 0);
+            assert_eq!(hamming_distance(&[0b1011, 0, 0, 1 << 63], &[0, 0, 0, 0]), 4);
+        }
+    }
+}
+
+pub use features::*;
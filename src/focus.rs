@@ -0,0 +1,103 @@
+pub mod focus {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    pub use crate::config::*;
+
+    use crate::convolve::*;
+    use crate::edge::*;
+    use crate::pyramid::*;
+
+    // how sharp each pixel is: the absolute laplacian of the lightly
+    // smoothed luma, averaged over the neighbourhood so whole regions count
+    pub fn focus_measure(img: &Image<u8>) -> Vec<f32> {
+        let (width, height) = img.info().wh();
+        let (w, h) = (width as usize, height as usize);
+        let smooth = gaussian_kernel(1.0);
+        let luma = convolve_plane_separable(&luma_plane(img), w, h, &smooth, &smooth, BorderMode::Reflect);
+        let laplacian = Kernel {
+            width: 3,
+            height: 3,
+            data: vec![0.0, 1.0, 0.0, 1.0, -4.0, 1.0, 0.0, 1.0, 0.0],
+        };
+        let detail: Vec<f32> = convolve_plane(&luma, w, h, &laplacian, BorderMode::Reflect)
+            .iter()
+            .map(|v| v.abs())
+            .collect();
+        let spread = gaussian_kernel((w.min(h) as f32 / 200.0).max(2.0));
+        convolve_plane_separable(&detail, w, h, &spread, &spread, BorderMode::Reflect)
+    }
+
+    // merges aligned shots focused at different distances, taking each
+    // region from the sharpest shot and hiding the seams with laplacian
+    // pyramid blending
+    pub fn stack_focus(images: &[Image<u8>]) -> Image<u8> {
+        let (width, height) = images[0].info().wh();
+        let (w, h) = (width as usize, height as usize);
+        let colors = images[0].info().channels_non_alpha() as usize;
+        let measures: Vec<Vec<f32>> = images.iter().map(focus_measure).collect();
+
+        // every pixel goes to the sharpest shot
+        let mut weights = vec![vec![0f32; w * h]; images.len()];
+        for i in 0..w * h {
+            let (best, _) = measures
+                .iter()
+                .enumerate()
+                .fold((0, f32::MIN), |best, (k, m)| if m[i] > best.1 { (k, m[i]) } else { best });
+            weights[best][i] = 1.0;
+        }
+        let levels = pyramid_levels(w, h);
+        let weight_pyramids: Vec<Vec<Level>> = weights.iter().map(|wt| gaussian_pyramid(wt, w, h, levels)).collect();
+
+        let planes: Vec<Vec<Vec<f32>>> = images.iter().map(to_planes).collect();
+        let stacked: Vec<Vec<f32>> = (0..colors)
+            .map(|c| {
+                let channel: Vec<&[f32]> = planes.iter().map(|p| p[c].as_slice()).collect();
+                blend_planes(&channel, &weight_pyramids, w, h)
+            })
+            .collect();
+        from_planes(&stacked, width, height, false)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn sharp_halves() {
+            let (width, height) = (96usize, 64usize);
+            let mut state = 99u32;
+            let sharp: Vec<f32> = (0..width * height)
+                .map(|_| {
+                    state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                    ((state >> 24) as f32).clamp(30.0, 225.0)
+                })
+                .collect();
+            let kernel = gaussian_kernel(2.5);
+            let blurred = convolve_plane_separable(&sharp, width, height, &kernel, &kernel, BorderMode::Reflect);
+            // each shot is sharp in one half
+            let shot = |sharp_left: bool| {
+                let mut data = Vec::with_capacity(width * height * 3);
+                for y in 0..height {
+                    for x in 0..width {
+                        let plane = if (x < width / 2) == sharp_left { &sharp } else { &blurred };
+                        data.extend([plane[y * width + x].round() as u8; 3]);
+                    }
+                }
+                Image::from_vec(width as u32, height as u32, 3, false, data)
+            };
+            let stacked = stack_focus(&[shot(true), shot(false)]);
+            assert_eq!(stacked.info().whc(), (width as u32, height as u32, 3));
+
+            // away from the seam every pixel comes from the sharp shot
+            let data = stacked.data();
+            for y in 0..height {
+                for x in (0..width).filter(|x| x.abs_diff(width / 2) > 8) {
+                    let value = data[(y * width + x) * 3] as f32;
+                    assert!((value - sharp[y * width + x]).abs() <= 2.0, "{} {}: {} {}", x, y, value, sharp[y * width + x]);
+                }
+            }
+        }
+    }
+}
+
+pub use focus::*;
@@ -0,0 +1,252 @@
+pub mod fusion {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    use std::fs;
+    pub use crate::config::*;
+
+    use crate::convolve::*;
+    use crate::pyramid::*;
+    use crate::rustyexif::*;
+
+    // what is known about one shot of a bracket or a focus stack
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Shot {
+        pub file: String,
+        // the exposure compensation in stops, when the EXIF data has it
+        pub exposure: Option<f32>,
+        // seconds since the epoch, from the EXIF date or the file date
+        pub time: f64,
+        // the camera's burst or bracket identifier
+        pub burst: Option<String>,
+    }
+
+    // parses an EXIF exposure compensation such as -1, +0.7 or +2/3
+    pub fn parse_exposure(value: &str) -> Option<f32> {
+        let value = value.trim().trim_start_matches('+');
+        match value.split_once('/') {
+            Some((n, d)) => {
+                let d = d.trim().parse::<f32>().ok()?;
+                if d == 0.0 {
+                    return None;
+                }
+                Some(n.trim().parse::<f32>().ok()? / d)
+            }
+            None => value.parse::<f32>().ok(),
+        }
+    }
+
+    // reads the exposure compensation, time and burst of a file
+    pub fn read_shot(file: &str) -> Shot {
+        let fields = read_exif_from_file(file);
+        let exposure = get_exif_value(&fields, "ExposureCompensation").and_then(|v| parse_exposure(&v));
+        let burst = ["BurstUUID", "BurstID"]
+            .iter()
+            .find_map(|name| get_exif_value(&fields, name))
+            .filter(|v| !v.is_empty());
+
+        let date = ["DateTimeOriginal", "CreateDate", "DateTime"]
+            .iter()
+            .find_map(|name| get_exif_value(&fields, name))
+            .and_then(|v| chrono::NaiveDateTime::parse_from_str(v.get(0..19)?, "%Y:%m:%d %H:%M:%S").ok());
+        let time = match date {
+            Some(d) => {
+                let subsec = get_exif_value(&fields, "SubSecTimeOriginal")
+                    .and_then(|s| format!("0.{}", s.trim()).parse::<f64>().ok())
+                    .unwrap_or(0.0);
+                d.timestamp() as f64 + subsec
+            }
+            None => fs::metadata(file)
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|m| m.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs_f64())
+                .unwrap_or(0.0),
+        };
+
+        Shot {
+            file: file.to_string(),
+            exposure,
+            time,
+            burst,
+        }
+    }
+
+    // groups shots into series: shots of the same burst belong together,
+    // otherwise a series ends after a gap of more than `gap` seconds or, for
+    // brackets, when an exposure compensation repeats
+    pub fn group_shots(shots: &[Shot], gap: f64, brackets: bool) -> Vec<Vec<Shot>> {
+        let mut sorted = shots.to_vec();
+        sorted.sort_by(|a, b| a.time.total_cmp(&b.time).then_with(|| a.file.cmp(&b.file)));
+
+        let mut groups: Vec<Vec<Shot>> = Vec::new();
+        for shot in sorted {
+            let joins = match groups.last().and_then(|g| g.last().map(|last| (g, last))) {
+                Some((group, last)) => match (&shot.burst, &last.burst) {
+                    (Some(a), Some(b)) => a == b,
+                    _ => {
+                        shot.time - last.time <= gap
+                            && !(brackets && shot.exposure.is_some() && group.iter().any(|s| s.exposure == shot.exposure))
+                    }
+                },
+                None => false,
+            };
+            if joins {
+                groups.last_mut().unwrap().push(shot);
+            } else {
+                groups.push(vec![shot]);
+            }
+        }
+        groups
+    }
+
+    // the shot whose metadata the result keeps: the middle exposure, or the
+    // middle shot in time when the exposures are unknown
+    pub fn middle_shot(group: &[Shot]) -> &Shot {
+        let mut order: Vec<&Shot> = group.iter().collect();
+        if order.iter().all(|s| s.exposure.is_some()) {
+            order.sort_by(|a, b| a.exposure.unwrap().total_cmp(&b.exposure.unwrap()));
+        }
+        order[(order.len() - 1) / 2]
+    }
+
+    // the Mertens weight of each pixel: local contrast, saturation and how
+    // close the values are to a middle gray
+    fn exposure_weights(planes: &[Vec<f32>], width: usize, height: usize) -> Vec<f32> {
+        let colors = planes.len();
+        let gray: Vec<f32> = if colors >= 3 {
+            (0..width * height)
+                .map(|i| 0.299 * planes[0][i] + 0.587 * planes[1][i] + 0.114 * planes[2][i])
+                .collect()
+        } else {
+            planes[0].clone()
+        };
+        let laplacian = Kernel {
+            width: 3,
+            height: 3,
+            data: vec![0.0, 1.0, 0.0, 1.0, -4.0, 1.0, 0.0, 1.0, 0.0],
+        };
+        let contrast = convolve_plane(&gray, width, height, &laplacian, BorderMode::Reflect);
+
+        (0..width * height)
+            .map(|i| {
+                let mean = planes.iter().map(|p| p[i]).sum::<f32>() / colors as f32;
+                let saturation = if colors >= 3 {
+                    (planes.iter().map(|p| (p[i] - mean) * (p[i] - mean)).sum::<f32>() / colors as f32).sqrt()
+                } else {
+                    1.0
+                };
+                let exposedness: f32 = planes
+                    .iter()
+                    .map(|p| (-(p[i] - 0.5) * (p[i] - 0.5) / (2.0 * 0.2 * 0.2)).exp())
+                    .product();
+                contrast[i].abs() * saturation * exposedness + 1e-12
+            })
+            .collect()
+    }
+
+    // fuses differently exposed images of the same size into one, blending
+    // the best exposed parts of each with laplacian pyramids (Mertens et al.)
+    pub fn fuse_exposures(images: &[Image<u8>]) -> Image<u8> {
+        let (width, height) = images[0].info().wh();
+        let (w, h) = (width as usize, height as usize);
+        let colors = images[0].info().channels_non_alpha() as usize;
+        let planes: Vec<Vec<Vec<f32>>> = images
+            .iter()
+            .map(|img| {
+                let mut planes = to_planes(img);
+                planes.truncate(colors);
+                for plane in planes.iter_mut() {
+                    plane.iter_mut().for_each(|v| *v /= 255.0);
+                }
+                planes
+            })
+            .collect();
+
+        let mut weights: Vec<Vec<f32>> = planes.iter().map(|p| exposure_weights(p, w, h)).collect();
+        for i in 0..w * h {
+            let total: f32 = weights.iter().map(|wt| wt[i]).sum();
+            for weight in weights.iter_mut() {
+                weight[i] /= total;
+            }
+        }
+        let levels = pyramid_levels(w, h);
+        let weight_pyramids: Vec<Vec<Level>> = weights.iter().map(|wt| gaussian_pyramid(wt, w, h, levels)).collect();
+
+        let fused: Vec<Vec<f32>> = (0..colors)
+            .map(|c| {
+                let channel: Vec<&[f32]> = planes.iter().map(|p| p[c].as_slice()).collect();
+                blend_planes(&channel, &weight_pyramids, w, h)
+                    .into_iter()
+                    .map(|v| v.clamp(0.0, 1.0) * 255.0)
+                    .collect()
+            })
+            .collect();
+        from_planes(&fused, width, height, false)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn shot(file: &str, time: f64, exposure: Option<f32>, burst: Option<&str>) -> Shot {
+            Shot {
+                file: file.to_string(),
+                exposure,
+                time,
+                burst: burst.map(|b| b.to_string()),
+            }
+        }
+
+        fn files(groups: &[Vec<Shot>]) -> Vec<Vec<&str>> {
+            groups.iter().map(|g| g.iter().map(|s| s.file.as_str()).collect()).collect()
+        }
+
+        #[test]
+        fn exposures() {
+            assert!((parse_exposure("+2/3").unwrap() - 2.0 / 3.0).abs() < 1e-6);
+            assert_eq!(parse_exposure("-1"), Some(-1.0));
+            assert_eq!(parse_exposure(" +0.7 "), Some(0.7));
+            assert_eq!(parse_exposure("-1/3").map(|v| (v * 3.0).round()), Some(-1.0));
+            assert_eq!(parse_exposure("1/0"), None);
+            assert_eq!(parse_exposure("1/"), None);
+            assert_eq!(parse_exposure("bright"), None);
+        }
+
+        #[test]
+        fn grouping() {
+            // a repeated exposure starts the next bracket, the files are
+            // ordered by time first
+            let shots: Vec<Shot> = [-1.0, 0.0, 1.0, -1.0, 0.0, 1.0]
+                .iter()
+                .enumerate()
+                .map(|(i, &e)| shot(&format!("{}.jpg", i), i as f64, Some(e), None))
+                .rev()
+                .collect();
+            assert_eq!(files(&group_shots(&shots, 10.0, true)), vec![vec!["0.jpg", "1.jpg", "2.jpg"], vec!["3.jpg", "4.jpg", "5.jpg"]]);
+            // a focus stack does not look at the exposures
+            assert_eq!(files(&group_shots(&shots, 10.0, false)).len(), 1);
+
+            // a gap in time ends a series
+            let shots = vec![shot("a", 0.0, None, None), shot("b", 1.5, None, None), shot("c", 30.0, None, None), shot("d", 31.0, None, None)];
+            assert_eq!(files(&group_shots(&shots, 2.0, true)), vec![vec!["a", "b"], vec!["c", "d"]]);
+
+            // a burst stays together across a gap and a repeated exposure
+            let shots = vec![
+                shot("a", 0.0, Some(0.0), Some("X")),
+                shot("b", 60.0, Some(0.0), Some("X")),
+                shot("c", 61.0, Some(1.0), Some("Y")),
+            ];
+            assert_eq!(files(&group_shots(&shots, 2.0, true)), vec![vec!["a", "b"], vec!["c"]]);
+        }
+
+        #[test]
+        fn middle_shots() {
+            let group = vec![shot("a", 0.0, Some(1.0), None), shot("b", 1.0, Some(-1.0), None), shot("c", 2.0, Some(0.0), None)];
+            assert_eq!(middle_shot(&group).file, "c");
+            let group = vec![shot("a", 0.0, Some(1.0), None), shot("b", 1.0, None, None), shot("c", 2.0, Some(0.0), None), shot("d", 3.0, None, None)];
+            assert_eq!(middle_shot(&group).file, "b");
+        }
+    }
+}
+
+pub use fusion::*;
@@ -0,0 +1,189 @@
+pub mod grayscale {
+
+    use imgproc_rs::image::{BaseImage, Image, ImageInfo};
+    pub use crate::config::*;
+
+    // the supported grayscale formulas
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum GrayMethod {
+        // ITU-R BT.601 luma: 0.299 R + 0.587 G + 0.114 B
+        Rec601,
+        // ITU-R BT.709 luma: 0.2126 R + 0.7152 G + 0.0722 B
+        Rec709,
+        // (R + G + B) / 3
+        Average,
+        // (max + min) / 2
+        Lightness,
+        // a single channel (0 = red, 1 = green, 2 = blue)
+        Channel(usize),
+        // custom weights, normalized to a sum of 1
+        Custom(f32, f32, f32),
+    }
+
+    // parses a grayscale method name or a list of custom weights like 0.5,0.3,0.2
+    pub fn parse_gray_method(spec: &str) -> Option<GrayMethod> {
+        let spec = spec.trim().to_lowercase();
+        match spec.as_str() {
+            "" | "rec601" | "bt601" | "601" | "luma" => Some(GrayMethod::Rec601),
+            "rec709" | "bt709" | "709" => Some(GrayMethod::Rec709),
+            "average" | "avg" | "mean" => Some(GrayMethod::Average),
+            "lightness" => Some(GrayMethod::Lightness),
+            "red" | "r" => Some(GrayMethod::Channel(0)),
+            "green" | "g" => Some(GrayMethod::Channel(1)),
+            "blue" | "b" => Some(GrayMethod::Channel(2)),
+            _ => {
+                let weights: Vec<f32> = spec
+                    .trim_start_matches("custom:")
+                    .split(',')
+                    .map(|w| w.trim().parse::<f32>())
+                    .collect::<Result<_, _>>()
+                    .ok()?;
+                if weights.len() != 3 {
+                    return None;
+                }
+                let sum = weights[0] + weights[1] + weights[2];
+                if sum <= 0.0 {
+                    return None;
+                }
+                Some(GrayMethod::Custom(
+                    weights[0] / sum,
+                    weights[1] / sum,
+                    weights[2] / sum,
+                ))
+            }
+        }
+    }
+
+    // converts an sRGB encoded value (0-255) to linear light (0.0-1.0)
+    pub fn srgb_to_linear(value: u8) -> f32 {
+        let v = value as f32 / 255.0;
+        if v <= 0.04045 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        }
+    }
+
+    // converts a linear light value (0.0-1.0) to sRGB encoding (0.0-255.0)
+    pub fn linear_to_srgb(value: f32) -> f32 {
+        let v = value.clamp(0.0, 1.0);
+        let encoded = if v <= 0.0031308 {
+            v * 12.92
+        } else {
+            1.055 * v.powf(1.0 / 2.4) - 0.055
+        };
+        encoded * 255.0
+    }
+
+    // rounds and clamps a value to the 0-255 range
+    pub fn round_u8(value: f32) -> u8 {
+        value.round().clamp(0.0, 255.0) as u8
+    }
+
+    // computes the gray value of an RGB pixel
+    // with `linear` set the weights are applied in linear light and the
+    // result is encoded back to sRGB
+    pub fn gray_value(pixel: &[u8], method: GrayMethod, linear: bool) -> u8 {
+        if pixel.len() < 3 {
+            return pixel[0];
+        }
+        let (r, g, b) = if linear {
+            (
+                srgb_to_linear(pixel[0]),
+                srgb_to_linear(pixel[1]),
+                srgb_to_linear(pixel[2]),
+            )
+        } else {
+            (pixel[0] as f32, pixel[1] as f32, pixel[2] as f32)
+        };
+
+        let value = match method {
+            GrayMethod::Rec601 => 0.299 * r + 0.587 * g + 0.114 * b,
+            GrayMethod::Rec709 => 0.2126 * r + 0.7152 * g + 0.0722 * b,
+            GrayMethod::Average => (r + g + b) / 3.0,
+            GrayMethod::Lightness => (r.max(g).max(b) + r.min(g).min(b)) / 2.0,
+            GrayMethod::Channel(c) => [r, g, b][c.min(2)],
+            GrayMethod::Custom(wr, wg, wb) => wr * r + wg * g + wb * b,
+        };
+
+        if linear {
+            round_u8(linear_to_srgb(value))
+        } else {
+            round_u8(value)
+        }
+    }
+
+    // converts an RGB image to a single channel image
+    pub fn to_grayscale(img: &Image<u8>, method: GrayMethod, linear: bool) -> Image<u8> {
+        let (width, height) = img.info().wh();
+        let mut img2 = Image::blank(ImageInfo::new(width, height, 1, false));
+        for y in 0..height {
+            for x in 0..width {
+                let value = gray_value(img.get_pixel(x, y), method, linear);
+                img2.set_pixel(x, y, &[value]);
+            }
+        }
+        img2
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn rec601_primaries() {
+            assert_eq!(gray_value(&[255, 0, 0], GrayMethod::Rec601, false), 76);
+            assert_eq!(gray_value(&[0, 255, 0], GrayMethod::Rec601, false), 150);
+            assert_eq!(gray_value(&[0, 0, 255], GrayMethod::Rec601, false), 29);
+            assert_eq!(gray_value(&[255, 255, 255], GrayMethod::Rec601, false), 255);
+            assert_eq!(gray_value(&[0, 0, 0], GrayMethod::Rec601, false), 0);
+        }
+
+        #[test]
+        fn rec709_primaries() {
+            assert_eq!(gray_value(&[255, 0, 0], GrayMethod::Rec709, false), 54);
+            assert_eq!(gray_value(&[0, 255, 0], GrayMethod::Rec709, false), 182);
+            assert_eq!(gray_value(&[0, 0, 255], GrayMethod::Rec709, false), 18);
+            assert_eq!(gray_value(&[255, 255, 255], GrayMethod::Rec709, false), 255);
+        }
+
+        #[test]
+        fn average_lightness_and_channel() {
+            assert_eq!(gray_value(&[10, 20, 31], GrayMethod::Average, false), 20);
+            assert_eq!(gray_value(&[10, 20, 31], GrayMethod::Lightness, false), 21);
+            assert_eq!(gray_value(&[10, 20, 31], GrayMethod::Channel(0), false), 10);
+            assert_eq!(gray_value(&[10, 20, 31], GrayMethod::Channel(1), false), 20);
+            assert_eq!(gray_value(&[10, 20, 31], GrayMethod::Channel(2), false), 31);
+        }
+
+        #[test]
+        fn custom_weights_are_normalized() {
+            let method = parse_gray_method("1,1,2").unwrap();
+            assert_eq!(method, GrayMethod::Custom(0.25, 0.25, 0.5));
+            assert_eq!(gray_value(&[100, 200, 50], method, false), 100);
+            assert_eq!(parse_gray_method("1,2"), None);
+            assert_eq!(parse_gray_method("0,0,0"), None);
+        }
+
+        #[test]
+        fn linear_light() {
+            // neutral colors survive the round trip
+            for v in [0, 1, 10, 64, 128, 200, 255] {
+                assert_eq!(gray_value(&[v, v, v], GrayMethod::Rec709, true), v);
+            }
+            assert_eq!(gray_value(&[255, 0, 0], GrayMethod::Rec709, true), 127);
+            assert_eq!(gray_value(&[0, 255, 0], GrayMethod::Rec709, true), 220);
+            assert_eq!(gray_value(&[0, 0, 255], GrayMethod::Rec709, true), 76);
+        }
+
+        #[test]
+        fn converts_image() {
+            let img = Image::from_slice(2, 1, 3, false, &[255, 0, 0, 0, 0, 255]);
+            let gray = to_grayscale(&img, GrayMethod::Rec601, false);
+            assert_eq!(gray.info().whc(), (2, 1, 1));
+            assert_eq!(gray.data(), &[76, 29]);
+        }
+    }
+}
+
+pub use grayscale::*;
@@ -6,6 +6,17 @@ pub mod imageaction {
     pub use crate::config::*;
     pub use crate::rustyexif::*;
     pub use crate::rustyimg::*;
+    use crate::bilevel::*;
+    use crate::threshold::*;
+    use crate::watermark::*;
+    use crate::lens::*;
+    use crate::align::*;
+    use crate::fusion::*;
+    use crate::focus::*;
+    use crate::panorama::*;
+    use crate::montage::*;
+    use crate::animation::*;
+    use crate::color::parse_color;
 
     pub use config::*;
 
@@ -14,9 +25,9 @@ pub mod imageaction {
     use std::path::Path;
 
     use filetime::{set_file_mtime, FileTime};
-    use imgproc_rs::image::{Image, ImageInfo};
+    use imgproc_rs::image::{BaseImage, Image, ImageInfo};
     use imgproc_rs::io;
-    use libheif_rs::{Channel, ColorSpace, HeifContext, RgbChroma};
+    use libheif_rs::{Channel, ColorSpace, HeifContext, ImageHandle, ItemId, RgbChroma};
 
     pub use std::fs::*;
 
@@ -83,6 +94,8 @@ pub mod imageaction {
                     return 0;
                 }
 
+                // lens corrections come before anything else
+                let image = correct_lens(image, src_file, opts);
                 let transformed_img = process_image(&image, opts);
 
                 if save_image(&transformed_img, src_file, &dst_file, &opts) == 1 {
@@ -98,6 +111,451 @@ pub mod imageaction {
         }
     }
 
+    /**
+     * Adds a watermark to an image, the text defaults to the artist
+     */
+    pub fn watermark_image(src_file: &str, opts: &ConfigOptions) -> u8 {
+        let artist = config::option("artist", "");
+        if opts.watermark.is_empty() && opts.watermark_text.is_empty() && artist.is_empty() {
+            println!("No watermark specified, use --watermark, --watermark-text or --artist");
+            return 0;
+        }
+
+        match get_dest_name(src_file, opts) {
+            Some(result) => {
+                let (dst_file, _base_name, _dir_name, ext) = result;
+                let image = match read_image(src_file, &ext) {
+                    Some(i) => i,
+                    None => {
+                        println!("Error reading image {}", opts.src_file);
+                        return 0;
+                    }
+                };
+
+                let watermarked = apply_watermark(image, opts, &artist);
+
+                if save_image(&watermarked, src_file, &dst_file, opts) == 1 {
+                    log!(" > Image watermarked succesfully");
+                    1
+                } else {
+                    0
+                }
+            }
+            None => 0,
+        }
+    }
+
+    /**
+     * Fuses the bracketed shots in a folder, one result per bracket saved
+     * next to its middle exposure and with its metadata
+     */
+    pub fn fuse_folder(folder: &str, opts: &ConfigOptions) -> u8 {
+        let gap = match opts.bracket_gap.trim().parse::<f64>() {
+            Ok(g) if g >= 0.0 => g,
+            _ => {
+                println!("Invalid bracket gap {}, using 2 seconds", opts.bracket_gap);
+                2.0
+            }
+        };
+
+        // an earlier result carries the EXIF data of its middle shot and
+        // would join the bracket again
+        let mut shots = read_folder_shots(folder);
+        shots.retain(|s| !is_merged_name(&s.file, opts, "fused"));
+
+        let mut count = 0;
+        for group in group_shots(&shots, gap, true) {
+            if group.len() < 2 {
+                log!(" > Skipping {}, not part of a bracket", group[0].file);
+                continue;
+            }
+            let middle = middle_shot(&group);
+            log!("Fusing {} shots around {}", group.len(), middle.file);
+            let images = match read_aligned_series(&group, &middle.file) {
+                Some(i) => i,
+                None => continue,
+            };
+
+            let fused = fuse_exposures(&images);
+            let dst_file = get_merged_name(&middle.file, opts, "fused");
+            if save_image(&fused, &middle.file, &dst_file, opts) == 1 {
+                log!(" > Bracket fused succesfully to {}", dst_file);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /**
+     * Focus stacks the series of shots in a folder, one result per stack
+     * saved next to its middle shot and with its metadata
+     */
+    pub fn stack_folder(folder: &str, opts: &ConfigOptions) -> u8 {
+        let gap = match opts.stack_gap.trim().parse::<f64>() {
+            Ok(g) if g >= 0.0 => g,
+            _ => {
+                println!("Invalid stack gap {}, using 10 seconds", opts.stack_gap);
+                10.0
+            }
+        };
+
+        // an earlier result has the time of its middle shot and would
+        // always join the stack again
+        let mut shots = read_folder_shots(folder);
+        shots.retain(|s| !is_merged_name(&s.file, opts, "stacked"));
+
+        let mut count = 0;
+        for group in group_shots(&shots, gap, false) {
+            if group.len() < 2 {
+                log!(" > Skipping {}, not part of a stack", group[0].file);
+                continue;
+            }
+            let middle = middle_shot(&group);
+            log!("Stacking {} shots around {}", group.len(), middle.file);
+            let images = match read_aligned_series(&group, &middle.file) {
+                Some(i) => i,
+                None => continue,
+            };
+
+            let stacked = stack_focus(&images);
+            let dst_file = get_merged_name(&middle.file, opts, "stacked");
+            if save_image(&stacked, &middle.file, &dst_file, opts) == 1 {
+                log!(" > Stack merged succesfully to {}", dst_file);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /**
+     * Stitches the images in a folder, in name order from left to right,
+     * into a panorama saved next to the middle image
+     */
+    pub fn stitch_folder(folder: &str, opts: &ConfigOptions) -> u8 {
+        let filter = Vec::from([String::from("jpg"), String::from("jpeg"), String::from("heic")]);
+        let mut files = get_files_in_folder(folder, filter);
+        files.sort();
+        // leave out the panoramas of an earlier run
+        files.retain(|f| !is_merged_name(f, opts, "panorama"));
+        if files.len() < 2 {
+            println!("Nothing to stitch in {}", folder);
+            return 0;
+        }
+        let middle_file = &files[(files.len() - 1) / 2];
+
+        // the 35mm equivalent focal length sets the cylinder
+        let focal_35mm = if !opts.focal.is_empty() {
+            opts.focal.trim().parse::<f32>().ok().filter(|&f| f > 0.0)
+        } else {
+            let fields = rustyexif::read_exif_from_file(&files[0]);
+            get_exif_value(&fields, "FocalLengthIn35mmFormat")
+                .and_then(|v| v.split_whitespace().next().and_then(|f| f.parse::<f32>().ok()))
+                .filter(|&f| f > 0.0)
+        };
+        let focal_35mm = match focal_35mm {
+            Some(f) => f,
+            None => {
+                println!("No focal length found, assuming 28mm, use --focal to set it");
+                28.0
+            }
+        };
+
+        let images = match read_series(&files, middle_file) {
+            Some(i) => i,
+            None => return 0,
+        };
+        let (width, height) = images[0].info().wh();
+        log!("Stitching {} images at {}mm", images.len(), focal_35mm);
+
+        match stitch_images(&images, focal_pixels(width, height, focal_35mm)) {
+            Some(panorama) => {
+                let dst_file = get_merged_name(middle_file, opts, "panorama");
+                if save_image(&panorama, middle_file, &dst_file, opts) == 1 {
+                    log!(" > Panorama stitched succesfully to {}", dst_file);
+                    1
+                } else {
+                    0
+                }
+            }
+            None => 0,
+        }
+    }
+
+    /**
+     * Lays out thumbnails of the images in a folder on contact sheets, as
+     * many pages as needed
+     */
+    pub fn contact_sheet_folder(folder: &str, opts: &ConfigOptions) -> u8 {
+        let captions = match parse_captions(&opts.captions) {
+            Some(c) => c,
+            None => {
+                println!("Invalid captions {}, using filename,date", opts.captions);
+                vec![Caption::Filename, Caption::Date]
+            }
+        };
+        let layout = match sheet_layout(opts, captions.len() as u32) {
+            Some(l) => l,
+            None => return 0,
+        };
+        let base_name = if opts.suffix.is_empty() { "contact_sheet" } else { opts.suffix.as_str() };
+        let format = if opts.format.is_empty() { String::from("jpg") } else { opts.format.to_lowercase() };
+
+        let filter = Vec::from([String::from("jpg"), String::from("jpeg"), String::from("heic")]);
+        let mut files = get_files_in_folder(folder, filter);
+        files.sort();
+        // leave out the sheets of an earlier run
+        files.retain(|f| {
+            let stem = Path::new(f).file_stem().and_then(OsStr::to_str).unwrap_or("");
+            !stem.starts_with(&format!("{}_", base_name))
+        });
+
+        let mut thumbs = Vec::new();
+        for file in files.iter() {
+            let ext = get_filename_extension(file).unwrap_or("").to_string();
+            let image = match read_image(file, &ext) {
+                Some(i) => i,
+                None => {
+                    println!("Error reading image {}", file);
+                    continue;
+                }
+            };
+            log!(" > Thumbnail of {}", file);
+            let lines: Vec<String> = captions
+                .iter()
+                .map(|caption| match caption {
+                    Caption::Filename => Path::new(file).file_name().and_then(OsStr::to_str).unwrap_or("").to_string(),
+                    Caption::Date => {
+                        let fields = rustyexif::read_exif_from_file(file);
+                        get_exif_value(&fields, "DateTimeOriginal")
+                            .and_then(|d| chrono::NaiveDateTime::parse_from_str(d.get(0..19)?, "%Y:%m:%d %H:%M:%S").ok())
+                            .map(|d| d.format("%Y-%m-%d %H:%M").to_string())
+                            .unwrap_or_default()
+                    }
+                })
+                .collect();
+            thumbs.push((make_thumbnail(&image, layout.thumb_size), lines));
+        }
+        if thumbs.is_empty() {
+            println!("No images found in {}", folder);
+            return 0;
+        }
+
+        let mut count = 0;
+        for (page, sheet) in contact_sheets(&thumbs, &layout).iter().enumerate() {
+            let dst_file = format!("{}/{}_{:02}.{}", folder.trim_end_matches('/'), base_name, page + 1, format);
+            match write_image(sheet, &dst_file) {
+                Ok(_) => {
+                    log!(" > Contact sheet written to {}", dst_file);
+                    count += 1;
+                }
+                Err(e) => println!("Error writing image {}: {}", dst_file, e),
+            }
+        }
+        count
+    }
+
+    /**
+     * Animates the images in a folder, in name order, or the images stored
+     * in a HEIC file, saving a GIF or WebP next to them
+     */
+    pub fn animate_images(src: &str, opts: &ConfigOptions) -> u8 {
+        let format = if opts.format.is_empty() { String::from("gif") } else { opts.format.to_lowercase() };
+        if format != "gif" && format != "webp" {
+            println!("Invalid animation format {}, use gif or webp", opts.format);
+            return 0;
+        }
+        let delays = match parse_delays(&opts.delay) {
+            Some(d) => d,
+            None => {
+                println!("Invalid delay {}, using 500", opts.delay);
+                vec![500]
+            }
+        };
+        let loops = match opts.loops.trim().parse::<u16>() {
+            Ok(l) => l,
+            Err(_) => {
+                println!("Invalid loops {}, playing forever", opts.loops);
+                0
+            }
+        };
+        let colors = match opts.colors.trim().parse::<usize>() {
+            Ok(c) if (2..=256).contains(&c) => c,
+            _ => {
+                println!("Invalid colors {}, using 256", opts.colors);
+                256
+            }
+        };
+        let dither = match opts.dither.trim().to_lowercase().as_str() {
+            "floyd-steinberg" => true,
+            "none" => false,
+            _ => {
+                println!("Invalid dither {}, using floyd-steinberg", opts.dither);
+                true
+            }
+        };
+        let background = match parse_color(&opts.background) {
+            Some(c) => c,
+            None => {
+                println!("Invalid background color {}, using white", opts.background);
+                [255, 255, 255]
+            }
+        };
+        let base_name = if opts.suffix.is_empty() { "animation" } else { opts.suffix.as_str() };
+
+        let (images, dst_file) = match get_filename_extension(src) {
+            Some(ext) if ext.to_lowercase() == "heic" => {
+                let stem = Path::new(src).file_stem().and_then(OsStr::to_str).unwrap_or("");
+                let dst_file = Path::new(src).with_file_name(format!("{}_{}.{}", stem, base_name, format));
+                (read_heic_images(src), dst_file.to_str().unwrap_or("").to_string())
+            }
+            Some(_) => {
+                println!("The animate action needs a folder of images or a HEIC file");
+                return 0;
+            }
+            None => {
+                let filter = Vec::from([String::from("jpg"), String::from("jpeg"), String::from("heic")]);
+                let mut files = get_files_in_folder(src, filter);
+                files.sort();
+                let images = files
+                    .iter()
+                    .filter_map(|file| {
+                        let ext = get_filename_extension(file).unwrap_or("").to_string();
+                        let image = read_image(file, &ext);
+                        if image.is_none() {
+                            println!("Error reading image {}", file);
+                        }
+                        image
+                    })
+                    .collect();
+                (images, format!("{}/{}.{}", src.trim_end_matches('/'), base_name, format))
+            }
+        };
+        if images.len() < 2 {
+            println!("Not enough images to animate in {}", src);
+            return 0;
+        }
+
+        let (first_width, first_height) = images[0].info().wh();
+        let (width, height) = match parse_frame_size(&opts.frame_size, first_width, first_height) {
+            Some(s) => s,
+            None => {
+                println!("Invalid frame size {}, using 640", opts.frame_size);
+                parse_frame_size("", first_width, first_height).unwrap()
+            }
+        };
+        if format == "gif" && (width > u16::MAX as u32 || height > u16::MAX as u32) {
+            println!("GIF frames are at most {} pixels wide and high", u16::MAX);
+            return 0;
+        }
+        log!("Animating {} frames of {}x{}", images.len(), width, height);
+        let frames: Vec<Frame> = images.iter().map(|img| fit_frame(img, width, height, background)).collect();
+
+        let result = if format == "gif" {
+            let palette = median_cut_palette(&frames, colors);
+            let indexed: Vec<Vec<u8>> = frames.iter().map(|f| quantize_frame(f, width, &palette, dither)).collect();
+            write_gif(&dst_file, &indexed, &palette, width, height, &delays, loops)
+        } else {
+            write_webp(&dst_file, &frames, width, height, &delays, loops, background)
+        };
+        match result {
+            Ok(_) => {
+                log!(" > Animation written to {}", dst_file);
+                1
+            }
+            Err(e) => {
+                println!("Error writing animation {}: {}", dst_file, e);
+                0
+            }
+        }
+    }
+
+    /**
+     * Reads the shot details of the images in a folder
+     */
+    fn read_folder_shots(folder: &str) -> Vec<Shot> {
+        let filter = Vec::from([String::from("jpg"), String::from("jpeg"), String::from("heic")]);
+        get_files_in_folder(folder, filter)
+            .iter()
+            .map(|file| read_shot(file))
+            .collect()
+    }
+
+    /**
+     * Reads a series of shots and aligns them with the reference shot
+     */
+    fn read_aligned_series(group: &[Shot], reference_file: &str) -> Option<Vec<Image<u8>>> {
+        let files: Vec<String> = group.iter().map(|s| s.file.clone()).collect();
+        let mut images = read_series(&files, reference_file)?;
+
+        let reference = group.iter().position(|s| s.file == reference_file).unwrap_or(0);
+        for i in 0..images.len() {
+            if i != reference {
+                let (dx, dy) = estimate_shift(&images[reference], &images[i]);
+                log!(" > {} shifted by {:.1}, {:.1}", group[i].file, dx, dy);
+                images[i] = shift_image(&images[i], dx, dy);
+            }
+        }
+        Some(images)
+    }
+
+    /**
+     * Reads a series of shots, which must all have the same size
+     */
+    fn read_series(files: &[String], reference_file: &str) -> Option<Vec<Image<u8>>> {
+        let mut images = Vec::new();
+        for file in files {
+            let ext = get_filename_extension(file).unwrap_or("").to_string();
+            match read_image(file, &ext) {
+                Some(i) => images.push(i),
+                None => {
+                    println!("Error reading image {}", file);
+                    return None;
+                }
+            }
+        }
+        let size = images[0].info().wh();
+        let colors = images[0].info().channels_non_alpha();
+        if images.iter().any(|i| i.info().wh() != size || i.info().channels_non_alpha() != colors) {
+            println!("Skipping the series of {}, the shots differ in size", reference_file);
+            return None;
+        }
+        Some(images)
+    }
+
+    /**
+     * The name of an image merged from several shots, next to the given
+     * shot and never replacing it
+     */
+    fn get_merged_name(src_file: &str, opts: &ConfigOptions, default_suffix: &str) -> String {
+        let base_name = Path::new(src_file).file_stem().unwrap().to_str().unwrap();
+        let dir_name = Path::new(src_file).parent().unwrap().to_str().unwrap();
+        let ext = get_filename_extension(src_file).unwrap_or("jpg").to_lowercase();
+        let suffix = if opts.suffix.is_empty() { default_suffix } else { opts.suffix.as_str() };
+        let format = if !opts.format.is_empty() {
+            opts.format.to_lowercase()
+        } else if ext == "heic" {
+            String::from("jpg")
+        } else {
+            ext
+        };
+        let mut dst_file = String::from("");
+        if !dir_name.is_empty() {
+            dst_file.push_str(dir_name);
+            dst_file.push('/');
+        }
+        dst_file.push_str(&format!("{}_{}.{}", base_name, suffix, format));
+        dst_file
+    }
+
+    /**
+     * Tells if a file is the result of an earlier run, as named by get_merged_name
+     */
+    fn is_merged_name(file: &str, opts: &ConfigOptions, default_suffix: &str) -> bool {
+        let suffix = if opts.suffix.is_empty() { default_suffix } else { opts.suffix.as_str() };
+        let stem = Path::new(file).file_stem().and_then(OsStr::to_str).unwrap_or("");
+        stem.ends_with(&format!("_{}", suffix))
+    }
+
     // * EXIF functions //
 
     /**
@@ -289,29 +747,7 @@ pub mod imageaction {
                 }
             };
             let handle = ctx.primary_image_handle().unwrap();
-            match handle.decode(ColorSpace::Rgb(RgbChroma::Rgb), None) {
-                Ok(heif_img) => {
-                    let w = heif_img.width(Channel::Interleaved).unwrap();
-                    let h = heif_img.height(Channel::Interleaved).unwrap();
-                    let mut image = Image::blank(ImageInfo::new(w, h, 3, false));
-                    let planes = heif_img.planes();
-                    let interleaved_plane = planes.interleaved.unwrap();
-                    let stride = interleaved_plane.stride;
-                    let data = interleaved_plane.data;
-
-                    // copy data to image
-                    for y in 0..h {
-                        for x in 0..w {
-                            let offset = (y * stride as u32 + x * 3) as usize;
-                            let pixel = &[data[offset], data[offset + 1], data[offset + 2]];
-                            image.set_pixel(x, y, pixel);
-                        }
-                    }
-
-                    return Some(image);
-                }
-                Err(_e) => None,
-            }
+            decode_heif_image(&handle)
         } else {
             match io::read(src_file) {
                 Ok(i) => Some(i),
@@ -320,6 +756,55 @@ pub mod imageaction {
         }
     }
 
+    /**
+     * Reads all the images stored in a HEIC file, e.g. a burst
+     */
+    pub fn read_heic_images(src_file: &str) -> Vec<Image<u8>> {
+        let ctx = match HeifContext::read_from_file(src_file) {
+            Ok(c) => c,
+            Err(e) => {
+                println!("Error reading heic file: {}", e);
+                return Vec::new();
+            }
+        };
+        let mut ids: Vec<ItemId> = vec![0; ctx.number_of_top_level_images()];
+        let count = ctx.top_level_image_ids(&mut ids);
+        ids[..count]
+            .iter()
+            .filter_map(|&id| ctx.image_handle(id).ok())
+            .filter_map(|handle| decode_heif_image(&handle))
+            .collect()
+    }
+
+    /**
+     * Decodes a HEIC image to RGB
+     */
+    fn decode_heif_image(handle: &ImageHandle) -> Option<Image<u8>> {
+        match handle.decode(ColorSpace::Rgb(RgbChroma::Rgb), None) {
+            Ok(heif_img) => {
+                let w = heif_img.width(Channel::Interleaved).unwrap();
+                let h = heif_img.height(Channel::Interleaved).unwrap();
+                let mut image = Image::blank(ImageInfo::new(w, h, 3, false));
+                let planes = heif_img.planes();
+                let interleaved_plane = planes.interleaved.unwrap();
+                let stride = interleaved_plane.stride;
+                let data = interleaved_plane.data;
+
+                // copy data to image
+                for y in 0..h {
+                    for x in 0..w {
+                        let offset = (y * stride as u32 + x * 3) as usize;
+                        let pixel = &[data[offset], data[offset + 1], data[offset + 2]];
+                        image.set_pixel(x, y, pixel);
+                    }
+                }
+
+                Some(image)
+            }
+            Err(_e) => None,
+        }
+    }
+
     /**
      * Saves an image to a file, preserving EXIF data if possible
      */
@@ -327,48 +812,56 @@ pub mod imageaction {
         let target = dst_file.replace(".heic", ".jpg");
 
         // write as <filename>_bw.<ext>
-        match io::write(&img, &target) {
+        match write_image(&img, &target) {
             Ok(_) => {
                 if opts.noexif == false {
                     // copy exif data
                     let exif_fields = rustyexif::read_exif_from_file(src_file);
-                    if opts.grayscale {
-                        // set the color space to grayscale
-                        let mut fields = Vec::new();
-                        for field in exif_fields.iter() {
-                            if field.name == "ColorSpaceData" {
-                                fields.push(ExifField {
-                                    name: field.name.clone(),
-                                    value: "GRAY".to_string(),
-                                });
-                                continue;
-                            }
-                            if field.name == "ColorSpace" {
-                                fields.push(ExifField {
-                                    name: field.name.clone(),
-                                    value: "1".to_string(),
-                                });
-                                continue;
-                            }
-
-                            fields.push(ExifField {
-                                name: field.name.clone(),
-                                value: field.value.clone(),
-                            });
-                        }
-                        return rustyexif::write_exif_to_file(&target, fields);
+                    let (width, height) = img.info().wh();
+                    // the grayscale result may have been toned again
+                    let gray = is_grayscale_image(img);
+                    let mut fields = Vec::new();
+                    for field in exif_fields.iter() {
+                        let value = match field.name.as_str() {
+                            // set the color space to grayscale
+                            "ColorSpaceData" if gray => "GRAY".to_string(),
+                            "ColorSpace" if gray => "1".to_string(),
+                            // the image may have been cropped
+                            "ImageWidth" | "ExifImageWidth" => width.to_string(),
+                            "ImageHeight" | "ExifImageHeight" => height.to_string(),
+                            _ => field.value.clone(),
+                        };
+                        fields.push(ExifField {
+                            name: field.name.clone(),
+                            value,
+                        });
                     }
-                    return rustyexif::write_exif_to_file(&target, exif_fields);
+                    return rustyexif::write_exif_to_file(&target, fields);
                 }
                 return 1;
             }
             Err(e) => {
-                println!("Error writing image {}: {:?}", target, e);
+                println!("Error writing image {}: {}", target, e);
                 return 0;
             }
         }
     }
 
+    /**
+     * Writes an image, black and white images go to 1-bit PNG or CCITT G4 TIFF
+     */
+    fn write_image(img: &Image<u8>, target: &str) -> Result<(), String> {
+        let ext = get_filename_extension(target).unwrap_or("").to_lowercase();
+        if is_binary_image(img) {
+            match ext.as_str() {
+                "png" => return write_png_1bit(img, target),
+                "tif" | "tiff" => return write_tiff_g4(img, target),
+                _ => {}
+            }
+        }
+        io::write(img, target).map_err(|e| format!("{:?}", e))
+    }
+
     /**
      * Returns the extension of a file name
      */
@@ -427,7 +920,9 @@ pub mod imageaction {
         dst_file.push_str(".");
 
         // cannot save to HEIC format yet
-        if ext == "heic" {
+        if !opts.format.is_empty() {
+            dst_file.push_str(&opts.format.to_lowercase());
+        } else if ext == "heic" {
             dst_file.push_str("jpg");
         } else {
             dst_file.push_str(ext);
@@ -0,0 +1,248 @@
+pub mod lens {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    use std::fs;
+    pub use crate::config::*;
+
+    use crate::aberration::*;
+    use crate::grayscale::*;
+    use crate::rustyexif::*;
+    use crate::warp::*;
+    use crate::log;
+
+    // the corrections for one lens
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct LensProfile {
+        // radial distortion, negative for barrel and positive for pincushion
+        pub k1: f32,
+        pub k2: f32,
+        // how much the corners are darkened, 0 to 100 percent
+        pub vignette: f32,
+        // where the darkening starts, 0 to 100 percent of the half diagonal
+        pub vignette_radius: f32,
+    }
+
+    // the strength is -100 to 100 percent and the radius 0 to under 100
+    fn valid_vignette(strength: f32, radius: f32) -> bool {
+        (-100.0..=100.0).contains(&strength) && (0.0..100.0).contains(&radius)
+    }
+
+    // parses STRENGTH[,RADIUS], e.g. 30,50; a negative strength brightens
+    // the corners, undoing the same positive vignette (default radius 50)
+    pub fn parse_vignette(spec: &str) -> Option<(f32, f32)> {
+        let values: Vec<f32> = spec
+            .split(',')
+            .map(|v| v.trim().parse::<f32>())
+            .collect::<Result<_, _>>()
+            .ok()?;
+        let strength = *values.first()?;
+        let radius = *values.get(1).unwrap_or(&50.0);
+        if values.len() > 2 || !valid_vignette(strength, radius) {
+            return None;
+        }
+        Some((strength, radius))
+    }
+
+    // parses K1[,K2], e.g. -0.08,0.01
+    pub fn parse_distortion(spec: &str) -> Option<(f32, f32)> {
+        let values: Vec<f32> = spec
+            .split(',')
+            .map(|v| v.trim().parse::<f32>())
+            .collect::<Result<_, _>>()
+            .ok()?;
+        if values.is_empty() || values.len() > 2 {
+            return None;
+        }
+        Some((values[0], *values.get(1).unwrap_or(&0.0)))
+    }
+
+    // reads a JSON table of lens profiles keyed by the EXIF LensModel, e.g.
+    // {"EF-S18-55mm f/3.5-5.6 IS II": {"k1": -0.06, "k2": 0.01, "vignette": 35}}
+    pub fn read_lens_profiles(path: &str) -> Option<Vec<(String, LensProfile)>> {
+        let text = fs::read_to_string(path).ok()?;
+        parse_lens_profiles(&text)
+    }
+
+    // the profiles of a JSON table; entries with values that are not numbers
+    // or are out of the vignette option's ranges are reported and skipped
+    pub fn parse_lens_profiles(text: &str) -> Option<Vec<(String, LensProfile)>> {
+        let parsed = json::parse(text).ok()?;
+        if !parsed.is_object() {
+            return None;
+        }
+        let mut profiles = Vec::new();
+        for (model, entry) in parsed.entries() {
+            // a missing value is the default, anything else must be a number
+            let value = |key: &str, default: f32| match &entry[key] {
+                json::JsonValue::Null => Some(default),
+                v => v.as_f32().filter(|v| v.is_finite()),
+            };
+            let profile = match (
+                entry.is_object(),
+                value("k1", 0.0),
+                value("k2", 0.0),
+                value("vignette", 0.0),
+                value("vignette_radius", 50.0),
+            ) {
+                (true, Some(k1), Some(k2), Some(vignette), Some(vignette_radius))
+                    if valid_vignette(vignette, vignette_radius) =>
+                {
+                    LensProfile { k1, k2, vignette, vignette_radius }
+                }
+                _ => {
+                    println!("Invalid lens profile {}: {}", model, entry.dump());
+                    continue;
+                }
+            };
+            profiles.push((model.to_string(), profile));
+        }
+        Some(profiles)
+    }
+
+    // finds the profile of a lens model, ignoring case and spacing
+    pub fn find_lens_profile(profiles: &[(String, LensProfile)], model: &str) -> Option<LensProfile> {
+        let normalize = |s: &str| s.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+        let model = normalize(model);
+        profiles.iter().find(|(name, _)| normalize(name) == model).map(|(_, p)| *p)
+    }
+
+    // darkens (positive strength) or brightens (negative strength) towards
+    // the corners in linear light; the falloff starts at `radius` percent
+    // of the half diagonal and is smooth up to the corners
+    pub fn vignette_image(img: &Image<u8>, strength: f32, radius: f32) -> Image<u8> {
+        let (width, height, channels, alpha) = img.info().whca();
+        let colors = img.info().channels_non_alpha() as usize;
+        let (cx, cy) = ((width as f32 - 1.0) / 2.0, (height as f32 - 1.0) / 2.0);
+        let half_diagonal = (cx * cx + cy * cy).sqrt().max(1.0);
+        let start = radius / 100.0;
+        let amount = strength.abs() / 100.0;
+        let linear: Vec<f32> = (0..=255).map(srgb_to_linear).collect();
+
+        let mut data = img.data().to_vec();
+        for y in 0..height {
+            for x in 0..width {
+                let (dx, dy) = (x as f32 - cx, y as f32 - cy);
+                let r = (dx * dx + dy * dy).sqrt() / half_diagonal;
+                if r <= start {
+                    continue;
+                }
+                let t = ((r - start) / (1.0 - start)).min(1.0);
+                let falloff = amount * t * t * (3.0 - 2.0 * t);
+                let gain = if strength >= 0.0 { 1.0 - falloff } else { 1.0 / (1.0 - falloff).max(0.01) };
+                let i = (y * width + x) as usize * channels as usize;
+                for value in data[i..i + colors].iter_mut() {
+                    *value = round_u8(linear_to_srgb(linear[*value as usize] * gain));
+                }
+            }
+        }
+        Image::from_vec(width, height, channels, alpha, data)
+    }
+
+    // corrects radial distortion: each output pixel is taken from where the
+    // lens put it, r * (1 + k1 r^2 + k2 r^4) with r relative to the half
+    // diagonal; the result is zoomed so no empty areas show
+    pub fn undistort_image(img: &Image<u8>, k1: f32, k2: f32) -> Image<u8> {
+        let (width, height) = img.info().wh();
+        let (cx, cy) = ((width as f32 - 1.0) / 2.0, (height as f32 - 1.0) / 2.0);
+        let half_diagonal = (cx * cx + cy * cy).sqrt().max(1.0);
+        let factor = |r: f32| 1.0 + k1 * r * r + k2 * r * r * r * r;
+        // the edge centers and corners must stay inside
+        let zoom = [cx / half_diagonal, cy / half_diagonal, 1.0]
+            .iter()
+            .map(|&r| factor(r))
+            .fold(1.0f32, f32::max);
+        warp_image(img, width, height, &[0, 0, 0, 255], |x, y| {
+            let (dx, dy) = (x - cx, y - cy);
+            let r = (dx * dx + dy * dy).sqrt() / half_diagonal;
+            let f = factor(r) / zoom;
+            Some((cx + dx * f, cy + dy * f))
+        })
+    }
+
+    // applies the lens profile for the source image's LensModel, the
+    // distortion and the color fringe options, before any other processing
+    pub fn correct_lens(img: Image<u8>, src_file: &str, opts: &ConfigOptions) -> Image<u8> {
+        let mut img = img;
+
+        if !opts.lens_profiles.is_empty() {
+            match read_lens_profiles(&opts.lens_profiles) {
+                Some(profiles) => {
+                    let fields = read_exif_from_file(src_file);
+                    let model = get_exif_value(&fields, "LensModel").unwrap_or_default();
+                    match find_lens_profile(&profiles, &model) {
+                        Some(profile) => {
+                            log!(" > Lens profile {}", model);
+                            if profile.k1 != 0.0 || profile.k2 != 0.0 {
+                                img = undistort_image(&img, profile.k1, profile.k2);
+                            }
+                            if profile.vignette != 0.0 {
+                                img = vignette_image(&img, -profile.vignette, profile.vignette_radius);
+                            }
+                        }
+                        None => log!(" > No lens profile for '{}'", model),
+                    }
+                }
+                None => println!("Error reading lens profiles {}", opts.lens_profiles),
+            }
+        }
+
+        if !opts.distortion.is_empty() {
+            match parse_distortion(&opts.distortion) {
+                Some((k1, k2)) => img = undistort_image(&img, k1, k2),
+                None => println!("Invalid distortion {}", opts.distortion),
+            }
+        }
+
+        // color fringes are measured on the undistorted image
+        apply_aberration(img, opts)
+    }
+
+    // runs the vignette option
+    pub fn apply_vignette(img: Image<u8>, opts: &ConfigOptions) -> Image<u8> {
+        if opts.vignette.is_empty() {
+            return img;
+        }
+        match parse_vignette(&opts.vignette) {
+            Some((strength, radius)) => vignette_image(&img, strength, radius),
+            None => {
+                println!("Invalid vignette {}", opts.vignette);
+                img
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn lens_profiles() {
+            let profiles = parse_lens_profiles(
+                r#"{
+                    "A": {"k1": -0.06, "k2": 0.01, "vignette": 35},
+                    "B": {"vignette": -20, "vignette_radius": 30},
+                    "C": {},
+                    "too bright": {"vignette": 150},
+                    "too dark": {"vignette": -101},
+                    "radius": {"vignette": 20, "vignette_radius": 100},
+                    "text": {"k1": "-0.06"},
+                    "not an object": 35
+                }"#,
+            )
+            .unwrap();
+            let profile = |k1, k2, vignette, vignette_radius| LensProfile { k1, k2, vignette, vignette_radius };
+            assert_eq!(
+                profiles,
+                vec![
+                    ("A".to_string(), profile(-0.06, 0.01, 35.0, 50.0)),
+                    ("B".to_string(), profile(0.0, 0.0, -20.0, 30.0)),
+                    ("C".to_string(), profile(0.0, 0.0, 0.0, 50.0)),
+                ]
+            );
+            assert_eq!(parse_lens_profiles("[1, 2]"), None);
+            assert_eq!(parse_lens_profiles("{"), None);
+        }
+    }
+}
+
+pub use lens::*;
@@ -0,0 +1,423 @@
+pub mod lut {
+
+    use imgproc_rs::image::{BaseImage, Image, ImageInfo};
+    pub use crate::config::*;
+
+    use std::fs;
+
+    use crate::grayscale::*;
+    use crate::tone::*;
+    use crate::debug;
+    use crate::log;
+
+    // how to look up colors between the points of a 3D LUT
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Interpolation {
+        Trilinear,
+        Tetrahedral,
+    }
+
+    // a LUT read from a .cube file; 3D tables have size^3 entries with red
+    // changing fastest, 1D tables have size entries
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct CubeLut {
+        pub size: usize,
+        pub is_3d: bool,
+        pub domain_min: [f32; 3],
+        pub domain_max: [f32; 3],
+        pub table: Vec<[f32; 3]>,
+    }
+
+    pub fn parse_interpolation(name: &str) -> Option<Interpolation> {
+        match name.trim().to_lowercase().as_str() {
+            "trilinear" => Some(Interpolation::Trilinear),
+            "" | "tetrahedral" => Some(Interpolation::Tetrahedral),
+            _ => None,
+        }
+    }
+
+    // applies a .cube or .acv file to an image
+    pub fn apply_lut_file(img: &Image<u8>, path: &str, interpolation: Interpolation) -> Option<Image<u8>> {
+        let ext = path.rsplit('.').next().unwrap_or("").to_lowercase();
+        if ext == "acv" {
+            let curves = read_acv(path)?;
+            log!(" > Applying {} curves from {}", curves.len(), path);
+            return Some(apply_luts(img, &acv_luts(&curves)));
+        }
+        if ext == "cube" {
+            let lut = read_cube(path)?;
+            log!(" > Applying {}{} LUT from {}", lut.size, if lut.is_3d { "^3" } else { "" }, path);
+            return Some(apply_cube(img, &lut, interpolation));
+        }
+        println!("Unsupported LUT file {}, use .cube or .acv", path);
+        None
+    }
+
+    // reads the curves of a Photoshop .acv file: the composite curve
+    // followed by the red, green and blue ones, as (input, output) points
+    pub fn read_acv(path: &str) -> Option<Vec<Vec<(f32, f32)>>> {
+        match fs::read(path) {
+            Ok(data) => parse_acv(&data, path),
+            Err(e) => {
+                println!("Error reading curves file {}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    fn parse_acv(data: &[u8], path: &str) -> Option<Vec<Vec<(f32, f32)>>> {
+        let word = |i: usize| -> Option<u16> {
+            match data.get(i..i + 2) {
+                Some(bytes) => Some(u16::from_be_bytes([bytes[0], bytes[1]])),
+                None => {
+                    println!("Invalid curves file {}: the data ends early", path);
+                    None
+                }
+            }
+        };
+
+        let mut curves = Vec::new();
+        let count = word(2)? as usize;
+        let mut offset = 4;
+        for _ in 0..count.min(4) {
+            let points = word(offset)? as usize;
+            offset += 2;
+            let mut curve = Vec::new();
+            for _ in 0..points {
+                // stored as output, input
+                let y = word(offset)? as f32;
+                let x = word(offset + 2)? as f32;
+                offset += 4;
+                curve.push((x.min(255.0), y.min(255.0)));
+            }
+            // the spline needs distinct inputs in order
+            curve.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            curve.dedup_by(|a, b| a.0 == b.0);
+            if curve.len() < 2 {
+                println!("Invalid curves file {}", path);
+                return None;
+            }
+            curves.push(curve);
+        }
+        if curves.is_empty() {
+            println!("No curves found in {}", path);
+            return None;
+        }
+        Some(curves)
+    }
+
+    // compiles .acv curves into per channel lookup tables,
+    // the channel curve is applied before the composite one
+    pub fn acv_luts(curves: &[Vec<(f32, f32)>]) -> [[u8; 256]; 3] {
+        let master = curve_lut(&curves[0]);
+        let mut luts = [master, master, master];
+        for (i, lut) in luts.iter_mut().enumerate() {
+            if let Some(curve) = curves.get(i + 1) {
+                *lut = compose_lut(&curve_lut(curve), &master);
+            }
+        }
+        luts
+    }
+
+    // reads an Adobe/Resolve .cube file with a 1D or 3D table
+    pub fn read_cube(path: &str) -> Option<CubeLut> {
+        match fs::read_to_string(path) {
+            Ok(text) => parse_cube(&text, path),
+            Err(e) => {
+                println!("Error reading LUT file {}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    fn parse_cube(text: &str, path: &str) -> Option<CubeLut> {
+        let mut lut = CubeLut {
+            size: 0,
+            is_3d: true,
+            domain_min: [0.0, 0.0, 0.0],
+            domain_max: [1.0, 1.0, 1.0],
+            table: Vec::new(),
+        };
+        let triple = |values: &[&str]| -> Option<[f32; 3]> {
+            if values.len() != 3 {
+                return None;
+            }
+            Some([
+                values[0].parse().ok()?,
+                values[1].parse().ok()?,
+                values[2].parse().ok()?,
+            ])
+        };
+
+        let domain = |values: &[&str]| -> Option<[f32; 3]> {
+            let value = triple(values);
+            if value.is_none() {
+                println!("Invalid LUT file {}: bad domain {}", path, values.join(" "));
+            }
+            value
+        };
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[0] {
+                "TITLE" => continue,
+                "LUT_3D_SIZE" | "LUT_1D_SIZE" => {
+                    lut.is_3d = words[0] == "LUT_3D_SIZE";
+                    lut.size = words.get(1).and_then(|w| w.parse().ok()).unwrap_or(0);
+                    // 256^3 entries are already far more than any LUT uses
+                    if lut.size < 2 || lut.size > if lut.is_3d { 256 } else { 65536 } {
+                        println!("Invalid LUT file {}: unsupported {}", path, line);
+                        return None;
+                    }
+                }
+                "DOMAIN_MIN" => lut.domain_min = domain(&words[1..])?,
+                "DOMAIN_MAX" => lut.domain_max = domain(&words[1..])?,
+                // the same range for all channels, written by Resolve
+                "LUT_3D_INPUT_RANGE" | "LUT_1D_INPUT_RANGE" => {
+                    let range = match (words.get(1).and_then(|w| w.parse::<f32>().ok()), words.get(2).and_then(|w| w.parse::<f32>().ok())) {
+                        (Some(min), Some(max)) if words.len() == 3 => (min, max),
+                        _ => {
+                            println!("Invalid LUT file {}: bad input range {}", path, line);
+                            return None;
+                        }
+                    };
+                    lut.domain_min = [range.0; 3];
+                    lut.domain_max = [range.1; 3];
+                }
+                _ => match triple(&words) {
+                    Some(entry) => lut.table.push(entry),
+                    None => {
+                        debug!(" > Skipping unknown LUT line {}", line);
+                    }
+                },
+            }
+        }
+
+        if (0..3).any(|c| lut.domain_max[c] <= lut.domain_min[c]) {
+            println!("Invalid LUT file {}: the domain maximum must be above the minimum", path);
+            return None;
+        }
+        let expected = if lut.is_3d { lut.size.pow(3) } else { lut.size };
+        if lut.size < 2 || lut.table.len() != expected {
+            println!(
+                "Invalid LUT file {}: expected {} entries, found {}",
+                path,
+                expected,
+                lut.table.len()
+            );
+            return None;
+        }
+        Some(lut)
+    }
+
+    // applies a .cube LUT to the colors of an image, leaving alpha alone;
+    // single channel images are looked up as neutral colors
+    pub fn apply_cube(img: &Image<u8>, lut: &CubeLut, interpolation: Interpolation) -> Image<u8> {
+        let (width, height, channels, alpha) = img.info().whca();
+        let mut img2 = Image::blank(ImageInfo::new(width, height, channels, alpha));
+
+        // position of a value in table coordinates
+        let scale = |value: u8, c: usize| -> f32 {
+            let v = value as f32 / 255.0;
+            let range = lut.domain_max[c] - lut.domain_min[c];
+            ((v - lut.domain_min[c]) / range).clamp(0.0, 1.0) * (lut.size - 1) as f32
+        };
+
+        for y in 0..height {
+            for x in 0..width {
+                let mut pixel = img.get_pixel(x, y).to_vec();
+                let rgb = if pixel.len() >= 3 {
+                    [pixel[0], pixel[1], pixel[2]]
+                } else {
+                    [pixel[0], pixel[0], pixel[0]]
+                };
+                let pos = [scale(rgb[0], 0), scale(rgb[1], 1), scale(rgb[2], 2)];
+                let out = if lut.is_3d {
+                    match interpolation {
+                        Interpolation::Trilinear => trilinear(lut, pos),
+                        Interpolation::Tetrahedral => tetrahedral(lut, pos),
+                    }
+                } else {
+                    linear_1d(lut, pos)
+                };
+
+                if pixel.len() >= 3 {
+                    for c in 0..3 {
+                        pixel[c] = round_u8(out[c] * 255.0);
+                    }
+                } else {
+                    pixel[0] = round_u8((0.299 * out[0] + 0.587 * out[1] + 0.114 * out[2]) * 255.0);
+                }
+                img2.set_pixel(x, y, &pixel);
+            }
+        }
+        img2
+    }
+
+    // splits table coordinates into the lower cell corner and the fraction
+    fn cell(lut: &CubeLut, pos: [f32; 3]) -> ([usize; 3], [f32; 3]) {
+        let mut base = [0usize; 3];
+        let mut frac = [0f32; 3];
+        for c in 0..3 {
+            base[c] = (pos[c] as usize).min(lut.size - 2);
+            frac[c] = pos[c] - base[c] as f32;
+        }
+        (base, frac)
+    }
+
+    fn lut_entry(lut: &CubeLut, base: [usize; 3], r: usize, g: usize, b: usize) -> [f32; 3] {
+        let n = lut.size;
+        lut.table[(base[0] + r) + (base[1] + g) * n + (base[2] + b) * n * n]
+    }
+
+    fn linear_1d(lut: &CubeLut, pos: [f32; 3]) -> [f32; 3] {
+        let mut out = [0f32; 3];
+        for c in 0..3 {
+            let i = (pos[c] as usize).min(lut.size - 2);
+            let f = pos[c] - i as f32;
+            out[c] = lut.table[i][c] * (1.0 - f) + lut.table[i + 1][c] * f;
+        }
+        out
+    }
+
+    fn trilinear(lut: &CubeLut, pos: [f32; 3]) -> [f32; 3] {
+        let (base, [fr, fg, fb]) = cell(lut, pos);
+        let mut out = [0f32; 3];
+        for (b, wb) in [(0, 1.0 - fb), (1, fb)] {
+            for (g, wg) in [(0, 1.0 - fg), (1, fg)] {
+                for (r, wr) in [(0, 1.0 - fr), (1, fr)] {
+                    let entry = lut_entry(lut, base, r, g, b);
+                    let w = wr * wg * wb;
+                    for c in 0..3 {
+                        out[c] += entry[c] * w;
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    // splits the cell into six tetrahedra along its main diagonal and
+    // interpolates within the one holding the color
+    fn tetrahedral(lut: &CubeLut, pos: [f32; 3]) -> [f32; 3] {
+        let (base, [fr, fg, fb]) = cell(lut, pos);
+        let c000 = lut_entry(lut, base, 0, 0, 0);
+        let c111 = lut_entry(lut, base, 1, 1, 1);
+
+        // the two intermediate corners and the four weights
+        let (c1, c2, w) = if fr > fg {
+            if fg > fb {
+                (lut_entry(lut, base, 1, 0, 0), lut_entry(lut, base, 1, 1, 0), [1.0 - fr, fr - fg, fg - fb, fb])
+            } else if fr > fb {
+                (lut_entry(lut, base, 1, 0, 0), lut_entry(lut, base, 1, 0, 1), [1.0 - fr, fr - fb, fb - fg, fg])
+            } else {
+                (lut_entry(lut, base, 0, 0, 1), lut_entry(lut, base, 1, 0, 1), [1.0 - fb, fb - fr, fr - fg, fg])
+            }
+        } else if fb > fg {
+            (lut_entry(lut, base, 0, 0, 1), lut_entry(lut, base, 0, 1, 1), [1.0 - fb, fb - fg, fg - fr, fr])
+        } else if fb > fr {
+            (lut_entry(lut, base, 0, 1, 0), lut_entry(lut, base, 0, 1, 1), [1.0 - fg, fg - fb, fb - fr, fr])
+        } else {
+            (lut_entry(lut, base, 0, 1, 0), lut_entry(lut, base, 1, 1, 0), [1.0 - fg, fg - fr, fr - fb, fb])
+        };
+
+        let mut out = [0f32; 3];
+        for c in 0..3 {
+            out[c] = c000[c] * w[0] + c1[c] * w[1] + c2[c] * w[2] + c111[c] * w[3];
+        }
+        out
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const IDENTITY: &str = "TITLE \"identity\"\nLUT_3D_SIZE 2\n\
+            0 0 0\n1 0 0\n0 1 0\n1 1 0\n0 0 1\n1 0 1\n0 1 1\n1 1 1\n";
+
+        fn lookup(lut: &CubeLut, rgb: [u8; 3], interpolation: Interpolation) -> Vec<u8> {
+            let img = Image::from_slice(1, 1, 3, false, &rgb);
+            apply_cube(&img, lut, interpolation).get_pixel(0, 0).to_vec()
+        }
+
+        #[test]
+        fn identity_cube() {
+            let lut = parse_cube(IDENTITY, "identity.cube").unwrap();
+            assert_eq!((lut.size, lut.is_3d, lut.table.len()), (2, true, 8));
+            assert_eq!(lut.table[1], [1.0, 0.0, 0.0]);
+            for rgb in [[0, 0, 0], [255, 255, 255], [10, 200, 90], [255, 0, 128]] {
+                assert_eq!(lookup(&lut, rgb, Interpolation::Tetrahedral), rgb.to_vec());
+                assert_eq!(lookup(&lut, rgb, Interpolation::Trilinear), rgb.to_vec());
+            }
+        }
+
+        #[test]
+        fn cube_domain() {
+            // the table covers 0 to 0.5, brighter values are clamped
+            let text = IDENTITY.replace("LUT_3D_SIZE 2", "LUT_3D_SIZE 2\nDOMAIN_MIN 0 0 0\nDOMAIN_MAX 0.5 0.5 0.5");
+            let lut = parse_cube(&text, "domain.cube").unwrap();
+            assert_eq!((lut.domain_min, lut.domain_max), ([0.0; 3], [0.5; 3]));
+            assert_eq!(lookup(&lut, [51, 0, 200], Interpolation::Tetrahedral), vec![102, 0, 255]);
+
+            let text = IDENTITY.replace("LUT_3D_SIZE 2", "LUT_3D_SIZE 2\nLUT_3D_INPUT_RANGE 0 0.5");
+            assert_eq!(parse_cube(&text, "range.cube"), Some(lut));
+
+            let text = IDENTITY.replace("LUT_3D_SIZE 2", "LUT_3D_SIZE 2\nDOMAIN_MAX 0 1 1");
+            assert_eq!(parse_cube(&text, "empty.cube"), None);
+            let text = IDENTITY.replace("LUT_3D_SIZE 2", "LUT_3D_SIZE 2\nLUT_3D_INPUT_RANGE 0");
+            assert_eq!(parse_cube(&text, "range.cube"), None);
+        }
+
+        #[test]
+        fn cube_1d() {
+            let lut = parse_cube("LUT_1D_SIZE 3\n1 1 1\n0.5 0.5 0.5\n0 0 0\n", "invert.cube").unwrap();
+            assert_eq!((lut.size, lut.is_3d), (3, false));
+            assert_eq!(lookup(&lut, [0, 255, 51], Interpolation::Tetrahedral), vec![255, 0, 204]);
+        }
+
+        #[test]
+        fn cube_entry_count() {
+            let missing = IDENTITY.replace("1 1 1\n", "");
+            assert_eq!(parse_cube(&missing, "missing.cube"), None);
+            let extra = IDENTITY.to_string() + "0.5 0.5 0.5\n";
+            assert_eq!(parse_cube(&extra, "extra.cube"), None);
+            for size in ["LUT_3D_SIZE 1", "LUT_3D_SIZE 100000", "LUT_3D_SIZE", "LUT_3D_SIZE x"] {
+                assert_eq!(parse_cube(&IDENTITY.replace("LUT_3D_SIZE 2", size), "size.cube"), None);
+            }
+        }
+
+        #[test]
+        fn acv_curves() {
+            // version 4, one curve from 0,255 to 255,0, stored as output, input
+            let data = [0, 4, 0, 1, 0, 2, 0, 255, 0, 0, 0, 0, 0, 255];
+            let curves = parse_acv(&data, "invert.acv").unwrap();
+            assert_eq!(curves, vec![vec![(0.0, 255.0), (255.0, 0.0)]]);
+            let luts = acv_luts(&curves);
+            assert_eq!((luts[0][0], luts[1][255], luts[2][100]), (255, 0, 155));
+
+            // every shorter file is rejected without a panic
+            for length in 0..data.len() {
+                assert_eq!(parse_acv(&data[..length], "truncated.acv"), None, "length {}", length);
+            }
+            // a curve needs two points
+            assert_eq!(parse_acv(&[0, 4, 0, 1, 0, 1, 0, 0, 0, 0], "short.acv"), None);
+            assert_eq!(parse_acv(&[0, 4, 0, 0], "empty.acv"), None);
+
+            // the points are sorted by input and repeated inputs are dropped,
+            // also inputs above 255 that end up equal
+            let data = [0, 4, 0, 1, 0, 4, 0, 0, 0, 255, 0, 200, 0, 128, 0, 255, 1, 0, 0, 0, 0, 0];
+            let curves = parse_acv(&data, "unsorted.acv").unwrap();
+            assert_eq!(curves, vec![vec![(0.0, 0.0), (128.0, 200.0), (255.0, 0.0)]]);
+            let luts = acv_luts(&curves);
+            assert_eq!((luts[0][0], luts[0][128], luts[0][255]), (0, 200, 0));
+            let data = [0, 4, 0, 1, 0, 2, 0, 10, 1, 0, 0, 20, 1, 44];
+            assert_eq!(parse_acv(&data, "equal.acv"), None);
+        }
+    }
+}
+
+pub use lut::*;
@@ -6,6 +6,35 @@ pub mod config;
 pub mod rustyimg;
 pub mod imageaction;
 pub mod rustyexif;
+pub mod crop;
+pub mod grayscale;
+pub mod mixer;
+pub mod equalize;
+pub mod tone;
+pub mod lut;
+pub mod whitebalance;
+pub mod color;
+pub mod toning;
+pub mod convolve;
+pub mod denoise;
+pub mod edge;
+pub mod morphology;
+pub mod threshold;
+pub mod bilevel;
+pub mod warp;
+pub mod deskew;
+pub mod watermark;
+pub mod canvas;
+pub mod lens;
+pub mod aberration;
+pub mod pyramid;
+pub mod align;
+pub mod fusion;
+pub mod focus;
+pub mod features;
+pub mod panorama;
+pub mod montage;
+pub mod animation;
 
 use imageaction::*;
 
@@ -50,7 +79,23 @@ fn main() {
         ]);
     }
 
-    if ext.is_none() {
+    if opts.action == "animate" {
+        // a folder of frames or the images stored in a HEIC file
+        count = animate_images(&opts.src_file, &opts);
+    } else if opts.action == "fuse" || opts.action == "stack" || opts.action == "stitch" || opts.action == "contact-sheet" {
+        // these combine the files of a folder
+        if ext.is_some() {
+            println!("The {} action needs a folder of images", opts.action);
+        } else if opts.action == "fuse" {
+            count = fuse_folder(&opts.src_file, &opts);
+        } else if opts.action == "stack" {
+            count = stack_folder(&opts.src_file, &opts);
+        } else if opts.action == "stitch" {
+            count = stitch_folder(&opts.src_file, &opts);
+        } else {
+            count = contact_sheet_folder(&opts.src_file, &opts);
+        }
+    } else if ext.is_none() {
         let files = get_files_in_folder(&opts.src_file, filter);
         let total = files.len();
         let mut current = 0;
@@ -90,6 +135,8 @@ fn process_image(src_file: &String, opts: &ConfigOptions) -> u8 {
         return set_exif_date(&src_file, Vec::new());
     } else if opts.action == "set-artist" {
         return set_artist_name(&src_file);
+    } else if opts.action == "watermark" {
+        return watermark_image(&src_file, &opts);
     } else {
         panic!("Unknown action {}", opts.action);
     }
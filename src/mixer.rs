@@ -0,0 +1,96 @@
+pub mod mixer {
+
+    use imgproc_rs::image::{BaseImage, Image, ImageInfo};
+    pub use crate::config::*;
+
+    use crate::grayscale::*;
+
+    // a channel mixer matrix, one row per output channel:
+    // out = r * row[0] + g * row[1] + b * row[2] + row[3]
+    // the offset is in 0-255 units; a single row produces a mono image
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct ChannelMixer {
+        pub rows: Vec<[f32; 4]>,
+    }
+
+    // the named black & white presets, emulating filters used with b&w film
+    pub fn mixer_preset(name: &str) -> Option<[f32; 3]> {
+        let name = name.trim().to_lowercase();
+        match name.trim_end_matches("-filter") {
+            "red" => Some([1.0, 0.0, 0.0]),
+            "orange" => Some([0.6, 0.4, 0.0]),
+            "yellow" => Some([0.45, 0.5, 0.05]),
+            "green" => Some([0.2, 0.7, 0.1]),
+            "blue" => Some([0.0, 0.0, 1.0]),
+            "infrared" => Some([-0.7, 2.0, -0.3]),
+            "neutral" => Some([0.299, 0.587, 0.114]),
+            _ => None,
+        }
+    }
+
+    // parses a preset name or a matrix with rows separated by ';'
+    // and 3 or 4 (with offset) comma separated values per row,
+    // e.g. "0.5,0.5,0" (mono) or "1,0,0;0,1,0;0.2,0,0.8,10" (rgb)
+    pub fn parse_mixer(spec: &str) -> Option<ChannelMixer> {
+        if let Some(weights) = mixer_preset(spec) {
+            return Some(ChannelMixer {
+                rows: vec![[weights[0], weights[1], weights[2], 0.0]],
+            });
+        }
+
+        let mut rows = Vec::new();
+        for row in spec.split(';') {
+            let values: Vec<f32> = row
+                .split(',')
+                .map(|v| v.trim().parse::<f32>())
+                .collect::<Result<_, _>>()
+                .ok()?;
+            match values.len() {
+                3 => rows.push([values[0], values[1], values[2], 0.0]),
+                4 => rows.push([values[0], values[1], values[2], values[3]]),
+                _ => return None,
+            }
+        }
+        if rows.len() != 1 && rows.len() != 3 {
+            return None;
+        }
+        Some(ChannelMixer { rows })
+    }
+
+    // mixes the channels of an RGB image
+    // with `linear` set the mixing happens in linear light
+    pub fn mix_channels(img: &Image<u8>, mixer: &ChannelMixer, linear: bool) -> Image<u8> {
+        let (width, height) = img.info().wh();
+        let channels = mixer.rows.len() as u8;
+        let mut img2 = Image::blank(ImageInfo::new(width, height, channels, false));
+        let mut out = [0u8; 3];
+
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = img.get_pixel(x, y);
+                let input = if linear {
+                    [
+                        srgb_to_linear(pixel[0]),
+                        srgb_to_linear(pixel[1]),
+                        srgb_to_linear(pixel[2]),
+                    ]
+                } else {
+                    [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32]
+                };
+
+                for (c, row) in mixer.rows.iter().enumerate() {
+                    let mixed = row[0] * input[0] + row[1] * input[1] + row[2] * input[2];
+                    out[c] = if linear {
+                        round_u8(linear_to_srgb(mixed) + row[3])
+                    } else {
+                        round_u8(mixed + row[3])
+                    };
+                }
+                img2.set_pixel(x, y, &out[0..channels as usize]);
+            }
+        }
+        img2
+    }
+}
+
+pub use mixer::*;
@@ -0,0 +1,235 @@
+pub mod montage {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    pub use crate::config::*;
+
+    use crate::canvas::*;
+    use crate::color::*;
+    use crate::warp::*;
+    use crate::watermark::*;
+
+    // the largest page side, the limit of JPEG files
+    const MAX_PAGE_SIDE: u32 = 65535;
+
+    // what is written below each thumbnail
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Caption {
+        Filename,
+        // the EXIF date the photo was taken
+        Date,
+    }
+
+    // how the thumbnails are laid out on the pages
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct SheetLayout {
+        pub columns: u32,
+        pub rows: u32,
+        // the side of the square each thumbnail fits in
+        pub thumb_size: u32,
+        pub spacing: u32,
+        pub background: [u8; 3],
+        pub caption_lines: u32,
+    }
+
+    impl SheetLayout {
+        // the caption font size in pixels
+        pub fn caption_size(&self) -> f32 {
+            (self.thumb_size as f32 / 14.0).max(10.0)
+        }
+
+        pub fn line_height(&self) -> u32 {
+            (self.caption_size() * 1.3).ceil() as u32
+        }
+
+        pub fn cell_height(&self) -> u32 {
+            self.thumb_size + self.caption_lines * self.line_height()
+        }
+
+        // the size of a page with the given number of rows, None when it
+        // does not fit in u32
+        pub fn page_size(&self, rows: u32) -> Option<(u32, u32)> {
+            let width = self.columns.checked_mul(self.thumb_size.checked_add(self.spacing)?)?.checked_add(self.spacing)?;
+            let height = rows.checked_mul(self.cell_height().checked_add(self.spacing)?)?.checked_add(self.spacing)?;
+            Some((width, height))
+        }
+    }
+
+    // parses a comma separated list of filename and date, or none
+    pub fn parse_captions(spec: &str) -> Option<Vec<Caption>> {
+        if spec.trim().is_empty() || spec.trim().eq_ignore_ascii_case("none") {
+            return Some(Vec::new());
+        }
+        split_list(spec)
+            .iter()
+            .map(|name| match name.to_lowercase().as_str() {
+                "filename" | "name" => Some(Caption::Filename),
+                "date" => Some(Caption::Date),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // the layout options, falling back to the defaults on invalid values;
+    // None when the full pages would be too large
+    pub fn sheet_layout(opts: &ConfigOptions, caption_lines: u32) -> Option<SheetLayout> {
+        let number = |spec: &str, name: &str, default: u32, max: u32| -> u32 {
+            match spec.trim().parse::<u32>() {
+                Ok(v) if (v > 0 || name == "spacing") && v <= max => v,
+                _ => {
+                    println!("Invalid contact sheet {} {}, using {}", name, spec, default);
+                    default
+                }
+            }
+        };
+        let background = match parse_color(&opts.background) {
+            Some(c) => c,
+            None => {
+                println!("Invalid background color {}, using white", opts.background);
+                [255, 255, 255]
+            }
+        };
+        let layout = SheetLayout {
+            columns: number(&opts.columns, "columns", 5, 100),
+            rows: number(&opts.rows, "rows", 6, 100),
+            thumb_size: number(&opts.thumb_size, "thumbnail size", 240, 4000),
+            spacing: number(&opts.spacing, "spacing", 16, 1000),
+            background,
+            caption_lines,
+        };
+        match layout.page_size(layout.rows) {
+            Some((width, height)) if width <= MAX_PAGE_SIDE && height <= MAX_PAGE_SIDE => Some(layout),
+            size => {
+                let (width, height) = size.unwrap_or((u32::MAX, u32::MAX));
+                println!("Contact sheet pages of {}x{} pixels are too large, at most {} pixels a side", width, height, MAX_PAGE_SIDE);
+                None
+            }
+        }
+    }
+
+    // a copy of the image fitting in a size x size square
+    pub fn make_thumbnail(img: &Image<u8>, size: u32) -> Image<u8> {
+        let (width, height) = img.info().wh();
+        let scale = (size as f32 / width as f32).min(size as f32 / height as f32).min(1.0);
+        let w = ((width as f32 * scale).round() as u32).clamp(1, size);
+        let h = ((height as f32 * scale).round() as u32).clamp(1, size);
+        resize_image(img, w, h)
+    }
+
+    // shortens a caption with an ellipsis until it fits the width
+    fn fit_caption(text: &str, size: f32, width: u32) -> String {
+        if text_width(text, size) <= width as f32 {
+            return text.to_string();
+        }
+        let mut chars: Vec<char> = text.chars().collect();
+        while !chars.is_empty() {
+            chars.pop();
+            let shortened = format!("{}…", chars.iter().collect::<String>());
+            if text_width(&shortened, size) <= width as f32 {
+                return shortened;
+            }
+        }
+        String::new()
+    }
+
+    // the pages of thumbnails with their caption lines, left to right and
+    // top to bottom; the last page only as high as its rows
+    pub fn contact_sheets(thumbs: &[(Image<u8>, Vec<String>)], layout: &SheetLayout) -> Vec<Image<u8>> {
+        let per_page = (layout.columns * layout.rows) as usize;
+        let cell_width = layout.thumb_size + layout.spacing;
+        let cell_height = layout.cell_height() + layout.spacing;
+        // dark text on light backgrounds and the other way round
+        let luma = 0.299 * layout.background[0] as f32 + 0.587 * layout.background[1] as f32 + 0.114 * layout.background[2] as f32;
+        let text_color = if luma > 128.0 { [0, 0, 0] } else { [255, 255, 255] };
+        let size = layout.caption_size();
+
+        let mut pages = Vec::new();
+        for page_thumbs in thumbs.chunks(per_page.max(1)) {
+            let rows = (page_thumbs.len() as u32).div_ceil(layout.columns);
+            let (width, height) = layout.page_size(rows).unwrap();
+            let mut page = solid_image(width, height, 3, false, layout.background);
+            for (i, (thumb, captions)) in page_thumbs.iter().enumerate() {
+                let (column, row) = (i as u32 % layout.columns, i as u32 / layout.columns);
+                let (x, y) = (layout.spacing + column * cell_width, layout.spacing + row * cell_height);
+                let (tw, th) = thumb.info().wh();
+                // centered at the bottom of its square, right above the captions
+                let (tx, ty) = (x + (layout.thumb_size - tw) / 2, y + layout.thumb_size - th);
+                composite(&mut page, thumb, tx as i64, ty as i64, 1.0);
+
+                for (line, caption) in captions.iter().enumerate() {
+                    let text = fit_caption(caption, size, layout.thumb_size);
+                    if let Some(rendered) = render_text_sized(&text, size, text_color) {
+                        let rw = rendered.info().width.min(layout.thumb_size);
+                        let cx = x + (layout.thumb_size - rw) / 2;
+                        let cy = y + layout.thumb_size + line as u32 * layout.line_height();
+                        composite(&mut page, &rendered, cx as i64, cy as i64, 1.0);
+                    }
+                }
+            }
+            pages.push(page);
+        }
+        pages
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn layout(columns: u32, rows: u32) -> SheetLayout {
+            SheetLayout {
+                columns,
+                rows,
+                thumb_size: 40,
+                spacing: 4,
+                background: [255, 255, 255],
+                caption_lines: 0,
+            }
+        }
+
+        #[test]
+        fn captions() {
+            assert_eq!(parse_captions("filename,date"), Some(vec![Caption::Filename, Caption::Date]));
+            assert_eq!(parse_captions(" Date , NAME "), Some(vec![Caption::Date, Caption::Filename]));
+            assert_eq!(parse_captions("none"), Some(vec![]));
+            assert_eq!(parse_captions(""), Some(vec![]));
+            assert_eq!(parse_captions("filename,size"), None);
+        }
+
+        #[test]
+        fn pages() {
+            let thumb = (solid_image(30, 20, 3, false, [0, 0, 0]), Vec::new());
+            let thumbs = vec![thumb; 7];
+            // 3 x 2 per page: a full page and one with a single row
+            let pages = contact_sheets(&thumbs, &layout(3, 2));
+            assert_eq!(pages.len(), 2);
+            assert_eq!(pages[0].info().wh(), (3 * 44 + 4, 2 * 44 + 4));
+            assert_eq!(pages[1].info().wh(), (3 * 44 + 4, 44 + 4));
+            // the thumbnail sits centered at the bottom of its square
+            assert_eq!(pages[1].get_pixel(4 + 5, 4 + 20)[0], 0);
+            assert_eq!(pages[1].get_pixel(4 + 4, 4 + 20)[0], 255);
+            assert_eq!(pages[1].get_pixel(4 + 5, 4 + 19)[0], 255);
+            assert_eq!(pages[1].get_pixel(48 + 5, 4 + 20)[0], 255);
+
+            assert_eq!(contact_sheets(&thumbs[..6], &layout(3, 2)).len(), 1);
+            assert_eq!(contact_sheets(&thumbs, &layout(10, 10)).len(), 1);
+            assert_eq!(contact_sheets(&thumbs, &layout(10, 10))[0].info().wh(), (10 * 44 + 4, 44 + 4));
+        }
+
+        #[test]
+        fn page_limits() {
+            assert_eq!(layout(3, 2).page_size(2), Some((136, 92)));
+            assert_eq!(layout(100_000_000, 2).page_size(2), None);
+            assert_eq!(layout(2, 100_000_000).page_size(100_000_000), None);
+
+            let mut opts = get();
+            opts.columns = String::from("100000000");
+            assert_eq!(sheet_layout(&opts, 2).map(|l| l.columns), Some(5));
+            opts.columns = String::from("100");
+            opts.thumb_size = String::from("4000");
+            assert_eq!(sheet_layout(&opts, 2), None);
+            opts.thumb_size = String::from("300");
+            assert_eq!(sheet_layout(&opts, 2).map(|l| (l.columns, l.thumb_size)), Some((100, 300)));
+        }
+    }
+}
+
+pub use montage::*;
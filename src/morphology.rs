@@ -0,0 +1,148 @@
+pub mod morphology {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    pub use crate::config::*;
+
+    use crate::convolve::*;
+    use crate::edge::*;
+
+    // the supported morphological operations
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum MorphOp {
+        Erode,
+        Dilate,
+        // erode, then dilate: removes specks
+        Open,
+        // dilate, then erode: fills small holes
+        Close,
+    }
+
+    // the shape of the structuring element
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum MorphShape {
+        Square,
+        Disk,
+    }
+
+    // parses operations separated by ';', each as OP[,RADIUS[,SHAPE]],
+    // e.g. close,2;open,1,disk (defaults: radius 1, square)
+    pub fn parse_morphology(spec: &str) -> Option<Vec<(MorphOp, usize, MorphShape)>> {
+        let mut ops = Vec::new();
+        for part in spec.to_lowercase().split(';') {
+            let mut args = part.split(',').map(|a| a.trim());
+            let op = match args.next()? {
+                "erode" => MorphOp::Erode,
+                "dilate" => MorphOp::Dilate,
+                "open" => MorphOp::Open,
+                "close" => MorphOp::Close,
+                _ => return None,
+            };
+            let radius = match args.next() {
+                Some(r) => r.parse::<usize>().ok()?,
+                None => 1,
+            };
+            let shape = match args.next() {
+                None | Some("square") => MorphShape::Square,
+                Some("disk") => MorphShape::Disk,
+                _ => return None,
+            };
+            if radius == 0 || args.next().is_some() {
+                return None;
+            }
+            ops.push((op, radius, shape));
+        }
+        Some(ops)
+    }
+
+    // takes the minimum (erode) or maximum (dilate) over the structuring
+    // element; squares are done as a row and a column pass
+    fn rank_plane(plane: &[f32], width: usize, height: usize, radius: usize, shape: MorphShape, max: bool) -> Vec<f32> {
+        let r = radius as i64;
+        let pick = |a: f32, b: f32| if max { a.max(b) } else { a.min(b) };
+        let start = if max { f32::MIN } else { f32::MAX };
+        let at = |p: &[f32], x: i64, y: i64| -> f32 {
+            let xi = border_index(x, width as i64, BorderMode::Clamp);
+            let yi = border_index(y, height as i64, BorderMode::Clamp);
+            p[yi * width + xi]
+        };
+
+        match shape {
+            MorphShape::Square => {
+                let mut rows = vec![0f32; plane.len()];
+                for y in 0..height as i64 {
+                    for x in 0..width as i64 {
+                        let v = (-r..=r).fold(start, |acc, d| pick(acc, at(plane, x + d, y)));
+                        rows[y as usize * width + x as usize] = v;
+                    }
+                }
+                let mut out = vec![0f32; plane.len()];
+                for y in 0..height as i64 {
+                    for x in 0..width as i64 {
+                        let v = (-r..=r).fold(start, |acc, d| pick(acc, at(&rows, x, y + d)));
+                        out[y as usize * width + x as usize] = v;
+                    }
+                }
+                out
+            }
+            MorphShape::Disk => {
+                let mut offsets = Vec::new();
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        if dx * dx + dy * dy <= r * r {
+                            offsets.push((dx, dy));
+                        }
+                    }
+                }
+                let mut out = vec![0f32; plane.len()];
+                for y in 0..height as i64 {
+                    for x in 0..width as i64 {
+                        let v = offsets
+                            .iter()
+                            .fold(start, |acc, &(dx, dy)| pick(acc, at(plane, x + dx, y + dy)));
+                        out[y as usize * width + x as usize] = v;
+                    }
+                }
+                out
+            }
+        }
+    }
+
+    // applies a morphological operation to the luma, producing a single
+    // channel image
+    pub fn morph_image(img: &Image<u8>, op: MorphOp, radius: usize, shape: MorphShape) -> Image<u8> {
+        let (width, height) = img.info().wh();
+        let (w, h) = (width as usize, height as usize);
+        let plane = luma_plane(img);
+        let out = match op {
+            MorphOp::Erode => rank_plane(&plane, w, h, radius, shape, false),
+            MorphOp::Dilate => rank_plane(&plane, w, h, radius, shape, true),
+            MorphOp::Open => {
+                let eroded = rank_plane(&plane, w, h, radius, shape, false);
+                rank_plane(&eroded, w, h, radius, shape, true)
+            }
+            MorphOp::Close => {
+                let dilated = rank_plane(&plane, w, h, radius, shape, true);
+                rank_plane(&dilated, w, h, radius, shape, false)
+            }
+        };
+        from_planes(&[out], width, height, false)
+    }
+
+    // runs the morphology operations in order
+    pub fn apply_morphology(img: Image<u8>, opts: &ConfigOptions) -> Image<u8> {
+        if opts.morph.is_empty() {
+            return img;
+        }
+        match parse_morphology(&opts.morph) {
+            Some(ops) => ops
+                .into_iter()
+                .fold(img, |img, (op, radius, shape)| morph_image(&img, op, radius, shape)),
+            None => {
+                println!("Invalid morphology {}", opts.morph);
+                img
+            }
+        }
+    }
+}
+
+pub use morphology::*;
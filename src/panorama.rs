@@ -0,0 +1,360 @@
+pub mod panorama {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    pub use crate::config::*;
+
+    use crate::convolve::*;
+    use crate::crop::*;
+    use crate::edge::*;
+    use crate::features::*;
+    use crate::pyramid::*;
+    use crate::warp::*;
+    use crate::log;
+
+    // the longest side keypoints are detected on
+    const FEATURE_SIZE: u32 = 1000;
+
+    // the fewest matches agreeing on a transform to trust it
+    const MIN_INLIERS: usize = 12;
+
+    // a point and where it should end up
+    pub type PointPair = ((f64, f64), (f64, f64));
+
+    // the focal length in pixels for a 35mm equivalent focal length, which
+    // relates to the 43.3mm diagonal of a 35mm frame
+    pub fn focal_pixels(width: u32, height: u32, focal_35mm: f32) -> f32 {
+        let diagonal = (width as f32).hypot(height as f32);
+        focal_35mm * diagonal / 43.27
+    }
+
+    // projects an image onto a cylinder of radius `focal` pixels around the
+    // camera, which turns panning between shots into sideways shifts; the
+    // mask tells which pixels come from the image
+    pub fn cylindrical_projection(img: &Image<u8>, focal: f32) -> (Image<u8>, Vec<bool>) {
+        let (width, height) = img.info().wh();
+        let (cx, cy) = ((width as f32 - 1.0) / 2.0, (height as f32 - 1.0) / 2.0);
+        let out_width = (2.0 * focal * (cx / focal).atan()).ceil() as u32 + 1;
+        let ocx = (out_width as f32 - 1.0) / 2.0;
+        let (max_x, max_y) = ((width - 1) as f32, (height - 1) as f32);
+        let source = |x: f32, y: f32| {
+            let theta = (x - ocx) / focal;
+            (cx + focal * theta.tan(), cy + (y - cy) / theta.cos())
+        };
+
+        let mut mask = Vec::with_capacity((out_width * height) as usize);
+        for y in 0..height {
+            for x in 0..out_width {
+                let (sx, sy) = source(x as f32, y as f32);
+                mask.push(sx > -0.5 && sy > -0.5 && sx < max_x + 0.5 && sy < max_y + 0.5);
+            }
+        }
+        // the edges are repeated outside, which keeps the blending smooth
+        let projected = warp_image(img, out_width, height, &[], |x, y| {
+            let (sx, sy) = source(x, y);
+            Some((sx.clamp(0.0, max_x), sy.clamp(0.0, max_y)))
+        });
+        (projected, mask)
+    }
+
+    // the keypoints of an image found on a reduced copy, in full size
+    // coordinates and inside the mask
+    fn image_keypoints(img: &Image<u8>, mask: &[bool]) -> Vec<Keypoint> {
+        let (width, height) = img.info().wh();
+        let scale = (width.max(height) as f32 / FEATURE_SIZE as f32).max(1.0);
+        let (sw, sh) = (((width as f32 / scale).round() as u32).max(1), ((height as f32 / scale).round() as u32).max(1));
+        let small = resize_image(img, sw, sh);
+        let (fx, fy) = (width as f32 / sw as f32, height as f32 / sh as f32);
+        let mut keypoints = detect_keypoints(&luma_plane(&small), sw as usize, sh as usize, 1500);
+        for keypoint in keypoints.iter_mut() {
+            keypoint.x *= fx;
+            keypoint.y *= fy;
+        }
+        keypoints.retain(|k| mask[k.y.round() as usize * width as usize + k.x.round() as usize]);
+        keypoints
+    }
+
+    // the homography taking the first point of each pair onto the second,
+    // fitted with RANSAC to the pairs agreeing within `threshold` pixels;
+    // returns it with the number of agreeing pairs
+    pub fn ransac_homography(pairs: &[PointPair], threshold: f64) -> Option<(Homography, usize)> {
+        if pairs.len() < 4 {
+            return None;
+        }
+        let inliers = |h: &Homography| -> Vec<usize> {
+            (0..pairs.len())
+                .filter(|&i| {
+                    let (from, to) = pairs[i];
+                    match h.apply(from.0, from.1) {
+                        Some((x, y)) => (x - to.0).hypot(y - to.1) < threshold,
+                        None => false,
+                    }
+                })
+                .collect()
+        };
+
+        let mut seed: u64 = 0x9e3779b97f4a7c15;
+        let mut random = move |n: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % n as u64) as usize
+        };
+        let mut best: Vec<usize> = Vec::new();
+        for _ in 0..2000 {
+            let mut sample = [0usize; 4];
+            for i in 0..4 {
+                sample[i] = random(pairs.len());
+                while sample[..i].contains(&sample[i]) {
+                    sample[i] = random(pairs.len());
+                }
+            }
+            let from = sample.map(|i| pairs[i].0);
+            let to = sample.map(|i| pairs[i].1);
+            if let Some(h) = homography_from_points(&from, &to) {
+                let agreeing = inliers(&h);
+                if agreeing.len() > best.len() {
+                    best = agreeing;
+                }
+            }
+        }
+        if best.len() < 4 {
+            return None;
+        }
+
+        // refit on everything that agreed
+        let from: Vec<(f64, f64)> = best.iter().map(|&i| pairs[i].0).collect();
+        let to: Vec<(f64, f64)> = best.iter().map(|&i| pairs[i].1).collect();
+        let h = fit_homography(&from, &to)?;
+        let count = inliers(&h).len();
+        Some((h, count))
+    }
+
+    // the largest rectangle without uncovered pixels, found by trimming the
+    // side with the most uncovered pixels until none are left, as (x, y, w, h)
+    pub fn covered_rect(covered: &[bool], width: usize, height: usize) -> (u32, u32, u32, u32) {
+        let (mut x0, mut y0, mut x1, mut y1) = (0, 0, width, height);
+        while x0 < x1 && y0 < y1 {
+            let row = |y: usize| (x0..x1).filter(|&x| !covered[y * width + x]).count() as f32 / (x1 - x0) as f32;
+            let column = |x: usize| (y0..y1).filter(|&y| !covered[y * width + x]).count() as f32 / (y1 - y0) as f32;
+            let sides = [row(y0), row(y1 - 1), column(x0), column(x1 - 1)];
+            let (side, &worst) = sides.iter().enumerate().fold((0, &0.0), |b, s| if s.1 > b.1 { s } else { b });
+            if worst == 0.0 {
+                return (x0 as u32, y0 as u32, (x1 - x0) as u32, (y1 - y0) as u32);
+            }
+            match side {
+                0 => y0 += 1,
+                1 => y1 -= 1,
+                2 => x0 += 1,
+                _ => x1 -= 1,
+            }
+        }
+        (0, 0, width as u32, height as u32)
+    }
+
+    // stitches a left to right sequence of overlapping shots into a
+    // panorama: the shots are projected onto a cylinder, matched pairwise,
+    // joined along seams between their centers and blended band by band
+    pub fn stitch_images(images: &[Image<u8>], focal: f32) -> Option<Image<u8>> {
+        let projected: Vec<(Image<u8>, Vec<bool>)> = images.iter().map(|i| cylindrical_projection(i, focal)).collect();
+        let keypoints: Vec<Vec<Keypoint>> = projected.iter().map(|(i, m)| image_keypoints(i, m)).collect();
+        let (width, height) = projected[0].0.info().wh();
+        let threshold = 3.0 * (width.max(height) as f64 / FEATURE_SIZE as f64).max(1.0);
+
+        // each shot mapped into the first one, through its neighbours
+        let mut transforms = vec![Homography::identity()];
+        for k in 1..projected.len() {
+            let pairs: Vec<PointPair> = match_keypoints(&keypoints[k], &keypoints[k - 1])
+                .iter()
+                .map(|&(i, j)| {
+                    let (a, b) = (&keypoints[k][i], &keypoints[k - 1][j]);
+                    ((a.x as f64, a.y as f64), (b.x as f64, b.y as f64))
+                })
+                .collect();
+            match ransac_homography(&pairs, threshold) {
+                Some((h, inliers)) if inliers >= MIN_INLIERS => {
+                    log!(" > Images {} and {}: {} matches, {} agree", k, k + 1, pairs.len(), inliers);
+                    let chained = transforms[k - 1].multiply(&h);
+                    transforms.push(chained);
+                }
+                _ => {
+                    println!("Could not find the overlap of images {} and {}", k, k + 1);
+                    return None;
+                }
+            }
+        }
+
+        // the canvas holding all the shots
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+        for (t, (img, _)) in transforms.iter().zip(projected.iter()) {
+            let (w, h) = img.info().wh();
+            let (w, h) = ((w - 1) as f64, (h - 1) as f64);
+            for (x, y) in [(0.0, 0.0), (w, 0.0), (0.0, h), (w, h)] {
+                let (u, v) = t.apply(x, y)?;
+                (min_x, min_y, max_x, max_y) = (min_x.min(u), min_y.min(v), max_x.max(u), max_y.max(v));
+            }
+        }
+        let total_width: u32 = projected.iter().map(|(i, _)| i.info().width).sum();
+        let (cw, ch) = ((max_x - min_x).ceil() + 1.0, (max_y - min_y).ceil() + 1.0);
+        if cw > 2.0 * total_width as f64 || ch > 3.0 * height as f64 {
+            println!("The images do not line up as a horizontal panorama");
+            return None;
+        }
+        let (cw, ch) = (cw as usize, ch as usize);
+        let offset = Homography {
+            m: [1.0, 0.0, min_x, 0.0, 1.0, min_y, 0.0, 0.0, 1.0],
+        };
+        // from the canvas back into each shot
+        let maps: Vec<Homography> = transforms
+            .iter()
+            .map(|t| t.inverse().map(|i| i.multiply(&offset)))
+            .collect::<Option<_>>()?;
+
+        // every canvas pixel belongs to the shot whose edges are farthest
+        let mut owner = vec![usize::MAX; cw * ch];
+        let mut distance = vec![0f64; cw * ch];
+        for (k, ((img, mask), map)) in projected.iter().zip(maps.iter()).enumerate() {
+            let (w, h) = img.info().wh();
+            let (mw, mh) = ((w - 1) as f64, (h - 1) as f64);
+            for y in 0..ch {
+                for x in 0..cw {
+                    if let Some((sx, sy)) = map.apply(x as f64, y as f64) {
+                        if sx < 0.0 || sy < 0.0 || sx > mw || sy > mh || !mask[sy.round() as usize * w as usize + sx.round() as usize] {
+                            continue;
+                        }
+                        let d = sx.min(mw - sx).min(sy).min(mh - sy) + 1.0;
+                        let i = y * cw + x;
+                        if d > distance[i] {
+                            distance[i] = d;
+                            owner[i] = k;
+                        }
+                    }
+                }
+            }
+        }
+
+        // blend the shots band by band across the seams
+        let colors = images[0].info().channels_non_alpha() as usize;
+        let levels = pyramid_levels(cw, ch).min(6);
+        let mut sums: Vec<Vec<Level>> = Vec::new();
+        let mut weight_sum: Vec<Level> = Vec::new();
+        for (k, ((img, _), map)) in projected.iter().zip(maps.iter()).enumerate() {
+            let (w, h) = img.info().wh();
+            let (mw, mh) = ((w - 1) as f32, (h - 1) as f32);
+            let warped = warp_image(img, cw as u32, ch as u32, &[], |x, y| {
+                map.apply(x as f64, y as f64)
+                    .map(|(sx, sy)| ((sx as f32).clamp(0.0, mw), (sy as f32).clamp(0.0, mh)))
+            });
+            let seam: Vec<f32> = owner.iter().map(|&o| if o == k { 1.0 } else { 0.0 }).collect();
+            let weights = gaussian_pyramid(&seam, cw, ch, levels);
+            if sums.is_empty() {
+                sums = (0..colors).map(|_| zero_pyramid(&weights)).collect();
+                weight_sum = zero_pyramid(&weights);
+            }
+            let planes = to_planes(&warped);
+            for (sum, plane) in sums.iter_mut().zip(planes.iter()) {
+                accumulate_pyramid(sum, &laplacian_pyramid(plane, cw, ch, levels), &weights);
+            }
+            for (total, level) in weight_sum.iter_mut().zip(weights.iter()) {
+                for (t, w) in total.data.iter_mut().zip(level.data.iter()) {
+                    *t += w;
+                }
+            }
+        }
+        let planes: Vec<Vec<f32>> = sums
+            .iter_mut()
+            .map(|sum| {
+                for (level, total) in sum.iter_mut().zip(weight_sum.iter()) {
+                    for (v, &t) in level.data.iter_mut().zip(total.data.iter()) {
+                        *v = if t > 1e-6 { *v / t } else { 0.0 };
+                    }
+                }
+                collapse_pyramid(sum)
+            })
+            .collect();
+        let panorama = from_planes(&planes, cw as u32, ch as u32, false);
+
+        // cut away the uncovered corners
+        let covered: Vec<bool> = owner.iter().map(|&o| o != usize::MAX).collect();
+        let (x, y, w, h) = covered_rect(&covered, cw, ch);
+        Some(crop_rect(&panorama, x, y, w, h))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn ransac_with_outliers() {
+            let known = Homography {
+                m: [1.02, 0.03, 15.0, -0.02, 0.98, -7.0, 1e-5, -2e-5, 1.0],
+            };
+            let mut state = 3u32;
+            let mut next = |n: f64| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                (state >> 8) as f64 / (1 << 24) as f64 * n
+            };
+            // 70 pairs follow the homography within a tenth of a pixel,
+            // 30 go anywhere
+            let pairs: Vec<PointPair> = (0..100)
+                .map(|i| {
+                    let from = (next(800.0), next(600.0));
+                    let to = if i % 10 < 3 {
+                        (next(800.0), next(600.0))
+                    } else {
+                        let (x, y) = known.apply(from.0, from.1).unwrap();
+                        (x + next(0.2) - 0.1, y + next(0.2) - 0.1)
+                    };
+                    (from, to)
+                })
+                .collect();
+            let (h, count) = ransac_homography(&pairs, 2.0).unwrap();
+            assert!((70..=72).contains(&count), "{} inliers", count);
+            for (x, y) in [(0.0, 0.0), (800.0, 0.0), (400.0, 300.0), (0.0, 600.0), (800.0, 600.0)] {
+                let (ex, ey) = known.apply(x, y).unwrap();
+                let (fx, fy) = h.apply(x, y).unwrap();
+                assert!((ex - fx).hypot(ey - fy) < 0.5, "{} {}: {} {} {} {}", x, y, ex, ey, fx, fy);
+            }
+
+            assert_eq!(ransac_homography(&pairs[..3], 2.0), None);
+            assert_eq!(ransac_homography(&[], 2.0), None);
+        }
+
+        #[test]
+        fn covered_rect_of_jagged_corners() {
+            let (width, height) = (20, 10);
+            let mut covered = vec![true; width * height];
+            // a staircase at the top left and the bottom right
+            for (x, y) in [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (0, 2), (17, 9), (18, 9), (19, 9), (19, 8)] {
+                covered[y * width + x] = false;
+            }
+            let (x, y, w, h) = covered_rect(&covered, width, height);
+            assert_eq!((x, y, w, h), (3, 0, 16, 9));
+            for yi in y..y + h {
+                for xi in x..x + w {
+                    assert!(covered[yi as usize * width + xi as usize]);
+                }
+            }
+            assert_eq!(covered_rect(&vec![true; width * height], width, height), (0, 0, 20, 10));
+        }
+
+        #[test]
+        fn cylinder() {
+            let (width, height) = (120u32, 80u32);
+            let data: Vec<u8> = (0..width * height).map(|i| (i % width) as u8).collect();
+            let img = Image::from_vec(width, height, 1, false, data);
+            let (projected, mask) = cylindrical_projection(&img, focal_pixels(width, height, 24.0));
+            let (out_width, out_height) = projected.info().wh();
+            assert_eq!(out_height, height);
+            assert!(out_width < width, "{}", out_width);
+            assert_eq!(mask.len(), (out_width * out_height) as usize);
+            // the middle row stays apart from the rounded up outer columns,
+            // the corners bend inwards
+            let w = out_width as usize;
+            assert!((1..w - 1).all(|x| mask[40 * w + x]));
+            assert!(!mask[0] && !mask[w - 1] && !mask[mask.len() - 1]);
+            assert!((projected.get_pixel(out_width / 2, 40)[0] as i32 - 60).abs() <= 1);
+        }
+    }
+}
+
+pub use panorama::*;
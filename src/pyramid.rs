@@ -0,0 +1,149 @@
+pub mod pyramid {
+
+    pub use crate::config::*;
+
+    use crate::convolve::*;
+
+    // one level of a pyramid, a float plane stored row by row
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Level {
+        pub data: Vec<f32>,
+        pub width: usize,
+        pub height: usize,
+    }
+
+    // the 5 tap binomial kernel used between the levels
+    const BINOMIAL: [f32; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+
+    // how many levels fit until the smaller side is about 8 pixels
+    pub fn pyramid_levels(width: usize, height: usize) -> usize {
+        let mut levels = 1;
+        let mut side = width.min(height);
+        while side >= 16 && levels < 12 {
+            side = side.div_ceil(2);
+            levels += 1;
+        }
+        levels
+    }
+
+    // blurs and halves a level
+    pub fn reduce(level: &Level) -> Level {
+        let blurred = convolve_plane_separable(&level.data, level.width, level.height, &BINOMIAL, &BINOMIAL, BorderMode::Reflect);
+        let (width, height) = (level.width.div_ceil(2), level.height.div_ceil(2));
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                data.push(blurred[2 * y * level.width + 2 * x]);
+            }
+        }
+        Level { data, width, height }
+    }
+
+    // doubles a level up to width x height, interpolating the new pixels
+    pub fn expand(level: &Level, width: usize, height: usize) -> Level {
+        let mut spread = vec![0f32; width * height];
+        for y in 0..level.height.min(height.div_ceil(2)) {
+            for x in 0..level.width.min(width.div_ceil(2)) {
+                spread[2 * y * width + 2 * x] = 4.0 * level.data[y * level.width + x];
+            }
+        }
+        let data = convolve_plane_separable(&spread, width, height, &BINOMIAL, &BINOMIAL, BorderMode::Reflect);
+        Level { data, width, height }
+    }
+
+    pub fn gaussian_pyramid(plane: &[f32], width: usize, height: usize, levels: usize) -> Vec<Level> {
+        let mut pyramid = vec![Level {
+            data: plane.to_vec(),
+            width,
+            height,
+        }];
+        while pyramid.len() < levels {
+            let next = reduce(pyramid.last().unwrap());
+            pyramid.push(next);
+        }
+        pyramid
+    }
+
+    // the details lost between each level and the next, the last level
+    // keeps the remaining low frequencies
+    pub fn laplacian_pyramid(plane: &[f32], width: usize, height: usize, levels: usize) -> Vec<Level> {
+        let mut pyramid = gaussian_pyramid(plane, width, height, levels);
+        for i in 0..pyramid.len() - 1 {
+            let expanded = expand(&pyramid[i + 1], pyramid[i].width, pyramid[i].height);
+            for (value, e) in pyramid[i].data.iter_mut().zip(expanded.data.iter()) {
+                *value -= e;
+            }
+        }
+        pyramid
+    }
+
+    // rebuilds a plane from a laplacian pyramid
+    pub fn collapse_pyramid(pyramid: &[Level]) -> Vec<f32> {
+        let mut current = pyramid.last().unwrap().clone();
+        for level in pyramid.iter().rev().skip(1) {
+            let mut expanded = expand(&current, level.width, level.height);
+            for (value, d) in expanded.data.iter_mut().zip(level.data.iter()) {
+                *value += d;
+            }
+            current = expanded;
+        }
+        current.data
+    }
+
+    // an empty pyramid with the level sizes of another one
+    pub fn zero_pyramid(like: &[Level]) -> Vec<Level> {
+        like.iter()
+            .map(|l| Level {
+                data: vec![0.0; l.data.len()],
+                width: l.width,
+                height: l.height,
+            })
+            .collect()
+    }
+
+    // adds a pyramid times the weights of the same level to a sum
+    pub fn accumulate_pyramid(sum: &mut [Level], pyramid: &[Level], weights: &[Level]) {
+        for ((out, level), weight) in sum.iter_mut().zip(pyramid.iter()).zip(weights.iter()) {
+            for ((o, v), w) in out.data.iter_mut().zip(level.data.iter()).zip(weight.data.iter()) {
+                *o += v * w;
+            }
+        }
+    }
+
+    // blends planes with per pixel weights level by level, the weights
+    // already summing to one at each pixel
+    pub fn blend_planes(planes: &[&[f32]], weights: &[Vec<Level>], width: usize, height: usize) -> Vec<f32> {
+        let levels = weights[0].len();
+        let mut blended: Vec<Level> = Vec::new();
+        for (plane, weight) in planes.iter().zip(weights.iter()) {
+            let laplacian = laplacian_pyramid(plane, width, height, levels);
+            if blended.is_empty() {
+                blended = zero_pyramid(&laplacian);
+            }
+            accumulate_pyramid(&mut blended, &laplacian, weight);
+        }
+        collapse_pyramid(&blended)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn collapse_undoes_laplacian() {
+            for (width, height) in [(37, 23), (16, 17), (5, 9), (1, 1)] {
+                let plane: Vec<f32> = (0..width * height).map(|i| ((i * 7919) % 256) as f32).collect();
+                let levels = pyramid_levels(width, height);
+                let pyramid = laplacian_pyramid(&plane, width, height, levels);
+                assert_eq!(pyramid.len(), levels);
+                let collapsed = collapse_pyramid(&pyramid);
+                assert_eq!(collapsed.len(), plane.len());
+                for (a, b) in collapsed.iter().zip(plane.iter()) {
+                    assert!((a - b).abs() < 1e-3, "{}x{}: {} {}", width, height, a, b);
+                }
+            }
+        }
+    }
+}
+
+pub use pyramid::*;
@@ -42,6 +42,10 @@ pub mod rustyexif {
         };
     }
 
+    pub fn get_exif_value(fields: &[ExifField], name: &str) -> Option<String> {
+        fields.iter().find(|f| f.name == name).map(|f| f.value.clone())
+    }
+
     pub fn write_exif_to_file(path: &str, fields: Vec<ExifField>) -> u8
     {
 
@@ -2,28 +2,121 @@ pub mod rustyimg {
 
     use imgproc_rs::image::{BaseImage, Image, ImageInfo};
     pub use crate::config::*;
+    use crate::crop::*;
+    use crate::grayscale::*;
+    use crate::mixer::*;
+    use crate::equalize::*;
+    use crate::tone::*;
+    use crate::lut::*;
+    use crate::whitebalance::*;
+    use crate::color::*;
+    use crate::toning::*;
+    use crate::convolve::*;
+    use crate::denoise::*;
+    use crate::edge::*;
+    use crate::morphology::*;
+    use crate::threshold::*;
+    use crate::deskew::*;
+    use crate::watermark::*;
+    use crate::canvas::*;
+    use crate::lens::*;
+
+    use crate::log;
 
     // process the image
     pub fn process_image(img: &Image<u8>, opts: &ConfigOptions) -> Image<u8> {
+        // straighten documents before cropping them
+        let straightened;
+        let img = if opts.perspective.is_empty() && opts.deskew.is_empty() {
+            img
+        } else {
+            straightened = straighten_image(img, opts);
+            &straightened
+        };
+
+        // crop first, so the other steps only look at the kept area
+        let cropped;
+        let img = if opts.crop.is_empty() {
+            img
+        } else {
+            match parse_crop(&opts.crop, &opts.gravity) {
+                Some(mode) => match crop_image(img, mode) {
+                    Some(c) => {
+                        cropped = c;
+                        &cropped
+                    }
+                    None => img,
+                },
+                None => {
+                    println!("Invalid crop specification {}", opts.crop);
+                    img
+                }
+            }
+        };
+
         let (width, height, channels) = img.info().whc();
         let mut img2;
 
         let is_grayscale = channels == 1;
         // should we convert to grayscale?
         let to_gray = opts.grayscale && !is_grayscale && opts.force;
+        let gray_method = match parse_gray_method(&opts.gray_method) {
+            Some(method) => method,
+            None => {
+                println!("Invalid grayscale method {}, using rec601", opts.gray_method);
+                GrayMethod::Rec601
+            }
+        };
 
-        // get the min and max values for each channel
-        let mut min = [0, 0, 0];
-        let mut max = [255, 255, 255];
+        // build the autocontrast lookup table for each channel
+        let mut lut = [identity_lut(), identity_lut(), identity_lut()];
         if opts.autocontrast {
-            for index in 0..channels {
-                let i = index as usize;
-                let (min1, max1) = get_channel_ranges(img, i);
-                min[i] = min1;
-                max[i] = max1;
+            let (low, high) = match parse_clip(&opts.clip) {
+                Some(clip) => clip,
+                None => {
+                    println!("Invalid clip percentages {}, not clipping", opts.clip);
+                    (0.0, 0.0)
+                }
+            };
+            if opts.linked && channels >= 3 {
+                // stretch the luminance only, keeping the color balance
+                let (min, max) = histogram_range(&get_luma_histogram(img), low, high);
+                for table in lut.iter_mut() {
+                    *table = stretch_lut(min, max);
+                }
+            } else {
+                for index in 0..channels.min(3) {
+                    let i = index as usize;
+                    let (min, max) = get_channel_ranges(img, i, low, high);
+                    lut[i] = stretch_lut(min, max);
+                }
+            }
+        }
+
+        // white balance, measured on the autocontrast result
+        if !opts.white_balance.is_empty() {
+            if is_grayscale_image(img) {
+                log!(" > Skipping white balance of a grayscale image");
+            } else {
+                match parse_white_balance(&opts.white_balance) {
+                    Some(mode) => {
+                        let gains = white_balance_gains(img, mode, &lut);
+                        log!(" > White balance gains {:.3} {:.3} {:.3}", gains[0], gains[1], gains[2]);
+                        for i in 0..3 {
+                            lut[i] = compose_lut(&lut[i], &gain_lut(gains[i]));
+                        }
+                    }
+                    None => println!("Invalid white balance {}", opts.white_balance),
+                }
             }
         }
 
+        // levels, curves, gamma, brightness and contrast run in the same pass
+        let tone = tone_luts(opts);
+        for i in 0..3 {
+            lut[i] = compose_lut(&lut[i], &tone[i]);
+        }
+
         img2 = Image::blank(ImageInfo::new(
             width,
             height,
@@ -48,51 +141,83 @@ pub mod rustyimg {
                 let n = pixel.len();
                 let mut tpixel: [u8; 3] = [0, 0, 0];
                 for i in 0..n {
-                    let mut value = pixel[i];
-                    if opts.autocontrast {
-                        // ((pixel – min) / (max – min))*255 [+ min?]
-                        let adjusted: f32 =
-                            255.0 * (value - min[i]) as f32 / (max[i] - min[i]) as f32;
-                        if adjusted > 255.0 {
-                            value = 255;
-                        } else if adjusted < 0.0 {
-                            value = 0;
-                        } else {
-                            value = adjusted as u8;
-                        }
-                    }
+                    tpixel[i] = lut[i][pixel[i] as usize];
+                }
 
-                    if n == 1 {
-                        // is grayscale image
-                        if opts.invert {
-                            value = 255 - value;
-                        }
-                        img2.set_pixel(xi, yi, &[value]);
-                    } else {
-                        if to_gray {
-                            // convert to grayscale
-                            let (r1, g1, b1) = (pixel[0], pixel[1], pixel[2]);
-                            let r = r1 as f32 * 0.299 as f32;
-                            let g = b1 as f32 * 0.587 as f32;
-                            let b = g1 as f32 * 0.114 as f32;
-
-                            value = if opts.invert {
-                                255 - (r + g + b) as u8
-                            } else {
-                                (r + g + b) as u8
-                            };
-                            img2.set_pixel(xi, yi, &[value]);
-                        } else {
-                            tpixel[i] = if opts.invert { 255 - value } else { value };
+                if to_gray {
+                    // convert to grayscale
+                    let value = gray_value(&tpixel, gray_method, opts.linear);
+                    img2.set_pixel(xi, yi, &[if opts.invert { 255 - value } else { value }]);
+                } else {
+                    if opts.invert {
+                        for value in tpixel.iter_mut() {
+                            *value = 255 - *value;
                         }
                     }
+                    img2.set_pixel(xi, yi, &tpixel[0..n]);
                 }
-                if !to_gray {
-                    // is color image
-                    img2.set_pixel(xi, yi, &tpixel);
+            }
+        }
+
+        // remove noise before anything amplifies it
+        img2 = apply_denoise(img2, opts);
+
+        // adaptive contrast on the luminance
+        if opts.equalize {
+            img2 = equalize_image(&img2);
+        }
+        if !opts.clahe.is_empty() {
+            match parse_clahe(&opts.clahe) {
+                Some((cols, rows, clip)) => img2 = clahe_image(&img2, cols, rows, clip),
+                None => println!("Invalid CLAHE settings {}", opts.clahe),
+            }
+        }
+
+        // saturation, vibrance and hue changes
+        let adjustments = color_adjustments(opts);
+        if !adjustments.is_empty() && img2.info().channels_non_alpha() >= 3 {
+            img2 = adjust_colors(&img2, &adjustments);
+        }
+
+        // apply a color grading LUT
+        if !opts.lut.is_empty() {
+            match parse_interpolation(&opts.lut_interpolation) {
+                Some(interpolation) => {
+                    if let Some(graded) = apply_lut_file(&img2, &opts.lut, interpolation) {
+                        img2 = graded;
+                    }
                 }
+                None => println!("Invalid LUT interpolation {}", opts.lut_interpolation),
             }
         }
+
+        // mix the channels of color images
+        if !opts.mixer.is_empty() && img2.info().whc().2 == 3 {
+            match parse_mixer(&opts.mixer) {
+                Some(mixer) => img2 = mix_channels(&img2, &mixer, opts.linear),
+                None => println!("Invalid channel mixer {}", opts.mixer),
+            }
+        }
+
+        // sepia, duotone and split toning, also on grayscale results
+        for toning in tonings(opts) {
+            img2 = tone_image(&img2, toning);
+        }
+
+        // blur and sharpen on the final colors, then the vignette
+        img2 = apply_filters(img2, opts);
+        img2 = apply_vignette(img2, opts);
+
+        // edges, thresholding and morphology give a single channel image
+        img2 = apply_edges(img2, opts);
+        img2 = apply_threshold(img2, opts);
+        img2 = apply_morphology(img2, opts);
+
+        // padding and borders around the finished image
+        img2 = apply_canvas(img2, opts);
+
+        // the watermark goes on top of the finished image
+        img2 = apply_watermark(img2, opts, "");
         return img2;
     }
 
@@ -109,12 +234,111 @@ pub mod rustyimg {
         return channel_data;
     }
 
-    // extracts a single channel from an image
-    fn get_channel_ranges(img: &Image<u8>, channel: usize) -> (u8, u8) {
-        let channel_data = get_channel(img, channel);
-        let min = channel_data.iter().min().unwrap();
-        let max = channel_data.iter().max().unwrap();
-        return (*min, *max);
+    // replaces a single channel of an image, in the order of get_channel
+    pub fn set_channel(img: &mut Image<u8>, channel: usize, channel_data: &[u8]) {
+        let (width, height) = img.info().wh();
+        let mut values = channel_data.iter();
+        for x in 0..width {
+            for y in 0..height {
+                let mut pixel = img.get_pixel(x, y).to_vec();
+                pixel[channel] = *values.next().unwrap_or(&pixel[channel]);
+                img.set_pixel(x, y, &pixel);
+            }
+        }
+    }
+
+    // counts the occurrences of each value in a single channel
+    pub fn get_channel_histogram(img: &Image<u8>, channel: usize) -> [u32; 256] {
+        let mut histogram = [0u32; 256];
+        for value in get_channel(img, channel) {
+            histogram[value as usize] += 1;
+        }
+        histogram
+    }
+
+    // counts the occurrences of each rec601 luma value
+    pub fn get_luma_histogram(img: &Image<u8>) -> [u32; 256] {
+        let mut histogram = [0u32; 256];
+        let (width, height) = img.info().wh();
+        for y in 0..height {
+            for x in 0..width {
+                let value = gray_value(img.get_pixel(x, y), GrayMethod::Rec601, false);
+                histogram[value as usize] += 1;
+            }
+        }
+        histogram
+    }
+
+    // gets the range of a channel, ignoring the darkest `low` and
+    // brightest `high` percent of the pixels
+    fn get_channel_ranges(img: &Image<u8>, channel: usize, low: f32, high: f32) -> (u8, u8) {
+        histogram_range(&get_channel_histogram(img, channel), low, high)
+    }
+
+    // finds the values below and above which the given percentages of the
+    // histogram lie; returns the full range if nothing would be left
+    pub fn histogram_range(histogram: &[u32; 256], low: f32, high: f32) -> (u8, u8) {
+        let total: u64 = histogram.iter().map(|&c| c as u64).sum();
+        let low_count = (total as f64 * low as f64 / 100.0) as u64;
+        let high_count = (total as f64 * high as f64 / 100.0) as u64;
+
+        let mut min = 0;
+        let mut sum = 0u64;
+        for (value, &count) in histogram.iter().enumerate() {
+            sum += count as u64;
+            if sum > low_count {
+                min = value;
+                break;
+            }
+        }
+
+        let mut max = 255;
+        sum = 0;
+        for (value, &count) in histogram.iter().enumerate().rev() {
+            sum += count as u64;
+            if sum > high_count {
+                max = value;
+                break;
+            }
+        }
+
+        if min >= max {
+            return (0, 255);
+        }
+        (min as u8, max as u8)
+    }
+
+    // parses the autocontrast clip percentages as LOW[,HIGH]
+    pub fn parse_clip(spec: &str) -> Option<(f32, f32)> {
+        let mut parts = spec.split(',');
+        let low = parts.next()?.trim().parse::<f32>().ok()?;
+        let high = match parts.next() {
+            Some(h) => h.trim().parse::<f32>().ok()?,
+            None => low,
+        };
+        if parts.next().is_some() || !(0.0..50.0).contains(&low) || !(0.0..50.0).contains(&high) {
+            return None;
+        }
+        Some((low, high))
+    }
+
+    // a lookup table leaving the values unchanged
+    pub fn identity_lut() -> [u8; 256] {
+        let mut lut = [0u8; 256];
+        for (i, value) in lut.iter_mut().enumerate() {
+            *value = i as u8;
+        }
+        lut
+    }
+
+    // a lookup table stretching min..max to the full range
+    pub fn stretch_lut(min: u8, max: u8) -> [u8; 256] {
+        let mut lut = [0u8; 256];
+        let range = (max as f32 - min as f32).max(1.0);
+        for (i, value) in lut.iter_mut().enumerate() {
+            *value = round_u8(255.0 * (i as f32 - min as f32) / range);
+        }
+        lut
     }
 
     // check if the source image is grayscale
@@ -145,6 +369,57 @@ pub mod rustyimg {
         }
         return true;
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        // one pixel of each value from 0 to 99
+        fn ramp() -> [u32; 256] {
+            let mut histogram = [0u32; 256];
+            histogram[..100].iter_mut().for_each(|c| *c = 1);
+            histogram
+        }
+
+        #[test]
+        fn histogram_range_clips() {
+            let mut histogram = [0u32; 256];
+            histogram[10] = 5;
+            histogram[200] = 7;
+            assert_eq!(histogram_range(&histogram, 0.0, 0.0), (10, 200));
+            assert_eq!(histogram_range(&ramp(), 0.0, 0.0), (0, 99));
+            assert_eq!(histogram_range(&ramp(), 1.0, 1.0), (1, 98));
+            assert_eq!(histogram_range(&ramp(), 1.0, 5.0), (1, 94));
+        }
+
+        #[test]
+        fn histogram_range_everything_clipped() {
+            // a single value leaves no range to stretch
+            let mut histogram = [0u32; 256];
+            histogram[128] = 1000;
+            assert_eq!(histogram_range(&histogram, 0.0, 0.0), (0, 255));
+            // both clips end in the peak
+            let mut histogram = [0u32; 256];
+            histogram[10] = 10;
+            histogram[11] = 80;
+            histogram[12] = 10;
+            assert_eq!(histogram_range(&histogram, 20.0, 20.0), (0, 255));
+            assert_eq!(histogram_range(&histogram, 5.0, 5.0), (10, 12));
+            assert_eq!(histogram_range(&[0u32; 256], 1.0, 1.0), (0, 255));
+        }
+
+        #[test]
+        fn clip_values() {
+            assert_eq!(parse_clip("0"), Some((0.0, 0.0)));
+            assert_eq!(parse_clip("1"), Some((1.0, 1.0)));
+            assert_eq!(parse_clip(" 1 , 5 "), Some((1.0, 5.0)));
+            assert_eq!(parse_clip("50"), None);
+            assert_eq!(parse_clip("-1"), None);
+            assert_eq!(parse_clip("1,2,3"), None);
+            assert_eq!(parse_clip("1,"), None);
+            assert_eq!(parse_clip("a"), None);
+        }
+    }
 }
 
 pub use rustyimg::*;
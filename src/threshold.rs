@@ -0,0 +1,175 @@
+pub mod threshold {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    pub use crate::config::*;
+
+    use crate::convolve::*;
+    use crate::edge::*;
+    use crate::log;
+
+    // the supported thresholding methods
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Threshold {
+        // pixels above the level become white
+        Fixed(u8),
+        // the level separating the histogram into two classes best
+        Otsu,
+        // local level from the mean and deviation of a window, for uneven
+        // lighting: window size and k
+        Sauvola(usize, f32),
+        // local level from the mean of a window minus an offset
+        Mean(usize, f32),
+    }
+
+    // parses a level (0-255), otsu, sauvola[,WINDOW[,K]] or mean[,WINDOW[,OFFSET]]
+    // (defaults: window 25, k 0.2, offset 10)
+    pub fn parse_threshold(spec: &str) -> Option<Threshold> {
+        let spec = spec.trim().to_lowercase();
+        let mut parts = spec.split(',');
+        let name = parts.next()?;
+        let values: Vec<f32> = parts
+            .map(|v| v.trim().parse::<f32>())
+            .collect::<Result<_, _>>()
+            .ok()?;
+        if values.len() > 2 {
+            return None;
+        }
+        let window = match values.first() {
+            Some(&w) if w >= 3.0 => w.round() as usize,
+            Some(_) => return None,
+            None => 25,
+        };
+        match name {
+            "otsu" if values.is_empty() => Some(Threshold::Otsu),
+            "sauvola" => {
+                let k = *values.get(1).unwrap_or(&0.2);
+                if !(0.0..=1.0).contains(&k) {
+                    return None;
+                }
+                Some(Threshold::Sauvola(window, k))
+            }
+            "mean" => Some(Threshold::Mean(window, *values.get(1).unwrap_or(&10.0))),
+            level if values.is_empty() => Some(Threshold::Fixed(level.parse::<u8>().ok()?)),
+            _ => None,
+        }
+    }
+
+    // checks if an image is single channel black and white, so it can be
+    // saved with one bit per pixel
+    pub fn is_binary_image(img: &Image<u8>) -> bool {
+        img.info().channels == 1 && img.data().iter().all(|&v| v == 0 || v == 255)
+    }
+
+    // the otsu level of a histogram: the split maximizing the variance
+    // between the dark and the light class
+    pub fn otsu_level(histogram: &[u32; 256]) -> u8 {
+        let total: f64 = histogram.iter().map(|&n| n as f64).sum();
+        let sum: f64 = histogram.iter().enumerate().map(|(v, &n)| v as f64 * n as f64).sum();
+        let mut dark = 0.0;
+        let mut dark_sum = 0.0;
+        let mut best = (0.0, 0u8);
+        for (level, &n) in histogram.iter().enumerate() {
+            dark += n as f64;
+            dark_sum += level as f64 * n as f64;
+            let light = total - dark;
+            if dark == 0.0 || light == 0.0 {
+                continue;
+            }
+            let diff = dark_sum / dark - (sum - dark_sum) / light;
+            let variance = dark * light * diff * diff;
+            if variance > best.0 {
+                best = (variance, level as u8);
+            }
+        }
+        best.1
+    }
+
+    // summed area tables of the values and their squares, one row and
+    // column larger than the plane
+    fn integral_planes(plane: &[f32], width: usize, height: usize) -> (Vec<f64>, Vec<f64>) {
+        let stride = width + 1;
+        let mut sums = vec![0f64; stride * (height + 1)];
+        let mut squares = vec![0f64; stride * (height + 1)];
+        for y in 0..height {
+            let mut row = 0.0;
+            let mut row2 = 0.0;
+            for x in 0..width {
+                let v = plane[y * width + x] as f64;
+                row += v;
+                row2 += v * v;
+                sums[(y + 1) * stride + x + 1] = sums[y * stride + x + 1] + row;
+                squares[(y + 1) * stride + x + 1] = squares[y * stride + x + 1] + row2;
+            }
+        }
+        (sums, squares)
+    }
+
+    // thresholds an image into a single channel black and white image
+    pub fn threshold_image(img: &Image<u8>, method: Threshold) -> Image<u8> {
+        let (width, height) = img.info().wh();
+        let (w, h) = (width as usize, height as usize);
+        let plane = luma_plane(img);
+
+        let out: Vec<f32> = match method {
+            Threshold::Fixed(_) | Threshold::Otsu => {
+                let level = match method {
+                    Threshold::Fixed(level) => level,
+                    _ => {
+                        let mut histogram = [0u32; 256];
+                        for &v in plane.iter() {
+                            histogram[v as usize] += 1;
+                        }
+                        let level = otsu_level(&histogram);
+                        log!(" > Otsu threshold {}", level);
+                        level
+                    }
+                };
+                plane.iter().map(|&v| if v > level as f32 { 255.0 } else { 0.0 }).collect()
+            }
+            Threshold::Sauvola(window, _) | Threshold::Mean(window, _) => {
+                let (sums, squares) = integral_planes(&plane, w, h);
+                let r = window / 2;
+                let stride = w + 1;
+                let mut out = vec![0f32; plane.len()];
+                for y in 0..h {
+                    let (y0, y1) = (y.saturating_sub(r), (y + r + 1).min(h));
+                    for x in 0..w {
+                        let (x0, x1) = (x.saturating_sub(r), (x + r + 1).min(w));
+                        let area = ((x1 - x0) * (y1 - y0)) as f64;
+                        let rect = |t: &[f64]| t[y1 * stride + x1] - t[y0 * stride + x1] - t[y1 * stride + x0] + t[y0 * stride + x0];
+                        let mean = rect(&sums) / area;
+                        let level = match method {
+                            Threshold::Sauvola(_, k) => {
+                                let deviation = (rect(&squares) / area - mean * mean).max(0.0).sqrt();
+                                mean * (1.0 + k as f64 * (deviation / 128.0 - 1.0))
+                            }
+                            Threshold::Mean(_, offset) => mean - offset as f64,
+                            _ => mean,
+                        };
+                        if plane[y * w + x] as f64 > level {
+                            out[y * w + x] = 255.0;
+                        }
+                    }
+                }
+                out
+            }
+        };
+        from_planes(&[out], width, height, false)
+    }
+
+    // runs the threshold option
+    pub fn apply_threshold(img: Image<u8>, opts: &ConfigOptions) -> Image<u8> {
+        if opts.threshold.is_empty() {
+            return img;
+        }
+        match parse_threshold(&opts.threshold) {
+            Some(method) => threshold_image(&img, method),
+            None => {
+                println!("Invalid threshold {}", opts.threshold);
+                img
+            }
+        }
+    }
+}
+
+pub use threshold::*;
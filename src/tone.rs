@@ -0,0 +1,226 @@
+pub mod tone {
+
+    use imgproc_rs::image::{BaseImage, Image, ImageInfo};
+    pub use crate::config::*;
+
+    use crate::grayscale::*;
+    use crate::rustyimg::*;
+
+    // input levels of a channel
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Levels {
+        pub black: u8,
+        pub white: u8,
+        // midtone gamma, above 1 brightens
+        pub gamma: f32,
+    }
+
+    // parses levels as BLACK,WHITE[,GAMMA], either once for all channels
+    // or three times separated by ';' for red, green and blue
+    pub fn parse_levels(spec: &str) -> Option<Vec<Levels>> {
+        let mut levels = Vec::new();
+        for part in spec.split(';') {
+            let values: Vec<f32> = part
+                .split(',')
+                .map(|v| v.trim().parse::<f32>())
+                .collect::<Result<_, _>>()
+                .ok()?;
+            if values.len() < 2 || values.len() > 3 {
+                return None;
+            }
+            let gamma = if values.len() == 3 { values[2] } else { 1.0 };
+            if values[0] < 0.0 || values[1] > 255.0 || values[0] >= values[1] || gamma <= 0.0 {
+                return None;
+            }
+            levels.push(Levels {
+                black: values[0] as u8,
+                white: values[1] as u8,
+                gamma,
+            });
+        }
+        per_channel(levels)
+    }
+
+    // parses curves as X:Y control points separated by ',', either once for
+    // all channels or three times separated by ';' for red, green and blue
+    pub fn parse_curves(spec: &str) -> Option<Vec<Vec<(f32, f32)>>> {
+        let mut curves = Vec::new();
+        for part in spec.split(';') {
+            let mut points = Vec::new();
+            for point in part.split(',') {
+                let (x, y) = point.split_once(':')?;
+                let x = x.trim().parse::<f32>().ok()?;
+                let y = y.trim().parse::<f32>().ok()?;
+                if !(0.0..=255.0).contains(&x) || !(0.0..=255.0).contains(&y) {
+                    return None;
+                }
+                points.push((x, y));
+            }
+            points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            points.dedup_by(|a, b| a.0 == b.0);
+            if points.len() < 2 {
+                return None;
+            }
+            curves.push(points);
+        }
+        per_channel(curves)
+    }
+
+    // accepts one value for all channels or exactly three
+    fn per_channel<T: Clone>(values: Vec<T>) -> Option<Vec<T>> {
+        match values.len() {
+            1 => Some(vec![values[0].clone(), values[0].clone(), values[0].clone()]),
+            3 => Some(values),
+            _ => None,
+        }
+    }
+
+    // builds a lookup table from a function over 0.0-255.0
+    fn build_lut<F: Fn(f32) -> f32>(f: F) -> [u8; 256] {
+        let mut lut = [0u8; 256];
+        for (i, value) in lut.iter_mut().enumerate() {
+            *value = round_u8(f(i as f32));
+        }
+        lut
+    }
+
+    // stretches black..white to the full range and applies the midtone gamma
+    pub fn levels_lut(levels: &Levels) -> [u8; 256] {
+        let black = levels.black as f32;
+        let range = levels.white as f32 - black;
+        build_lut(|v| 255.0 * ((v - black) / range).clamp(0.0, 1.0).powf(1.0 / levels.gamma))
+    }
+
+    // gamma above 1 brightens the midtones
+    pub fn gamma_lut(gamma: f32) -> [u8; 256] {
+        build_lut(|v| 255.0 * (v / 255.0).powf(1.0 / gamma))
+    }
+
+    // adds brightness (-255 to 255) to every value
+    pub fn brightness_lut(brightness: f32) -> [u8; 256] {
+        build_lut(|v| v + brightness)
+    }
+
+    // contrast from -100 (flat gray) to 100 (4x), pivoting around the middle
+    pub fn contrast_lut(contrast: f32) -> [u8; 256] {
+        let factor = ((100.0 + contrast.clamp(-100.0, 100.0)) / 100.0).powi(2);
+        build_lut(|v| (v - 127.5) * factor + 127.5)
+    }
+
+    // a natural cubic spline through the control points, flat outside them
+    pub fn curve_lut(points: &[(f32, f32)]) -> [u8; 256] {
+        let n = points.len();
+        let xs: Vec<f32> = points.iter().map(|p| p.0).collect();
+        let ys: Vec<f32> = points.iter().map(|p| p.1).collect();
+
+        // solve the tridiagonal system for the second derivatives
+        let mut m = vec![0.0f32; n];
+        if n > 2 {
+            let mut c = vec![0.0f32; n];
+            let mut d = vec![0.0f32; n];
+            for i in 1..n - 1 {
+                let h0 = xs[i] - xs[i - 1];
+                let h1 = xs[i + 1] - xs[i];
+                let a = h0;
+                let b = 2.0 * (h0 + h1);
+                let r = 6.0 * ((ys[i + 1] - ys[i]) / h1 - (ys[i] - ys[i - 1]) / h0);
+                let denom = b - a * c[i - 1];
+                c[i] = h1 / denom;
+                d[i] = (r - a * d[i - 1]) / denom;
+            }
+            for i in (1..n - 1).rev() {
+                m[i] = d[i] - c[i] * m[i + 1];
+            }
+        }
+
+        build_lut(|x| {
+            if x <= xs[0] {
+                return ys[0];
+            }
+            if x >= xs[n - 1] {
+                return ys[n - 1];
+            }
+            let i = xs.iter().rposition(|&xi| xi <= x).unwrap().min(n - 2);
+            let h = xs[i + 1] - xs[i];
+            let a = (xs[i + 1] - x) / h;
+            let b = (x - xs[i]) / h;
+            a * ys[i] + b * ys[i + 1] + ((a * a * a - a) * m[i] + (b * b * b - b) * m[i + 1]) * h * h / 6.0
+        })
+    }
+
+    // a lookup table applying `first` and then `then`
+    pub fn compose_lut(first: &[u8; 256], then: &[u8; 256]) -> [u8; 256] {
+        let mut lut = [0u8; 256];
+        for (i, value) in lut.iter_mut().enumerate() {
+            *value = then[first[i] as usize];
+        }
+        lut
+    }
+
+    // compiles the levels, curves, gamma, brightness and contrast options
+    // into one lookup table per channel; invalid options are reported and skipped
+    pub fn tone_luts(opts: &ConfigOptions) -> [[u8; 256]; 3] {
+        let mut luts = [identity_lut(), identity_lut(), identity_lut()];
+
+        if !opts.levels.is_empty() {
+            match parse_levels(&opts.levels) {
+                Some(levels) => {
+                    for i in 0..3 {
+                        luts[i] = compose_lut(&luts[i], &levels_lut(&levels[i]));
+                    }
+                }
+                None => println!("Invalid levels {}", opts.levels),
+            }
+        }
+
+        if !opts.curves.is_empty() {
+            match parse_curves(&opts.curves) {
+                Some(curves) => {
+                    for i in 0..3 {
+                        luts[i] = compose_lut(&luts[i], &curve_lut(&curves[i]));
+                    }
+                }
+                None => println!("Invalid curves {}", opts.curves),
+            }
+        }
+
+        let mut adjust = |spec: &str, name: &str, valid: fn(f32) -> bool, lut: fn(f32) -> [u8; 256]| {
+            if spec.is_empty() {
+                return;
+            }
+            match spec.trim().parse::<f32>() {
+                Ok(value) if valid(value) => {
+                    let table = lut(value);
+                    for channel in luts.iter_mut() {
+                        *channel = compose_lut(channel, &table);
+                    }
+                }
+                _ => println!("Invalid {} {}", name, spec),
+            }
+        };
+        adjust(&opts.gamma, "gamma", |v| v > 0.0, gamma_lut);
+        adjust(&opts.brightness, "brightness", |v| (-255.0..=255.0).contains(&v), brightness_lut);
+        adjust(&opts.contrast, "contrast", |v| (-100.0..=100.0).contains(&v), contrast_lut);
+
+        luts
+    }
+
+    // applies per channel lookup tables to an image, leaving alpha alone
+    pub fn apply_luts(img: &Image<u8>, luts: &[[u8; 256]; 3]) -> Image<u8> {
+        let (width, height, channels, alpha) = img.info().whca();
+        let colors = img.info().channels_non_alpha() as usize;
+        let mut img2 = Image::blank(ImageInfo::new(width, height, channels, alpha));
+        for y in 0..height {
+            for x in 0..width {
+                let mut pixel = img.get_pixel(x, y).to_vec();
+                for (value, lut) in pixel.iter_mut().take(colors).zip(luts.iter()) {
+                    *value = lut[*value as usize];
+                }
+                img2.set_pixel(x, y, &pixel);
+            }
+        }
+        img2
+    }
+}
+
+pub use tone::*;
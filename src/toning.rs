@@ -0,0 +1,156 @@
+pub mod toning {
+
+    use imgproc_rs::image::{BaseImage, Image, ImageInfo};
+    pub use crate::config::*;
+
+    use crate::color::*;
+    use crate::grayscale::*;
+
+    // the supported toning operations
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Toning {
+        // classic sepia with a strength from 0 to 100 percent
+        Sepia(f32),
+        // maps black to the first color and white to the second
+        Duotone([u8; 3], [u8; 3]),
+        // tints shadows and highlights with a balance (-100 to 100,
+        // positive favors the highlights) and a strength (0 to 100 percent)
+        SplitTone([u8; 3], [u8; 3], f32, f32),
+    }
+
+    // parses the sepia strength, 100 when empty
+    pub fn parse_sepia(spec: &str) -> Option<Toning> {
+        let amount = match spec.trim() {
+            "" => 100.0,
+            s => s.parse::<f32>().ok()?,
+        };
+        if !(0.0..=100.0).contains(&amount) {
+            return None;
+        }
+        Some(Toning::Sepia(amount))
+    }
+
+    // parses duotone colors as SHADOW,HIGHLIGHT
+    pub fn parse_duotone(spec: &str) -> Option<Toning> {
+        let colors = split_list(spec);
+        if colors.len() != 2 {
+            return None;
+        }
+        Some(Toning::Duotone(parse_color(colors[0])?, parse_color(colors[1])?))
+    }
+
+    // parses split toning as SHADOW,HIGHLIGHT[,BALANCE[,STRENGTH]]
+    pub fn parse_split_tone(spec: &str) -> Option<Toning> {
+        let parts = split_list(spec);
+        if parts.len() < 2 || parts.len() > 4 {
+            return None;
+        }
+        let balance = match parts.get(2) {
+            Some(b) => b.parse::<f32>().ok()?,
+            None => 0.0,
+        };
+        let strength = match parts.get(3) {
+            Some(s) => s.parse::<f32>().ok()?,
+            None => 30.0,
+        };
+        if !(-100.0..=100.0).contains(&balance) || !(0.0..=100.0).contains(&strength) {
+            return None;
+        }
+        Some(Toning::SplitTone(
+            parse_color(parts[0])?,
+            parse_color(parts[1])?,
+            balance,
+            strength,
+        ))
+    }
+
+    // collects the toning options in the order they are applied
+    pub fn tonings(opts: &ConfigOptions) -> Vec<Toning> {
+        let mut list = Vec::new();
+        let mut add = |spec: &str, name: &str, parse: fn(&str) -> Option<Toning>| {
+            if spec.is_empty() {
+                return;
+            }
+            match parse(spec) {
+                Some(toning) => list.push(toning),
+                None => println!("Invalid {} {}", name, spec),
+            }
+        };
+        add(&opts.sepia, "sepia", parse_sepia);
+        add(&opts.duotone, "duotone", parse_duotone);
+        add(&opts.split_tone, "split toning", parse_split_tone);
+        list
+    }
+
+    // tones a single color
+    pub fn tone_color(rgb: [u8; 3], toning: Toning) -> [u8; 3] {
+        let (r, g, b) = (rgb[0] as f32, rgb[1] as f32, rgb[2] as f32);
+        match toning {
+            Toning::Sepia(amount) => {
+                let sepia = [
+                    0.393 * r + 0.769 * g + 0.189 * b,
+                    0.349 * r + 0.686 * g + 0.168 * b,
+                    0.272 * r + 0.534 * g + 0.131 * b,
+                ];
+                let t = amount / 100.0;
+                [
+                    round_u8(r + (sepia[0] - r) * t),
+                    round_u8(g + (sepia[1] - g) * t),
+                    round_u8(b + (sepia[2] - b) * t),
+                ]
+            }
+            Toning::Duotone(shadow, highlight) => {
+                let t = gray_value(&rgb, GrayMethod::Rec601, false) as f32 / 255.0;
+                let mut out = [0u8; 3];
+                for c in 0..3 {
+                    out[c] = round_u8(shadow[c] as f32 + (highlight[c] as f32 - shadow[c] as f32) * t);
+                }
+                out
+            }
+            Toning::SplitTone(shadow, highlight, balance, strength) => {
+                let luma = gray_value(&rgb, GrayMethod::Rec601, false) as f32 / 255.0;
+                // the point where shadows turn into highlights
+                let pivot = (0.5 - balance / 200.0).clamp(0.01, 0.99);
+                let (tint, weight) = if luma < pivot {
+                    (shadow, 1.0 - luma / pivot)
+                } else {
+                    (highlight, (luma - pivot) / (1.0 - pivot))
+                };
+                // shift by the tint's difference from its own gray, keeping the luminance
+                let tint_gray = gray_value(&tint, GrayMethod::Rec601, false) as f32;
+                let amount = weight * strength / 100.0;
+                let mut out = [0u8; 3];
+                for c in 0..3 {
+                    out[c] = round_u8(rgb[c] as f32 + (tint[c] as f32 - tint_gray) * amount);
+                }
+                out
+            }
+        }
+    }
+
+    // tones an image; single channel images become RGB
+    pub fn tone_image(img: &Image<u8>, toning: Toning) -> Image<u8> {
+        let (width, height, channels, alpha) = img.info().whca();
+        let colors = img.info().channels_non_alpha();
+        let out_channels = if colors < 3 { channels + 2 } else { channels };
+        let mut img2 = Image::blank(ImageInfo::new(width, height, out_channels, alpha));
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = img.get_pixel(x, y);
+                let rgb = if colors < 3 {
+                    [pixel[0], pixel[0], pixel[0]]
+                } else {
+                    [pixel[0], pixel[1], pixel[2]]
+                };
+                let mut out = tone_color(rgb, toning).to_vec();
+                if alpha {
+                    out.push(pixel[pixel.len() - 1]);
+                }
+                img2.set_pixel(x, y, &out);
+            }
+        }
+        img2
+    }
+}
+
+pub use toning::*;
@@ -0,0 +1,281 @@
+pub mod warp {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    pub use crate::config::*;
+
+    use crate::grayscale::*;
+
+    // a 3x3 projective transform stored row by row
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Homography {
+        pub m: [f64; 9],
+    }
+
+    impl Homography {
+        // maps a point, None when it goes to infinity
+        pub fn apply(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+            let m = &self.m;
+            let w = m[6] * x + m[7] * y + m[8];
+            if w.abs() < 1e-12 {
+                return None;
+            }
+            Some(((m[0] * x + m[1] * y + m[2]) / w, (m[3] * x + m[4] * y + m[5]) / w))
+        }
+
+        pub fn identity() -> Homography {
+            Homography {
+                m: [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            }
+        }
+
+        // the transform applying `other` first and then this one
+        pub fn multiply(&self, other: &Homography) -> Homography {
+            let (a, b) = (&self.m, &other.m);
+            let mut m = [0f64; 9];
+            for row in 0..3 {
+                for col in 0..3 {
+                    m[row * 3 + col] = (0..3).map(|k| a[row * 3 + k] * b[k * 3 + col]).sum();
+                }
+            }
+            Homography { m }
+        }
+
+        // the reverse transform, None when it is singular
+        pub fn inverse(&self) -> Option<Homography> {
+            let m = &self.m;
+            let cofactors = [
+                m[4] * m[8] - m[5] * m[7],
+                m[2] * m[7] - m[1] * m[8],
+                m[1] * m[5] - m[2] * m[4],
+                m[5] * m[6] - m[3] * m[8],
+                m[0] * m[8] - m[2] * m[6],
+                m[2] * m[3] - m[0] * m[5],
+                m[3] * m[7] - m[4] * m[6],
+                m[1] * m[6] - m[0] * m[7],
+                m[0] * m[4] - m[1] * m[3],
+            ];
+            let determinant = m[0] * cofactors[0] + m[1] * cofactors[3] + m[2] * cofactors[6];
+            if determinant.abs() < 1e-12 {
+                return None;
+            }
+            Some(Homography {
+                m: cofactors.map(|c| c / determinant),
+            })
+        }
+    }
+
+    // solves a square linear system with gaussian elimination and partial
+    // pivoting, None when it is singular
+    pub fn solve_linear(a: &mut [Vec<f64>], b: &mut [f64]) -> Option<Vec<f64>> {
+        let n = b.len();
+        for col in 0..n {
+            let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            b.swap(col, pivot);
+            let (top, rest) = a.split_at_mut(col + 1);
+            let pivot_row = &top[col];
+            for (offset, row) in rest.iter_mut().enumerate() {
+                let f = row[col] / pivot_row[col];
+                for (value, p) in row[col..].iter_mut().zip(pivot_row[col..].iter()) {
+                    *value -= f * p;
+                }
+                b[col + 1 + offset] -= f * b[col];
+            }
+        }
+        let mut x = vec![0f64; n];
+        for row in (0..n).rev() {
+            let sum: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+            x[row] = (b[row] - sum) / a[row][row];
+        }
+        Some(x)
+    }
+
+    // the homography mapping four points onto four other points
+    pub fn homography_from_points(from: &[(f64, f64); 4], to: &[(f64, f64); 4]) -> Option<Homography> {
+        let mut a = Vec::with_capacity(8);
+        let mut b = Vec::with_capacity(8);
+        for (&(x, y), &(u, v)) in from.iter().zip(to.iter()) {
+            a.push(vec![x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y]);
+            b.push(u);
+            a.push(vec![0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y]);
+            b.push(v);
+        }
+        let h = solve_linear(&mut a, &mut b)?;
+        Some(Homography {
+            m: [h[0], h[1], h[2], h[3], h[4], h[5], h[6], h[7], 1.0],
+        })
+    }
+
+    // the homography best mapping any number of points onto others in the
+    // least squares sense; the points are normalized first so the system
+    // stays well conditioned
+    pub fn fit_homography(from: &[(f64, f64)], to: &[(f64, f64)]) -> Option<Homography> {
+        if from.len() < 4 || from.len() != to.len() {
+            return None;
+        }
+        let normalize = |points: &[(f64, f64)]| {
+            let n = points.len() as f64;
+            let (cx, cy) = points.iter().fold((0.0, 0.0), |s, p| (s.0 + p.0 / n, s.1 + p.1 / n));
+            let spread = points.iter().map(|p| ((p.0 - cx).powi(2) + (p.1 - cy).powi(2)).sqrt()).sum::<f64>() / n;
+            let scale = std::f64::consts::SQRT_2 / spread.max(1e-12);
+            Homography {
+                m: [scale, 0.0, -scale * cx, 0.0, scale, -scale * cy, 0.0, 0.0, 1.0],
+            }
+        };
+        let (from_norm, to_norm) = (normalize(from), normalize(to));
+
+        // the normal equations of the 8 unknowns
+        let mut a = vec![vec![0f64; 8]; 8];
+        let mut b = vec![0f64; 8];
+        for (&p, &q) in from.iter().zip(to.iter()) {
+            let (x, y) = from_norm.apply(p.0, p.1)?;
+            let (u, v) = to_norm.apply(q.0, q.1)?;
+            for (row, target) in [
+                ([x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y], u),
+                ([0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y], v),
+            ] {
+                for i in 0..8 {
+                    for j in 0..8 {
+                        a[i][j] += row[i] * row[j];
+                    }
+                    b[i] += row[i] * target;
+                }
+            }
+        }
+        let h = solve_linear(&mut a, &mut b)?;
+        let fitted = Homography {
+            m: [h[0], h[1], h[2], h[3], h[4], h[5], h[6], h[7], 1.0],
+        };
+        Some(to_norm.inverse()?.multiply(&fitted).multiply(&from_norm))
+    }
+
+    // samples all channels at a fractional position with bilinear
+    // interpolation, clamping to the image edges
+    pub fn sample_bilinear(img: &Image<u8>, x: f32, y: f32, out: &mut [f32]) {
+        let (width, height) = img.info().wh();
+        let channels = img.info().channels as usize;
+        let x = x.clamp(0.0, (width - 1) as f32);
+        let y = y.clamp(0.0, (height - 1) as f32);
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let data = img.data();
+        let at = |px: u32, py: u32, c: usize| data[(py * width + px) as usize * channels + c] as f32;
+        for (c, value) in out.iter_mut().enumerate().take(channels) {
+            let top = at(x0, y0, c) * (1.0 - fx) + at(x1, y0, c) * fx;
+            let bottom = at(x0, y1, c) * (1.0 - fx) + at(x1, y1, c) * fx;
+            *value = top * (1.0 - fy) + bottom * fy;
+        }
+    }
+
+    // builds a width x height image where each pixel is sampled from the
+    // source position returned by `map`; positions mapped to None or
+    // outside the source get the fill color
+    pub fn warp_image<F>(img: &Image<u8>, width: u32, height: u32, fill: &[u8], map: F) -> Image<u8>
+    where
+        F: Fn(f32, f32) -> Option<(f32, f32)>,
+    {
+        let (src_width, src_height) = img.info().wh();
+        let channels = img.info().channels as usize;
+        let alpha = img.info().alpha;
+        let mut data = Vec::with_capacity((width * height) as usize * channels);
+        let mut pixel = vec![0f32; channels];
+        for y in 0..height {
+            for x in 0..width {
+                match map(x as f32, y as f32) {
+                    Some((sx, sy))
+                        if sx > -0.5 && sy > -0.5 && sx < src_width as f32 - 0.5 && sy < src_height as f32 - 0.5 =>
+                    {
+                        sample_bilinear(img, sx, sy, &mut pixel);
+                        data.extend(pixel.iter().map(|&v| round_u8(v)));
+                    }
+                    _ => data.extend((0..channels).map(|c| *fill.get(c).unwrap_or(&255))),
+                }
+            }
+        }
+        Image::from_vec(width, height, channels as u8, alpha, data)
+    }
+
+    // rotates an image clockwise around its center keeping its size;
+    // corners coming from outside get the fill color
+    pub fn rotate_image(img: &Image<u8>, degrees: f32, fill: &[u8]) -> Image<u8> {
+        let (width, height) = img.info().wh();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let (cx, cy) = ((width as f32 - 1.0) / 2.0, (height as f32 - 1.0) / 2.0);
+        warp_image(img, width, height, fill, |x, y| {
+            let (dx, dy) = (x - cx, y - cy);
+            Some((cx + dx * cos + dy * sin, cy - dx * sin + dy * cos))
+        })
+    }
+
+    // the largest centered rectangle with the image's aspect ratio that
+    // stays inside the image after rotating it, as (x, y, w, h)
+    pub fn rotated_crop_rect(width: u32, height: u32, degrees: f32) -> (u32, u32, u32, u32) {
+        let (sin, cos) = degrees.to_radians().abs().sin_cos();
+        let (w, h) = (width as f32, height as f32);
+        let scale = (w / (w * cos + h * sin)).min(h / (w * sin + h * cos));
+        let cw = ((w * scale).floor() as u32).clamp(1, width);
+        let ch = ((h * scale).floor() as u32).clamp(1, height);
+        ((width - cw) / 2, (height - ch) / 2, cw, ch)
+    }
+
+    // resizes an image; large reductions first average whole blocks of
+    // pixels so no detail is skipped, the rest is bilinear
+    pub fn resize_image(img: &Image<u8>, width: u32, height: u32) -> Image<u8> {
+        let (src_width, src_height) = img.info().wh();
+        let block = (src_width / width.max(1)).min(src_height / height.max(1)).max(1);
+        let reduced;
+        let img = if block >= 2 {
+            let channels = img.info().channels as usize;
+            let (bw, bh) = (src_width / block, src_height / block);
+            let mut data = Vec::with_capacity((bw * bh) as usize * channels);
+            let mut sums = vec![0u32; channels];
+            for by in 0..bh {
+                for bx in 0..bw {
+                    sums.iter_mut().for_each(|s| *s = 0);
+                    for y in by * block..(by + 1) * block {
+                        for x in bx * block..(bx + 1) * block {
+                            for (s, &v) in sums.iter_mut().zip(img.get_pixel(x, y).iter()) {
+                                *s += v as u32;
+                            }
+                        }
+                    }
+                    let count = block * block;
+                    data.extend(sums.iter().map(|&s| ((s + count / 2) / count) as u8));
+                }
+            }
+            reduced = Image::from_vec(bw, bh, channels as u8, img.info().alpha, data);
+            &reduced
+        } else {
+            img
+        };
+
+        let (src_width, src_height) = img.info().wh();
+        let sx = src_width as f32 / width as f32;
+        let sy = src_height as f32 / height as f32;
+        warp_image(img, width, height, &[], |x, y| {
+            // pixel centers line up
+            Some((((x + 0.5) * sx - 0.5).max(0.0), ((y + 0.5) * sy - 0.5).max(0.0)))
+        })
+    }
+
+    // maps the quadrilateral (top left, top right, bottom right, bottom
+    // left) onto an upright rectangle sized by its longest edges
+    pub fn warp_perspective(img: &Image<u8>, corners: &[(f64, f64); 4]) -> Option<Image<u8>> {
+        let dist = |a: (f64, f64), b: (f64, f64)| ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt();
+        let [tl, tr, br, bl] = *corners;
+        let width = dist(tl, tr).max(dist(bl, br)).round().max(1.0);
+        let height = dist(tl, bl).max(dist(tr, br)).round().max(1.0);
+        let rect = [(0.0, 0.0), (width - 1.0, 0.0), (width - 1.0, height - 1.0), (0.0, height - 1.0)];
+        // map each output pixel back into the source
+        let h = homography_from_points(&rect, corners)?;
+        Some(warp_image(img, width as u32, height as u32, &[255, 255, 255, 255], |x, y| {
+            h.apply(x as f64, y as f64).map(|(sx, sy)| (sx as f32, sy as f32))
+        }))
+    }
+}
+
+pub use warp::*;
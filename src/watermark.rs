@@ -0,0 +1,198 @@
+pub mod watermark {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    use rusttype::{point, Font, Scale};
+    pub use crate::config::*;
+
+    use crate::color::*;
+    use crate::crop::*;
+    use crate::grayscale::*;
+    use crate::imageaction::*;
+    use crate::warp::*;
+
+    // the font used for text watermarks, built into the binary
+    const FONT_DATA: &[u8] = include_bytes!("../assets/DejaVuSans-Bold.ttf");
+
+    // the width in pixels of a line of text at a font size in pixels
+    pub fn text_width(text: &str, size: f32) -> f32 {
+        match Font::try_from_bytes(FONT_DATA) {
+            Some(font) => font
+                .layout(text, Scale::uniform(size), point(0.0, 0.0))
+                .last()
+                .map(|g| g.position().x + g.unpositioned().h_metrics().advance_width)
+                .unwrap_or(0.0),
+            None => 0.0,
+        }
+    }
+
+    // renders a line of text as an RGBA image, the font size chosen so the
+    // text is `width` pixels wide
+    pub fn render_text(text: &str, width: u32, color: [u8; 3]) -> Option<Image<u8>> {
+        let natural = text_width(text, 100.0);
+        if natural <= 0.0 {
+            return None;
+        }
+        render_text_sized(text, 100.0 * width as f32 / natural, color)
+    }
+
+    // renders a line of text as an RGBA image at a font size in pixels
+    pub fn render_text_sized(text: &str, size: f32, color: [u8; 3]) -> Option<Image<u8>> {
+        let font = Font::try_from_bytes(FONT_DATA)?;
+        let scale = Scale::uniform(size);
+        let metrics = font.v_metrics(scale);
+        let height = (metrics.ascent - metrics.descent).ceil().max(1.0) as u32;
+        let width = text_width(text, size).ceil().max(1.0) as u32;
+
+        // the glyph coverage becomes the alpha channel
+        let mut data = Vec::with_capacity((width * height * 4) as usize);
+        for _ in 0..width * height {
+            data.extend_from_slice(&[color[0], color[1], color[2], 0]);
+        }
+        for glyph in font.layout(text, scale, point(0.0, metrics.ascent)) {
+            if let Some(bounds) = glyph.pixel_bounding_box() {
+                glyph.draw(|gx, gy, coverage| {
+                    let x = gx as i32 + bounds.min.x;
+                    let y = gy as i32 + bounds.min.y;
+                    if x >= 0 && y >= 0 && (x as u32) < width && (y as u32) < height {
+                        let i = ((y as u32 * width + x as u32) * 4 + 3) as usize;
+                        data[i] = data[i].max(round_u8(coverage * 255.0));
+                    }
+                });
+            }
+        }
+        Some(Image::from_vec(width, height, 4, true, data))
+    }
+
+    // blends an overlay onto an image at x, y using the overlay's alpha and
+    // an opacity from 0 to 1; the image keeps its channels and alpha
+    pub fn composite(img: &mut Image<u8>, overlay: &Image<u8>, x: i64, y: i64, opacity: f32) {
+        let (width, height) = img.info().wh();
+        let (ow, oh) = overlay.info().wh();
+        let colors = img.info().channels_non_alpha() as usize;
+        let overlay_colors = overlay.info().channels_non_alpha() as usize;
+        let overlay_alpha = overlay.info().alpha;
+
+        for oy in 0..oh {
+            let ty = y + oy as i64;
+            if ty < 0 || ty >= height as i64 {
+                continue;
+            }
+            for ox in 0..ow {
+                let tx = x + ox as i64;
+                if tx < 0 || tx >= width as i64 {
+                    continue;
+                }
+                let over = overlay.get_pixel(ox, oy);
+                let a = if overlay_alpha { over[over.len() - 1] as f32 / 255.0 } else { 1.0 } * opacity;
+                if a <= 0.0 {
+                    continue;
+                }
+                let rgb = if overlay_colors < 3 { [over[0]; 3] } else { [over[0], over[1], over[2]] };
+                let mut pixel = img.get_pixel(tx as u32, ty as u32).to_vec();
+                if colors < 3 {
+                    let gray = gray_value(&rgb, GrayMethod::Rec601, false) as f32;
+                    pixel[0] = round_u8(pixel[0] as f32 * (1.0 - a) + gray * a);
+                } else {
+                    for c in 0..3 {
+                        pixel[c] = round_u8(pixel[c] as f32 * (1.0 - a) + rgb[c] as f32 * a);
+                    }
+                }
+                img.set_pixel(tx as u32, ty as u32, &pixel);
+            }
+        }
+    }
+
+    // the watermark overlay: the logo if one is given, otherwise the text
+    fn watermark_overlay(opts: &ConfigOptions, text: &str, width: u32) -> Option<Image<u8>> {
+        if !opts.watermark.is_empty() {
+            let ext = get_filename_extension(&opts.watermark).unwrap_or("").to_string();
+            let logo = match read_image(&opts.watermark, &ext) {
+                Some(logo) => logo,
+                None => {
+                    println!("Error reading watermark image {}", opts.watermark);
+                    return None;
+                }
+            };
+            let (lw, lh) = logo.info().wh();
+            let height = ((lh as f32 * width as f32 / lw as f32).round() as u32).max(1);
+            return Some(resize_image(&logo, width, height));
+        }
+        let color = match parse_color(&opts.watermark_color) {
+            Some(c) => c,
+            None => {
+                println!("Invalid watermark color {}, using white", opts.watermark_color);
+                [255, 255, 255]
+            }
+        };
+        render_text(text, width, color)
+    }
+
+    // adds the watermark options to an image; `default_text` is used when
+    // neither a logo nor a text is given
+    pub fn apply_watermark(img: Image<u8>, opts: &ConfigOptions, default_text: &str) -> Image<u8> {
+        let text = if opts.watermark_text.is_empty() { default_text } else { opts.watermark_text.as_str() };
+        if opts.watermark.is_empty() && text.is_empty() {
+            return img;
+        }
+        let percent = |spec: &str, name: &str, default: f32| -> f32 {
+            match spec.trim().parse::<f32>() {
+                Ok(v) if (0.0..=100.0).contains(&v) => v,
+                _ => {
+                    println!("Invalid watermark {} {}, using {}", name, spec, default);
+                    default
+                }
+            }
+        };
+        let opacity = percent(&opts.watermark_opacity, "opacity", 50.0) / 100.0;
+        let scale = percent(&opts.watermark_scale, "scale", 20.0) / 100.0;
+        let margin_percent = percent(&opts.watermark_margin, "margin", 2.0) / 100.0;
+        let gravity = match parse_gravity(&opts.watermark_gravity) {
+            Some(g) => g,
+            None => {
+                println!("Invalid watermark gravity {}, using southeast", opts.watermark_gravity);
+                Gravity::SouthEast
+            }
+        };
+
+        let (width, height) = img.info().wh();
+        let overlay_width = ((width as f32 * scale).round() as u32).max(1);
+        let overlay = match watermark_overlay(opts, text, overlay_width) {
+            Some(o) => o,
+            None => return img,
+        };
+        let (ow, oh) = overlay.info().wh();
+        let margin = (width as f32 * margin_percent).round() as i64;
+        let mut img = img;
+
+        if opts.watermark_tile {
+            // a grid over the whole image, the margin between the tiles
+            let mut y = margin;
+            while y < height as i64 {
+                let mut x = margin;
+                while x < width as i64 {
+                    composite(&mut img, &overlay, x, y, opacity);
+                    x += ow as i64 + margin.max(1);
+                }
+                y += oh as i64 + margin.max(1);
+            }
+            return img;
+        }
+
+        let (x, y) = gravity_offset(width, height, ow, oh, gravity);
+        let (mut x, mut y) = (x as i64, y as i64);
+        match gravity {
+            Gravity::West | Gravity::NorthWest | Gravity::SouthWest => x += margin,
+            Gravity::East | Gravity::NorthEast | Gravity::SouthEast => x -= margin,
+            _ => {}
+        }
+        match gravity {
+            Gravity::North | Gravity::NorthWest | Gravity::NorthEast => y += margin,
+            Gravity::South | Gravity::SouthWest | Gravity::SouthEast => y -= margin,
+            _ => {}
+        }
+        composite(&mut img, &overlay, x, y, opacity);
+        img
+    }
+}
+
+pub use watermark::*;
@@ -0,0 +1,193 @@
+pub mod whitebalance {
+
+    use imgproc_rs::image::{BaseImage, Image};
+    pub use crate::config::*;
+
+    use crate::grayscale::*;
+    use crate::rustyimg::*;
+
+    // the supported white balance modes
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum WhiteBalance {
+        // the average color is neutral gray
+        GrayWorld,
+        // the brightest value of each channel is white
+        WhitePatch,
+        // the given percent of brightest values of each channel is white
+        Percentile(f32),
+        // the scene was lit with the given color temperature (K) and tint
+        Temperature(f32, f32),
+        // the pixel at x, y is neutral gray
+        Pixel(u32, u32),
+    }
+
+    // parses a white balance mode:
+    //   gray-world, white-patch, percentile[:P] (default 1),
+    //   temp:KELVIN[,TINT] (tint from -100 green to 100 magenta), pixel:X,Y
+    pub fn parse_white_balance(spec: &str) -> Option<WhiteBalance> {
+        let spec = spec.trim().to_lowercase();
+        let (mode, arg) = match spec.split_once(':') {
+            Some((m, a)) => (m, Some(a)),
+            None => (spec.as_str(), None),
+        };
+        let numbers = |a: &str| -> Option<Vec<f32>> {
+            a.split(',')
+                .map(|v| v.trim().parse::<f32>())
+                .collect::<Result<_, _>>()
+                .ok()
+        };
+
+        match mode {
+            "gray-world" | "grayworld" | "auto" => Some(WhiteBalance::GrayWorld),
+            "white-patch" | "whitepatch" => Some(WhiteBalance::WhitePatch),
+            "percentile" => {
+                let p = match arg {
+                    Some(a) => a.trim().parse::<f32>().ok()?,
+                    None => 1.0,
+                };
+                if !(0.0..50.0).contains(&p) {
+                    return None;
+                }
+                Some(WhiteBalance::Percentile(p))
+            }
+            "temp" | "temperature" => {
+                let values = numbers(arg?)?;
+                let kelvin = *values.first()?;
+                let tint = *values.get(1).unwrap_or(&0.0);
+                if !(1000.0..=40000.0).contains(&kelvin) || !(-100.0..=100.0).contains(&tint) {
+                    return None;
+                }
+                Some(WhiteBalance::Temperature(kelvin, tint))
+            }
+            "pixel" => {
+                let values = numbers(arg?)?;
+                if values.len() != 2 || values[0] < 0.0 || values[1] < 0.0 {
+                    return None;
+                }
+                Some(WhiteBalance::Pixel(values[0] as u32, values[1] as u32))
+            }
+            _ => None,
+        }
+    }
+
+    // computes the per channel gains; the statistics are taken from the
+    // channel histograms passed through the lookup tables applied before
+    pub fn white_balance_gains(img: &Image<u8>, mode: WhiteBalance, luts: &[[u8; 256]; 3]) -> [f32; 3] {
+        let mapped_histogram = |c: usize| -> [u32; 256] {
+            let mut mapped = [0u32; 256];
+            for (value, &count) in get_channel_histogram(img, c).iter().enumerate() {
+                mapped[luts[c][value] as usize] += count;
+            }
+            mapped
+        };
+
+        let reference: [f32; 3] = match mode {
+            WhiteBalance::GrayWorld => {
+                let mut means = [0f32; 3];
+                for (c, mean) in means.iter_mut().enumerate() {
+                    let histogram = mapped_histogram(c);
+                    let total: f64 = histogram.iter().map(|&n| n as f64).sum();
+                    let sum: f64 = histogram
+                        .iter()
+                        .enumerate()
+                        .map(|(v, &n)| v as f64 * n as f64)
+                        .sum();
+                    *mean = (sum / total.max(1.0)) as f32;
+                }
+                means
+            }
+            WhiteBalance::WhitePatch | WhiteBalance::Percentile(_) => {
+                let percent = match mode {
+                    WhiteBalance::Percentile(p) => p,
+                    _ => 0.0,
+                };
+                let mut whites = [0f32; 3];
+                for (c, white) in whites.iter_mut().enumerate() {
+                    let (_min, max) = histogram_range(&mapped_histogram(c), 0.0, percent);
+                    *white = max as f32;
+                }
+                // gains towards white rather than the channel average
+                let target = 255.0;
+                return [
+                    target / whites[0].max(1.0),
+                    target / whites[1].max(1.0),
+                    target / whites[2].max(1.0),
+                ];
+            }
+            WhiteBalance::Temperature(kelvin, tint) => {
+                let scene = kelvin_to_rgb(kelvin);
+                let daylight = kelvin_to_rgb(6500.0);
+                let mut gains = [0f32; 3];
+                for c in 0..3 {
+                    // very warm light has almost no blue to scale up
+                    gains[c] = (daylight[c] / scene[c].max(0.001)).clamp(0.25, 4.0);
+                }
+                // positive tint removes green, adding magenta
+                gains[1] *= 1.0 - tint / 200.0;
+                // keep the overall brightness
+                let luma = 0.299 * gains[0] + 0.587 * gains[1] + 0.114 * gains[2];
+                return [gains[0] / luma, gains[1] / luma, gains[2] / luma];
+            }
+            WhiteBalance::Pixel(x, y) => {
+                let (width, height) = img.info().wh();
+                if x >= width || y >= height {
+                    println!("White balance pixel {},{} is outside the {}x{} image", x, y, width, height);
+                    return [1.0, 1.0, 1.0];
+                }
+                let pixel = img.get_pixel(x, y);
+                [
+                    luts[0][pixel[0] as usize] as f32,
+                    luts[1][pixel[1] as usize] as f32,
+                    luts[2][pixel[2] as usize] as f32,
+                ]
+            }
+        };
+
+        // scale each channel towards the average of the reference
+        let gray = (reference[0] + reference[1] + reference[2]) / 3.0;
+        [
+            gray / reference[0].max(1.0),
+            gray / reference[1].max(1.0),
+            gray / reference[2].max(1.0),
+        ]
+    }
+
+    // approximates the color of a black body at the given temperature
+    // (Tanner Helland's fit of the CIE data), as 0.0-1.0 values
+    pub fn kelvin_to_rgb(kelvin: f32) -> [f32; 3] {
+        let t = kelvin / 100.0;
+        let r = if t <= 66.0 {
+            255.0
+        } else {
+            329.69873 * (t - 60.0).powf(-0.13320476)
+        };
+        let g = if t <= 66.0 {
+            99.4708 * t.ln() - 161.11957
+        } else {
+            288.12216 * (t - 60.0).powf(-0.075514846)
+        };
+        let b = if t >= 66.0 {
+            255.0
+        } else if t <= 19.0 {
+            0.0
+        } else {
+            138.51773 * (t - 10.0).ln() - 305.0448
+        };
+        [
+            r.clamp(0.0, 255.0) / 255.0,
+            g.clamp(0.0, 255.0) / 255.0,
+            b.clamp(0.0, 255.0) / 255.0,
+        ]
+    }
+
+    // a lookup table multiplying every value by the gain
+    pub fn gain_lut(gain: f32) -> [u8; 256] {
+        let mut lut = [0u8; 256];
+        for (i, value) in lut.iter_mut().enumerate() {
+            *value = round_u8(i as f32 * gain);
+        }
+        lut
+    }
+}
+
+pub use whitebalance::*;